@@ -0,0 +1,163 @@
+use serde::Deserialize;
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::mpsc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Line format of the access log.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Common Log Format with the latency appended
+    Common,
+    /// one json object per line
+    Json,
+}
+
+/// Access log that records one line per request.
+///
+/// Lines are handed to a background thread that does the writing, so
+/// serving a request never waits on the log's I/O.
+#[derive(Debug, Clone)]
+pub struct AccessLog {
+    sender: mpsc::Sender<String>,
+    format: LogFormat,
+}
+impl AccessLog {
+    /// Logs to stdout.
+    pub fn stdout() -> AccessLog {
+        AccessLog::writer(io::stdout())
+    }
+
+    /// Appends to the file at `path`, creating it if needed.
+    pub fn file(path: impl AsRef<Path>) -> io::Result<AccessLog> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AccessLog::writer(file))
+    }
+
+    /// Logs to any writer.
+    pub fn writer<W: Write + Send + 'static>(writer: W) -> AccessLog {
+        let (sender, receiver) = mpsc::channel::<String>();
+        std::thread::spawn(move || {
+            let mut writer = BufWriter::new(writer);
+            // write everything that is queued, then flush once
+            while let Ok(line) = receiver.recv() {
+                let _ = writeln!(writer, "{}", line);
+                while let Ok(line) = receiver.try_recv() {
+                    let _ = writeln!(writer, "{}", line);
+                }
+                let _ = writer.flush();
+            }
+        });
+        AccessLog {
+            sender,
+            format: LogFormat::Common,
+        }
+    }
+
+    /// Writes json lines instead of Common Log Format.
+    pub fn json(mut self) -> AccessLog {
+        self.format = LogFormat::Json;
+        self
+    }
+
+    /// Queues a line for a finished request.
+    pub(crate) fn record(&self, entry: AccessLogEntry) {
+        let line = match self.format {
+            LogFormat::Common => entry.to_common(),
+            LogFormat::Json => entry.to_json(),
+        };
+        let _ = self.sender.send(line);
+    }
+}
+
+/// What gets logged about a request.
+pub(crate) struct AccessLogEntry {
+    pub remote_addr: Option<SocketAddr>,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    /// body size, `None` for streamed bodies
+    pub size: Option<usize>,
+    pub latency: Duration,
+    pub time: SystemTime,
+}
+impl AccessLogEntry {
+    fn remote_ip(&self) -> String {
+        self.remote_addr
+            .map_or(String::from("-"), |address| address.ip().to_string())
+    }
+
+    /// `host ident user [time] "request" status size latency`
+    fn to_common(&self) -> String {
+        let (year, month, day, hour, minute, second) = utc_parts(self.time);
+        format!(
+            "{} - - [{:02}/{}/{}:{:02}:{:02}:{:02} +0000] \"{} {}\" {} {} {:.3}ms",
+            self.remote_ip(),
+            day,
+            MONTHS[month as usize - 1],
+            year,
+            hour,
+            minute,
+            second,
+            self.method,
+            self.path,
+            self.status,
+            // clf uses "-" for responses without a body
+            match self.size {
+                Some(size) if size > 0 => size.to_string(),
+                _ => String::from("-"),
+            },
+            self.latency.as_secs_f64() * 1000.0
+        )
+    }
+
+    fn to_json(&self) -> String {
+        let (year, month, day, hour, minute, second) = utc_parts(self.time);
+        serde_json::json!({
+            "time": format!(
+                "{}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+                year, month, day, hour, minute, second
+            ),
+            "remote_addr": self.remote_addr.map(|address| address.ip().to_string()),
+            "method": self.method,
+            "path": self.path,
+            "status": self.status,
+            "size": self.size,
+            "latency_ms": self.latency.as_secs_f64() * 1000.0,
+        })
+        .to_string()
+    }
+}
+
+/// Splits a time into utc `(year, month, day, hour, minute, second)`.
+fn utc_parts(time: SystemTime) -> (i64, u32, u32, u64, u64, u64) {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs());
+    let (days, rest) = (seconds / 86400, seconds % 86400);
+
+    // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day, rest / 3600, rest % 3600 / 60, rest % 60)
+}
@@ -0,0 +1,431 @@
+use crate::{HandlerFuture, Next, Request, Response, StatusCode};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Reads the username and password from an `Authorization: Basic` header.
+pub fn parse_basic_auth(header: &str) -> Option<(String, String)> {
+    let (scheme, credentials) = header.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let decoded = STANDARD.decode(credentials.trim()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+/// Middleware that requires valid Basic auth credentials.
+///
+/// The validator gets the username and password from the request. Missing
+/// or rejected credentials get a 401 asking the client to log in to
+/// `realm`.
+pub fn basic_auth<F>(
+    realm: &str,
+    validator: F,
+) -> impl Fn(Request, Next) -> HandlerFuture + Send + Sync + Clone + 'static
+where
+    F: Fn(&str, &str) -> bool + Send + Sync + 'static,
+{
+    let challenge = format!("Basic realm=\"{}\", charset=\"UTF-8\"", realm);
+    let validator = Arc::new(validator);
+    move |request: Request, next: Next| {
+        let is_valid = request
+            .headers
+            .get("authorization")
+            .and_then(|header| parse_basic_auth(header))
+            .is_some_and(|(username, password)| validator(&username, &password));
+        if is_valid {
+            return next.run(request);
+        }
+
+        let response = Response::new(StatusCode::UNAUTHORIZED)
+            .with_header("WWW-Authenticate", challenge.clone());
+        Box::pin(async move { response })
+    }
+}
+
+/// Reasons a JSON web token is rejected.
+#[derive(Debug, thiserror::Error)]
+pub enum JwtError {
+    #[error("token is not made of three base64url parts")]
+    Malformed,
+    #[error("unsupported signing algorithm {0}")]
+    UnsupportedAlgorithm(String),
+    #[error("signature does not match")]
+    InvalidSignature,
+    #[error("token has expired")]
+    Expired,
+    #[error("token is not valid yet")]
+    NotYetValid,
+}
+
+/// Claims of a verified JSON web token, added to `Request::extensions` by
+/// `jwt_auth`.
+#[derive(Debug, Clone)]
+pub struct JwtClaims(pub serde_json::Value);
+impl JwtClaims {
+    /// The `sub` claim, usually the user id.
+    pub fn subject(&self) -> Option<&str> {
+        self.0.get("sub").and_then(|sub| sub.as_str())
+    }
+
+    pub fn get(&self, claim: &str) -> Option<&serde_json::Value> {
+        self.0.get(claim)
+    }
+}
+
+/// Verifies an HS256 signed JSON web token and returns its claims.
+/// `exp` and `nbf` are checked when present.
+pub fn verify_jwt(token: &str, secret: &[u8]) -> Result<JwtClaims, JwtError> {
+    let parts: Vec<&str> = token.split('.').collect();
+    if parts.len() != 3 {
+        return Err(JwtError::Malformed);
+    }
+
+    let header: serde_json::Value = decode_jwt_part(parts[0])?;
+    let algorithm = header.get("alg").and_then(|alg| alg.as_str()).unwrap_or("");
+    if algorithm != "HS256" {
+        return Err(JwtError::UnsupportedAlgorithm(algorithm.to_string()));
+    }
+
+    let signature = URL_SAFE_NO_PAD
+        .decode(parts[2])
+        .map_err(|_| JwtError::Malformed)?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).map_err(|_| JwtError::InvalidSignature)?;
+    mac.update(format!("{}.{}", parts[0], parts[1]).as_bytes());
+    mac.verify_slice(&signature)
+        .map_err(|_| JwtError::InvalidSignature)?;
+
+    let claims: serde_json::Value = decode_jwt_part(parts[1])?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs_f64())
+        .unwrap_or(0.0);
+    if let Some(exp) = claims.get("exp").and_then(|exp| exp.as_f64()) {
+        if now >= exp {
+            return Err(JwtError::Expired);
+        }
+    }
+    if let Some(nbf) = claims.get("nbf").and_then(|nbf| nbf.as_f64()) {
+        if now < nbf {
+            return Err(JwtError::NotYetValid);
+        }
+    }
+
+    Ok(JwtClaims(claims))
+}
+
+fn decode_jwt_part(part: &str) -> Result<serde_json::Value, JwtError> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(part)
+        .map_err(|_| JwtError::Malformed)?;
+    serde_json::from_slice(&bytes).map_err(|_| JwtError::Malformed)
+}
+
+/// Reads the token from an `Authorization: Bearer` header.
+pub fn parse_bearer_token(header: &str) -> Option<&str> {
+    let (scheme, token) = header.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("bearer") {
+        return None;
+    }
+    Some(token.trim())
+}
+
+/// Middleware that requires an HS256 JSON web token signed with `secret`
+/// in an `Authorization: Bearer` header.
+///
+/// The verified claims are added to the request as `JwtClaims`. Missing or
+/// invalid tokens get a 401.
+pub fn jwt_auth(
+    secret: impl Into<Vec<u8>>,
+) -> impl Fn(Request, Next) -> HandlerFuture + Send + Sync + Clone + 'static {
+    let secret: Arc<[u8]> = secret.into().into();
+    move |mut request: Request, next: Next| {
+        let token = request
+            .headers
+            .get("authorization")
+            .and_then(|header| parse_bearer_token(header));
+        let challenge = match token.map(|token| verify_jwt(token, &secret)) {
+            Some(Ok(claims)) => {
+                request.extensions.insert(claims);
+                return next.run(request);
+            }
+            Some(Err(e)) => format!(
+                "Bearer error=\"invalid_token\", error_description=\"{}\"",
+                e
+            ),
+            None => String::from("Bearer"),
+        };
+
+        let response =
+            Response::new(StatusCode::UNAUTHORIZED).with_header("WWW-Authenticate", challenge);
+        Box::pin(async move { response })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Server;
+    use serde_json::json;
+
+    const SECRET: &[u8] = b"secret";
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    fn basic(credentials: &str) -> String {
+        format!("Basic {}", STANDARD.encode(credentials))
+    }
+
+    #[test]
+    fn parses_basic_auth_headers() {
+        assert_eq!(
+            parse_basic_auth(&basic("alice:secret")),
+            Some((String::from("alice"), String::from("secret")))
+        );
+        // only the first colon separates them
+        assert_eq!(
+            parse_basic_auth(&basic("alice:a:b")),
+            Some((String::from("alice"), String::from("a:b")))
+        );
+        assert_eq!(
+            parse_basic_auth(&format!("basic  {} ", STANDARD.encode("alice:"))),
+            Some((String::from("alice"), String::new()))
+        );
+        assert_eq!(parse_basic_auth(&basic("alice")), None);
+        assert_eq!(parse_basic_auth("Basic !!!"), None);
+        assert_eq!(parse_basic_auth("Basic"), None);
+        assert_eq!(
+            parse_basic_auth(&format!("Bearer {}", STANDARD.encode("alice:secret"))),
+            None
+        );
+        let invalid_utf8 = format!("Basic {}", STANDARD.encode(b"alice:\xff"));
+        assert_eq!(parse_basic_auth(&invalid_utf8), None);
+    }
+
+    #[tokio::test]
+    async fn basic_auth_asks_for_credentials() {
+        let mut server = Server::new(0);
+        server.use_middleware(basic_auth("Admin", |username, password| {
+            username == "alice" && password == "secret"
+        }));
+        server.get(String::from("admin"), |_| async { Response::text("hi") });
+        let client = server.test();
+
+        let response = client
+            .get("/admin")
+            .header("Authorization", basic("alice:secret"))
+            .send()
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.text(), "hi");
+
+        for authorization in [
+            None,
+            Some(basic("alice:wrong")),
+            Some(String::from("Basic")),
+        ] {
+            let mut request = client.get("/admin");
+            if let Some(authorization) = authorization {
+                request = request.header("Authorization", authorization);
+            }
+            let response = request.send().await;
+            assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+            assert_eq!(
+                response.header("www-authenticate").unwrap(),
+                "Basic realm=\"Admin\", charset=\"UTF-8\""
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn protect_only_guards_matching_paths() {
+        let mut server = Server::new(0);
+        server.protect(String::from("admin/*"), |username, password| {
+            username == "alice" && password == "secret"
+        });
+        server.get(String::from("admin/panel"), |_| async {
+            Response::text("panel")
+        });
+        server.get(String::from("public"), |_| async {
+            Response::text("public")
+        });
+        let client = server.test();
+
+        assert_eq!(client.get("/public").send().await.status, StatusCode::OK);
+        let response = client.get("/admin/panel").send().await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+        let response = client
+            .get("/admin/panel")
+            .header("Authorization", basic("alice:secret"))
+            .send()
+            .await;
+        assert_eq!(response.text(), "panel");
+    }
+
+    /// A token with `header` and `claims`, signed with `secret`.
+    fn sign(header: serde_json::Value, claims: serde_json::Value, secret: &[u8]) -> String {
+        let header = URL_SAFE_NO_PAD.encode(header.to_string());
+        sign_encoded(&header, &claims.to_string(), secret)
+    }
+
+    /// A token with the encoded `header` and `claims` as they are, even
+    /// when they aren't JSON.
+    fn sign_encoded(header: &str, claims: &str, secret: &[u8]) -> String {
+        let claims = URL_SAFE_NO_PAD.encode(claims);
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+        mac.update(format!("{}.{}", header, claims).as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+        format!("{}.{}.{}", header, claims, signature)
+    }
+
+    fn hs256(claims: serde_json::Value) -> String {
+        sign(json!({"alg": "HS256", "typ": "JWT"}), claims, SECRET)
+    }
+
+    #[test]
+    fn accepts_valid_tokens() {
+        let token = hs256(json!({"sub": "alice", "exp": now() + 60, "nbf": now() - 60}));
+        let claims = verify_jwt(&token, SECRET).unwrap();
+        assert_eq!(claims.subject(), Some("alice"));
+    }
+
+    #[test]
+    fn rejects_bad_signatures() {
+        let token = sign(json!({"alg": "HS256"}), json!({"sub": "alice"}), b"other");
+        assert!(matches!(
+            verify_jwt(&token, SECRET),
+            Err(JwtError::InvalidSignature)
+        ));
+
+        // changed claims with the original signature
+        let token = hs256(json!({"sub": "alice"}));
+        let (_, signature) = token.rsplit_once('.').unwrap();
+        let forged = format!(
+            "{}.{}.{}",
+            URL_SAFE_NO_PAD.encode(json!({"alg": "HS256"}).to_string()),
+            URL_SAFE_NO_PAD.encode(json!({"sub": "admin"}).to_string()),
+            signature
+        );
+        assert!(matches!(
+            verify_jwt(&forged, SECRET),
+            Err(JwtError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn rejects_other_algorithms() {
+        let header = URL_SAFE_NO_PAD.encode(json!({"alg": "none"}).to_string());
+        let claims = URL_SAFE_NO_PAD.encode(json!({"sub": "admin"}).to_string());
+        for token in [
+            format!("{}.{}.", header, claims),
+            format!("{}.{}", header, claims),
+        ] {
+            assert!(verify_jwt(&token, SECRET).is_err());
+        }
+        assert!(matches!(
+            verify_jwt(&format!("{}.{}.", header, claims), SECRET),
+            Err(JwtError::UnsupportedAlgorithm(alg)) if alg == "none"
+        ));
+
+        let token = sign(json!({"alg": "HS512"}), json!({"sub": "admin"}), SECRET);
+        assert!(matches!(
+            verify_jwt(&token, SECRET),
+            Err(JwtError::UnsupportedAlgorithm(alg)) if alg == "HS512"
+        ));
+        let token = sign(json!({}), json!({"sub": "admin"}), SECRET);
+        assert!(matches!(
+            verify_jwt(&token, SECRET),
+            Err(JwtError::UnsupportedAlgorithm(_))
+        ));
+    }
+
+    #[test]
+    fn checks_expiry_and_not_before() {
+        let token = hs256(json!({"sub": "alice", "exp": now() - 1}));
+        assert!(matches!(verify_jwt(&token, SECRET), Err(JwtError::Expired)));
+
+        let token = hs256(json!({"sub": "alice", "nbf": now() + 60}));
+        assert!(matches!(
+            verify_jwt(&token, SECRET),
+            Err(JwtError::NotYetValid)
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_tokens() {
+        let valid = hs256(json!({"sub": "alice"}));
+        let (head, _) = valid.split_once('.').unwrap();
+        for token in [
+            "",
+            "abc",
+            "a.b",
+            "a.b.c.d",
+            "!!!.e30.sig",
+            &sign_encoded(head, "not json", SECRET),
+            &format!("{}.{}.@@@", head, URL_SAFE_NO_PAD.encode("{}")),
+        ] {
+            assert!(
+                matches!(verify_jwt(token, SECRET), Err(JwtError::Malformed)),
+                "{:?}",
+                token
+            );
+        }
+    }
+
+    #[test]
+    fn parses_bearer_tokens() {
+        assert_eq!(parse_bearer_token("Bearer abc"), Some("abc"));
+        assert_eq!(parse_bearer_token("bearer  abc "), Some("abc"));
+        assert_eq!(parse_bearer_token("Basic abc"), None);
+        assert_eq!(parse_bearer_token("Bearer"), None);
+    }
+
+    #[tokio::test]
+    async fn jwt_auth_guards_routes() {
+        let mut server = Server::new(0);
+        server.use_middleware(jwt_auth(SECRET));
+        server.get(String::from("me"), |request: Request| async move {
+            let claims = request.extensions.get::<JwtClaims>().unwrap();
+            Response::text(claims.subject().unwrap_or_default().to_string())
+        });
+        let client = server.test();
+
+        let token = hs256(json!({"sub": "alice", "exp": now() + 60}));
+        let response = client
+            .get("/me")
+            .header("Authorization", format!("Bearer {}", token))
+            .send()
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.text(), "alice");
+
+        let response = client.get("/me").send().await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(response.header("www-authenticate").unwrap(), "Bearer");
+
+        let expired = hs256(json!({"sub": "alice", "exp": now() - 1}));
+        let forged = sign(json!({"alg": "HS256"}), json!({"sub": "alice"}), b"other");
+        for token in [expired, forged, String::from("garbage")] {
+            let response = client
+                .get("/me")
+                .header("Authorization", format!("Bearer {}", token))
+                .send()
+                .await;
+            assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+            assert!(response
+                .header("www-authenticate")
+                .unwrap()
+                .starts_with("Bearer error=\"invalid_token\""));
+        }
+    }
+}
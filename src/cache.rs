@@ -0,0 +1,85 @@
+use std::time::Duration;
+
+/// `Cache-Control` policy for files served from a static directory.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum CachePolicy {
+    /// never store the file
+    NoStore,
+    /// store the file but revalidate it with the server before every use
+    NoCache,
+    /// reuse the file without asking the server for `max_age`
+    MaxAge { max_age: Duration, immutable: bool },
+}
+impl CachePolicy {
+    pub fn max_age(max_age: Duration) -> CachePolicy {
+        CachePolicy::MaxAge {
+            max_age,
+            immutable: false,
+        }
+    }
+
+    /// Marks the file as never changing, for fingerprinted assets.
+    /// Only has an effect on `MaxAge` policies.
+    pub fn immutable(self) -> CachePolicy {
+        match self {
+            CachePolicy::MaxAge { max_age, .. } => CachePolicy::MaxAge {
+                max_age,
+                immutable: true,
+            },
+            policy => policy,
+        }
+    }
+
+    /// Value used in the `Cache-Control` header.
+    pub fn header_value(&self) -> String {
+        match self {
+            CachePolicy::NoStore => String::from("no-store"),
+            CachePolicy::NoCache => String::from("no-cache"),
+            CachePolicy::MaxAge { max_age, immutable } => {
+                let mut value = format!("public, max-age={}", max_age.as_secs());
+                if *immutable {
+                    value.push_str(", immutable");
+                }
+                value
+            }
+        }
+    }
+}
+
+/// Matches a path relative to a static directory against a glob.
+///
+/// `*` matches anything but `/` and `?` matches a single character.
+/// Patterns without a `/` only look at the file name, so `*.css` matches
+/// css files in every subdirectory.
+pub(crate) fn glob_match(pattern: &str, path: &str) -> bool {
+    let path = path.trim_start_matches('/');
+    let path = if pattern.contains('/') {
+        path
+    } else {
+        path.rsplit('/').next().unwrap_or(path)
+    };
+    let pattern = pattern.trim_start_matches('/');
+    wildcard_match(pattern.as_bytes(), path.as_bytes())
+}
+
+fn wildcard_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => {
+            // try every split point up to the next directory separator
+            for skip in 0..=text.len() {
+                if wildcard_match(rest, &text[skip..]) {
+                    return true;
+                }
+                if text.get(skip) == Some(&b'/') {
+                    break;
+                }
+            }
+            false
+        }
+        Some((b'?', rest)) => {
+            matches!(text.first(), Some(c) if *c != b'/') && wildcard_match(rest, &text[1..])
+        }
+        Some((c, rest)) => text.first() == Some(c) && wildcard_match(rest, &text[1..]),
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Whether a circuit lets requests through.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum CircuitState {
+    /// requests go through
+    #[default]
+    Closed,
+    /// requests are rejected right away until the open duration passed
+    Open,
+    /// a few probe requests go through to find out if the upstream
+    /// recovered
+    HalfOpen,
+}
+
+/// What a circuit has seen so far, see `CircuitBreaker::stats`.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct CircuitStats {
+    pub state: CircuitState,
+    /// failures since the last success
+    pub consecutive_failures: u32,
+    pub successes: u64,
+    /// 5xx responses, timeouts and failed requests
+    pub failures: u64,
+    /// requests rejected without being sent
+    pub rejected: u64,
+    /// how often the circuit opened
+    pub times_opened: u64,
+}
+
+#[derive(Debug, Default)]
+struct Circuit {
+    stats: CircuitStats,
+    opened_at: Option<Instant>,
+    probes_in_flight: u32,
+    probes_passed: u32,
+}
+
+/// Stops sending requests to an upstream that keeps failing, so clients
+/// get a quick 503 instead of waiting for timeouts, see
+/// `Server::proxy_circuit_breaker`.
+///
+/// Every upstream has its own circuit. It opens after `failure_threshold`
+/// 5xx responses or timeouts in a row and rejects requests for
+/// `open_duration`. Then it lets a few probe requests through and closes
+/// again once they all succeed, or opens again when one fails.
+///
+/// Clones share their circuits, so a clone kept around can report them,
+/// like for a metrics endpoint.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    failure_threshold: u32,
+    open_duration: Duration,
+    half_open_probes: u32,
+    /// by upstream `host:port`
+    circuits: Arc<Mutex<HashMap<String, Circuit>>>,
+}
+impl CircuitBreaker {
+    /// Opens a circuit after `failure_threshold` failures in a row for
+    /// `open_duration`, and closes it after 1 successful probe.
+    pub fn new(failure_threshold: u32, open_duration: Duration) -> CircuitBreaker {
+        CircuitBreaker {
+            failure_threshold: failure_threshold.max(1),
+            open_duration,
+            half_open_probes: 1,
+            circuits: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Sets how many probe requests a half open circuit lets through at
+    /// once, all of them have to succeed to close it.
+    pub fn with_half_open_probes(mut self, probes: u32) -> CircuitBreaker {
+        self.half_open_probes = probes.max(1);
+        self
+    }
+
+    /// How long an open circuit rejects requests.
+    pub fn open_duration(&self) -> Duration {
+        self.open_duration
+    }
+
+    /// The circuit of every upstream that got a request, by `host:port`,
+    /// sorted.
+    pub fn stats(&self) -> Vec<(String, CircuitStats)> {
+        let circuits = self.circuits.lock().unwrap();
+        let mut stats: Vec<(String, CircuitStats)> = circuits
+            .iter()
+            .map(|(upstream, circuit)| (upstream.clone(), circuit.stats))
+            .collect();
+        stats.sort_by(|a, b| a.0.cmp(&b.0));
+        stats
+    }
+
+    /// Lets a request to `upstream` through, or `None` when its circuit
+    /// rejects it.
+    pub(crate) fn try_acquire(&self, upstream: &str) -> Option<CircuitPermit> {
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(upstream.to_string()).or_default();
+        if circuit.stats.state == CircuitState::Open {
+            let elapsed = circuit.opened_at.map(|opened_at| opened_at.elapsed());
+            if elapsed.is_some_and(|elapsed| elapsed < self.open_duration) {
+                circuit.stats.rejected += 1;
+                return None;
+            }
+            circuit.stats.state = CircuitState::HalfOpen;
+            circuit.probes_passed = 0;
+        }
+        let probe = circuit.stats.state == CircuitState::HalfOpen;
+        if probe {
+            if circuit.probes_in_flight >= self.half_open_probes {
+                circuit.stats.rejected += 1;
+                return None;
+            }
+            circuit.probes_in_flight += 1;
+        }
+        Some(CircuitPermit {
+            breaker: self.clone(),
+            upstream: upstream.to_string(),
+            probe,
+        })
+    }
+}
+
+/// A request let through by a circuit, reports how it went.
+pub(crate) struct CircuitPermit {
+    breaker: CircuitBreaker,
+    upstream: String,
+    probe: bool,
+}
+impl CircuitPermit {
+    pub(crate) fn succeeded(self) {
+        let breaker = &self.breaker;
+        let mut circuits = breaker.circuits.lock().unwrap();
+        let circuit = circuits.entry(self.upstream.clone()).or_default();
+        circuit.stats.successes += 1;
+        circuit.stats.consecutive_failures = 0;
+        if self.probe && circuit.stats.state == CircuitState::HalfOpen {
+            circuit.probes_passed += 1;
+            if circuit.probes_passed >= breaker.half_open_probes {
+                circuit.stats.state = CircuitState::Closed;
+                circuit.opened_at = None;
+                info!(upstream = %self.upstream, "circuit closed");
+            }
+        }
+    }
+
+    pub(crate) fn failed(self) {
+        let breaker = &self.breaker;
+        let mut circuits = breaker.circuits.lock().unwrap();
+        let circuit = circuits.entry(self.upstream.clone()).or_default();
+        circuit.stats.failures += 1;
+        circuit.stats.consecutive_failures += 1;
+        let open = match circuit.stats.state {
+            CircuitState::Closed => circuit.stats.consecutive_failures >= breaker.failure_threshold,
+            // a failed probe opens it again, late failures of requests
+            // sent before it opened don't restart the wait
+            CircuitState::HalfOpen => self.probe,
+            CircuitState::Open => false,
+        };
+        if open {
+            circuit.stats.state = CircuitState::Open;
+            circuit.stats.times_opened += 1;
+            circuit.opened_at = Some(Instant::now());
+            warn!(
+                upstream = %self.upstream,
+                failures = circuit.stats.consecutive_failures,
+                "circuit opened for {:?}",
+                breaker.open_duration
+            );
+        }
+    }
+}
+impl Drop for CircuitPermit {
+    fn drop(&mut self) {
+        if self.probe {
+            let mut circuits = self.breaker.circuits.lock().unwrap();
+            if let Some(circuit) = circuits.get_mut(&self.upstream) {
+                circuit.probes_in_flight = circuit.probes_in_flight.saturating_sub(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use crate::{Body, Request, Response, StatusCode};
+use flate2::read::{DeflateDecoder, MultiGzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use std::io::{Read, Write};
+
+/// Content codings the server can compress response bodies with.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ContentEncoding {
+    Brotli,
+    Gzip,
+    Deflate,
+}
+impl ContentEncoding {
+    /// Supported encodings, most preferred first.
+    const ALL: [ContentEncoding; 3] = [
+        ContentEncoding::Brotli,
+        ContentEncoding::Gzip,
+        ContentEncoding::Deflate,
+    ];
+
+    /// Value used in the `Content-Encoding` header.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentEncoding::Brotli => "br",
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Deflate => "deflate",
+        }
+    }
+
+    fn encode(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            ContentEncoding::Brotli => {
+                let mut encoded = Vec::new();
+                {
+                    // quality 5 is a good speed/size trade off for dynamic content
+                    let mut encoder = brotli::CompressorWriter::new(&mut encoded, 4096, 5, 22);
+                    encoder.write_all(data)?;
+                }
+                Ok(encoded)
+            }
+            ContentEncoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            // http "deflate" is the zlib format
+            ContentEncoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+
+    fn from_name(name: &str) -> Option<ContentEncoding> {
+        match name.to_ascii_lowercase().as_str() {
+            "br" => Some(ContentEncoding::Brotli),
+            "gzip" | "x-gzip" => Some(ContentEncoding::Gzip),
+            "deflate" => Some(ContentEncoding::Deflate),
+            _ => None,
+        }
+    }
+
+    /// Decodes `data`, reading at most `limit` bytes of output plus one
+    /// so bodies over the limit can be told apart.
+    fn decode(&self, data: &[u8], limit: usize) -> std::io::Result<Vec<u8>> {
+        let limit = limit as u64 + 1;
+        let mut decoded = Vec::new();
+        match self {
+            ContentEncoding::Brotli => {
+                brotli::Decompressor::new(data, 4096)
+                    .take(limit)
+                    .read_to_end(&mut decoded)?;
+            }
+            ContentEncoding::Gzip => {
+                MultiGzDecoder::new(data)
+                    .take(limit)
+                    .read_to_end(&mut decoded)?;
+            }
+            ContentEncoding::Deflate => {
+                let zlib = ZlibDecoder::new(data).take(limit).read_to_end(&mut decoded);
+                if zlib.is_err() {
+                    // some clients send raw deflate without the zlib wrapper
+                    decoded.clear();
+                    DeflateDecoder::new(data)
+                        .take(limit)
+                        .read_to_end(&mut decoded)?;
+                }
+            }
+        }
+        Ok(decoded)
+    }
+}
+
+/// Undoes the `Content-Encoding` of a request body, so handlers get the
+/// body the client compressed. The decoded body is held to `max_size`,
+/// which keeps small compressed bodies from expanding without bound.
+///
+/// Unknown encodings get a 415, bodies that fail to decode a 400 and ones
+/// over the limit a 413.
+pub(crate) fn decompress_request(request: &mut Request, max_size: usize) -> Result<(), Response> {
+    let Some(header) = request.headers.get_joined("content-encoding") else {
+        return Ok(());
+    };
+    let mut encodings = Vec::new();
+    for name in header.split(',').map(str::trim) {
+        if name.is_empty() || name.eq_ignore_ascii_case("identity") {
+            continue;
+        }
+        let encoding = ContentEncoding::from_name(name).ok_or_else(|| {
+            // tells the client which encodings it can use instead
+            Response::new(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+                .with_header("Accept-Encoding", "br, gzip, deflate")
+        })?;
+        encodings.push(encoding);
+    }
+
+    let mut body = std::mem::take(&mut request.body_raw);
+    // the last encoding listed was applied last
+    for encoding in encodings.iter().rev() {
+        body = encoding
+            .decode(&body, max_size)
+            .map_err(|_| Response::new(StatusCode::BAD_REQUEST))?;
+        if body.len() > max_size {
+            return Err(Response::new(StatusCode::PAYLOAD_TOO_LARGE));
+        }
+    }
+    request.headers.remove("content-encoding");
+    if request.headers.contains_key("content-length") {
+        request
+            .headers
+            .insert("content-length", body.len().to_string());
+    }
+    request.body = String::from_utf8_lossy(&body).to_string();
+    request.body_raw = body;
+    Ok(())
+}
+
+/// Parses an `Accept-Encoding` header value into `(coding, q)` pairs.
+/// Codings are lowercased and a missing q-value counts as 1.
+pub fn parse_accept_encoding(accept_encoding: &str) -> Vec<(String, f32)> {
+    accept_encoding
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let coding = parts.next()?.trim().to_lowercase();
+            if coding.is_empty() {
+                return None;
+            }
+            let q = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .filter_map(|q| q.trim().parse::<f32>().ok())
+                .next()
+                .unwrap_or(1.0);
+            Some((coding, q.clamp(0.0, 1.0)))
+        })
+        .collect()
+}
+
+/// Picks the best encoding to use from an `Accept-Encoding` header value.
+///
+/// The encoding with the highest q-value wins, ties go to the server's
+/// preference (br, then gzip, then deflate). Codings with `q=0` and
+/// anything less preferred than `identity` are never chosen.
+pub fn choose_encoding(accept_encoding: &str) -> Option<ContentEncoding> {
+    choose_encoding_among(accept_encoding, &ContentEncoding::ALL)
+}
+
+/// Like `choose_encoding`, but only picks from `available`, which is in
+/// the server's preference.
+pub(crate) fn choose_encoding_among(
+    accept_encoding: &str,
+    available: &[ContentEncoding],
+) -> Option<ContentEncoding> {
+    let accepted = parse_accept_encoding(accept_encoding);
+    let q_of = |name: &str| {
+        accepted
+            .iter()
+            .find(|(coding, _)| coding == name)
+            .or_else(|| accepted.iter().find(|(coding, _)| coding == "*"))
+            .map(|(_, q)| *q)
+    };
+    // identity is acceptable unless explicitly refused
+    let identity_q = q_of("identity").unwrap_or(0.0);
+
+    let mut best: Option<(ContentEncoding, f32)> = None;
+    for &encoding in available {
+        let q = match q_of(encoding.as_str()) {
+            Some(q) if q > 0.0 && q >= identity_q => q,
+            _ => continue,
+        };
+        if best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((encoding, q));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Compresses the response body in place when the client accepts one of
+/// our encodings and the body is at least `min_size` bytes.
+///
+/// Streaming bodies, partial content and responses that already have a
+/// `Content-Encoding` are left untouched. Bodies that would be compressed
+/// for a client accepting it get `Vary: Accept-Encoding` either way.
+pub(crate) fn compress_response(
+    response: &mut Response,
+    accept_encoding: Option<&str>,
+    min_size: usize,
+) {
+    // Content-Range offsets refer to the uncompressed file
+    if response.status == StatusCode::PARTIAL_CONTENT
+        || response.header("Content-Encoding").is_some()
+    {
+        return;
+    }
+    let compressible = matches!(
+        &response.body,
+        Body::Full(body) if !body.is_empty() && body.len() >= min_size
+    );
+    if !compressible {
+        return;
+    }
+    // clients asking without Accept-Encoding would get it compressed too,
+    // so caches must not hand them this one
+    response.add_vary("Accept-Encoding");
+    let encoding = match accept_encoding.and_then(choose_encoding) {
+        Some(encoding) => encoding,
+        None => return,
+    };
+
+    let Body::Full(body) = &response.body else {
+        return;
+    };
+    if let Ok(compressed) = encoding.encode(body) {
+        response.set_header("Content-Encoding", encoding.as_str());
+        response.set_header("Content-Length", compressed.len().to_string());
+        response.body = compressed.into();
+    }
+}
@@ -0,0 +1,373 @@
+use crate::{
+    AccessLog, CachePolicy, ErrorPages, IpCidr, Listener, ListenerConfig, LogFormat, RewriteAction,
+    RewriteRule, Server, StatusCode, SymlinkPolicy,
+};
+use serde::Deserialize;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+/// Server settings read from a TOML file, see `Server::from_config`.
+///
+/// Every setting is optional and missing ones keep the server's defaults,
+/// except that either `address` or `listeners` has to be set:
+///
+/// ```toml
+/// address = "0.0.0.0:8080"
+/// max_connections = 512
+/// trusted_proxies = ["10.0.0.0/8"]
+/// error_pages = "/var/www/errors"
+///
+/// [[listeners]]
+/// tls = { addr = "0.0.0.0:8443", cert_path = "cert.pem", key_path = "key.pem" }
+///
+/// [[static]]
+/// path = "files"
+/// directory = "/var/www/files"
+/// max_age = 3600
+/// upload = true
+/// create_directories = true
+/// max_upload_size = 10485760
+/// upload_types = [".pdf", "image/*"]
+///
+/// [[static]]
+/// path = ""
+/// directory = "/var/www/docs"
+/// host = "docs.example.com"
+///
+/// [[rewrite]]
+/// prefix = "/old/"
+/// to = "/new/"
+/// redirect = 301
+///
+/// [[rewrite]]
+/// regex = "^/u/(\\d+)$"
+/// to = "/users/$1"
+///
+/// [timeouts]
+/// keep_alive = 5
+/// shutdown = 10
+///
+/// [compression]
+/// min_size = 1024
+///
+/// [tcp]
+/// nodelay = true
+/// keepalive = 60
+///
+/// [logging]
+/// level = "debug"
+/// access_log = "stdout"
+/// format = "json"
+/// ```
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// plain http address, used when no `listeners` are given
+    pub address: Option<SocketAddr>,
+    pub listeners: Vec<Listener>,
+    #[serde(rename = "static")]
+    pub static_mounts: Vec<StaticMountConfig>,
+    #[serde(rename = "rewrite")]
+    pub rewrites: Vec<RewriteConfig>,
+    pub timeouts: TimeoutConfig,
+    pub compression: CompressionConfig,
+    pub tcp: TcpConfig,
+    pub logging: LoggingConfig,
+    pub max_body_size: Option<usize>,
+    pub max_request_line_size: Option<usize>,
+    pub max_header_size: Option<usize>,
+    pub max_header_count: Option<usize>,
+    pub max_connections: Option<usize>,
+    pub max_requests_per_connection: Option<usize>,
+    pub reject_when_busy: Option<bool>,
+    /// proxies whose forwarding headers are trusted, like "10.0.0.0/8"
+    pub trusted_proxies: Vec<IpCidr>,
+    /// `Server` header value, an empty string leaves the header out
+    pub server_header: Option<String>,
+    /// directory with pages like 404.html for error responses
+    pub error_pages: Option<String>,
+}
+impl ServerConfig {
+    /// Reads and parses a config file.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<ServerConfig> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        toml::from_str(&text).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid config {}: {e}", path.display()),
+            )
+        })
+    }
+}
+
+/// A directory served like `Server::serve`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StaticMountConfig {
+    /// url path the directory is mounted at
+    pub path: String,
+    pub directory: String,
+    /// only serve the directory for this `Host`, see `Server::vhost`
+    pub host: Option<String>,
+    /// allow uploading files with POST
+    #[serde(default)]
+    pub upload: bool,
+    /// allow replacing and creating files with PUT
+    #[serde(default)]
+    pub put: bool,
+    /// allow removing files with DELETE
+    #[serde(default)]
+    pub delete: bool,
+    /// create missing directories for uploads
+    #[serde(default)]
+    pub create_directories: bool,
+    /// largest file uploads may store, in bytes
+    pub max_upload_size: Option<usize>,
+    /// extensions like ".png" and media types like "image/*" uploads may
+    /// have
+    #[serde(default)]
+    pub upload_types: Vec<String>,
+    #[serde(default)]
+    pub list_directories: bool,
+    #[serde(default)]
+    pub spa_fallback: bool,
+    /// serve `.br` and `.gz` copies of files when they exist
+    #[serde(default)]
+    pub precompressed: bool,
+    /// serve files whose name starts with a dot
+    #[serde(default)]
+    pub dotfiles: bool,
+    /// globs like "*.secret" of paths that are never served
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    /// "deny", "within_root" or "allow_all"
+    #[serde(default)]
+    pub symlinks: SymlinkPolicy,
+    /// `Cache-Control` max-age in seconds for every file
+    pub max_age: Option<u64>,
+}
+
+/// A rule checked before routing, see `Server::add_rewrite`. Takes either
+/// a `prefix` or a `regex`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RewriteConfig {
+    pub prefix: Option<String>,
+    pub regex: Option<String>,
+    /// path the request is routed as, or where it is redirected to
+    pub to: String,
+    /// redirect with this 3xx status instead of rewriting the path
+    pub redirect: Option<u16>,
+}
+impl RewriteConfig {
+    fn to_rule(&self) -> io::Result<RewriteRule> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+        let action = match self.redirect {
+            Some(status) if (300..400).contains(&status) => {
+                RewriteAction::Redirect(self.to.clone(), StatusCode::from_u16(status))
+            }
+            Some(status) => return Err(invalid(format!("{} isn't a redirect status", status))),
+            None => RewriteAction::Rewrite(self.to.clone()),
+        };
+        match (&self.prefix, &self.regex) {
+            (Some(prefix), None) => Ok(RewriteRule::prefix(prefix.clone(), action)),
+            (None, Some(regex)) => RewriteRule::regex(regex, action)
+                .map_err(|e| invalid(format!("invalid rewrite regex {:?}: {}", regex, e))),
+            _ => Err(invalid(format!(
+                "rewrite to {:?} needs either a prefix or a regex",
+                self.to
+            ))),
+        }
+    }
+}
+
+/// Timeouts in seconds.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutConfig {
+    pub keep_alive: Option<f64>,
+    pub header_read: Option<f64>,
+    pub body_read: Option<f64>,
+    pub write: Option<f64>,
+    pub shutdown: Option<f64>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressionConfig {
+    pub enabled: Option<bool>,
+    pub min_size: Option<usize>,
+    /// decode compressed request bodies
+    pub requests: Option<bool>,
+}
+
+/// Socket options, see `ListenerConfig`.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TcpConfig {
+    pub nodelay: Option<bool>,
+    pub reuse_address: Option<bool>,
+    pub reuse_port: Option<bool>,
+    pub backlog: Option<u32>,
+    /// keepalive idle time in seconds
+    pub keepalive: Option<f64>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// tracing filter like `info`, only read by the binary since the
+    /// library doesn't install a subscriber
+    pub level: Option<String>,
+    /// `stdout` or the path of a file to append to
+    pub access_log: Option<String>,
+    pub format: Option<LogFormat>,
+}
+
+impl Server {
+    /// Server set up from the TOML config file at `path`.
+    pub fn from_config(path: impl AsRef<Path>) -> io::Result<Server> {
+        Server::from_server_config(&ServerConfig::load(path)?)
+    }
+
+    /// Server set up from an already loaded config.
+    /// Fails when there is nothing to listen on or the access log file
+    /// can't be opened.
+    pub fn from_server_config(config: &ServerConfig) -> io::Result<Server> {
+        let addr = match (config.address, config.listeners.first()) {
+            (Some(addr), _) => addr,
+            (None, Some(_)) => SocketAddr::from(([127, 0, 0, 1], 0)),
+            (None, None) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "config needs an address or listeners",
+                ))
+            }
+        };
+        let mut server = Server::new_with_addr(addr);
+        for listener in &config.listeners {
+            server.add_listener(listener.clone());
+        }
+
+        for rewrite in &config.rewrites {
+            server.add_rewrite(rewrite.to_rule()?);
+        }
+
+        for mount in &config.static_mounts {
+            let (path, directory) = (mount.path.clone(), mount.directory.clone());
+            let entry = match &mount.host {
+                Some(host) => server.vhost(host).serve(path, directory, mount.upload),
+                None => server.serve(path, directory, mount.upload),
+            };
+            let Some(entry) = entry else {
+                continue;
+            };
+            entry
+                .list_directories(mount.list_directories)
+                .spa_fallback(mount.spa_fallback)
+                .precompressed(mount.precompressed)
+                .allow_dotfiles(mount.dotfiles)
+                .symlinks(mount.symlinks)
+                .allow_put(mount.put)
+                .allow_delete(mount.delete)
+                .create_directories(mount.create_directories);
+            if let Some(size) = mount.max_upload_size {
+                entry.max_upload_size(size);
+            }
+            for pattern in &mount.upload_types {
+                entry.allow_upload_type(pattern.clone());
+            }
+            for pattern in &mount.ignore {
+                entry.ignore(pattern.clone());
+            }
+            if let Some(max_age) = mount.max_age {
+                entry.default_cache(CachePolicy::max_age(Duration::from_secs(max_age)));
+            }
+        }
+
+        let timeouts = &config.timeouts;
+        let seconds = |value: Option<f64>| value.map(Duration::from_secs_f64);
+        if let Some(timeout) = seconds(timeouts.keep_alive) {
+            server.set_keep_alive_timeout(timeout);
+        }
+        if let Some(timeout) = seconds(timeouts.header_read) {
+            server.set_header_read_timeout(timeout);
+        }
+        if let Some(timeout) = seconds(timeouts.body_read) {
+            server.set_body_read_timeout(timeout);
+        }
+        if let Some(timeout) = seconds(timeouts.write) {
+            server.set_write_timeout(timeout);
+        }
+        if let Some(timeout) = seconds(timeouts.shutdown) {
+            server.set_shutdown_timeout(timeout);
+        }
+
+        if let Some(enabled) = config.compression.enabled {
+            server.set_compression(enabled);
+        }
+        if let Some(size) = config.compression.min_size {
+            server.set_compression_min_size(size);
+        }
+        if let Some(enabled) = config.compression.requests {
+            server.set_request_decompression(enabled);
+        }
+        let tcp = &config.tcp;
+        let defaults = ListenerConfig::default();
+        server.set_listener_config(ListenerConfig {
+            nodelay: tcp.nodelay.unwrap_or(defaults.nodelay),
+            reuse_address: tcp.reuse_address.unwrap_or(defaults.reuse_address),
+            reuse_port: tcp.reuse_port.unwrap_or(defaults.reuse_port),
+            backlog: tcp.backlog.unwrap_or(defaults.backlog),
+            keepalive: seconds(tcp.keepalive),
+        });
+
+        if let Some(size) = config.max_body_size {
+            server.set_max_body_size(size);
+        }
+        if let Some(size) = config.max_request_line_size {
+            server.set_max_request_line_size(size);
+        }
+        if let Some(size) = config.max_header_size {
+            server.set_max_header_size(size);
+        }
+        if let Some(count) = config.max_header_count {
+            server.set_max_header_count(count);
+        }
+        if let Some(limit) = config.max_connections {
+            server.set_max_connections(limit);
+        }
+        if let Some(limit) = config.max_requests_per_connection {
+            server.set_max_requests_per_connection(limit);
+        }
+        if let Some(reject) = config.reject_when_busy {
+            server.set_reject_when_busy(reject);
+        }
+        if !config.trusted_proxies.is_empty() {
+            server.set_trusted_proxies(config.trusted_proxies.clone());
+        }
+        if let Some(header) = &config.server_header {
+            server.set_server_header(Some(header.clone()).filter(|header| !header.is_empty()));
+        }
+        if let Some(directory) = &config.error_pages {
+            server.set_error_pages(ErrorPages::from_directory(directory)?);
+        }
+
+        if let Some(target) = &config.logging.access_log {
+            let log = match target.as_str() {
+                "stdout" => AccessLog::stdout(),
+                path => AccessLog::file(path)?,
+            };
+            let log = match config.logging.format {
+                Some(LogFormat::Json) => log.json(),
+                _ => log,
+            };
+            server.set_access_log(log);
+        }
+
+        Ok(server)
+    }
+}
@@ -0,0 +1,546 @@
+use crate::{parse_request_head, Body, HeaderMap, Request, Response, ServerOptions, StatusCode};
+use bytes::BytesMut;
+use futures_util::StreamExt;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::time::Instant;
+
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+/// buffers that grew past this, like after a large body, aren't kept
+const MAX_POOLED_BUFFER_SIZE: usize = 64 * 1024;
+/// most idle buffers kept around for new connections
+const MAX_POOLED_BUFFERS: usize = 256;
+
+/// Read buffers of closed connections, handed out again to new ones so
+/// accepting a connection doesn't have to allocate.
+#[derive(Debug, Default)]
+pub(crate) struct BufferPool {
+    buffers: Mutex<Vec<BytesMut>>,
+}
+impl BufferPool {
+    fn take(&self) -> BytesMut {
+        self.buffers
+            .lock()
+            .unwrap()
+            .pop()
+            .unwrap_or_else(|| BytesMut::with_capacity(READ_CHUNK_SIZE))
+    }
+
+    fn give_back(&self, mut buffer: BytesMut) {
+        if buffer.capacity() > MAX_POOLED_BUFFER_SIZE {
+            return;
+        }
+        buffer.clear();
+        let mut buffers = self.buffers.lock().unwrap();
+        if buffers.len() < MAX_POOLED_BUFFERS {
+            buffers.push(buffer);
+        }
+    }
+}
+
+/// A client connection that reads requests incrementally.
+///
+/// Bytes received after the end of one request are kept in the buffer so
+/// pipelined requests are not lost.
+pub(crate) struct Connection<S> {
+    stream: S,
+    /// grows to fit the request being read, returned to `pool` on drop
+    buffer: BytesMut,
+    pool: Arc<BufferPool>,
+    /// when the part of the request being read has to be complete
+    deadline: Option<Instant>,
+}
+impl<S: AsyncRead + AsyncWrite + Unpin> Connection<S> {
+    pub fn new(stream: S, pool: Arc<BufferPool>) -> Connection<S> {
+        Connection {
+            stream,
+            buffer: pool.take(),
+            pool,
+            deadline: None,
+        }
+    }
+
+    /// Reads more bytes from the socket into the buffer.
+    /// Returns false when the peer closed the connection or it errored,
+    /// and a 408 response when the read deadline passed.
+    async fn fill_buffer(&mut self) -> Result<bool, Response> {
+        self.buffer.reserve(READ_CHUNK_SIZE);
+        let read = self.stream.read_buf(&mut self.buffer);
+        let read = match self.deadline {
+            Some(deadline) => match tokio::time::timeout_at(deadline, read).await {
+                Ok(read) => read,
+                Err(_) => return Err(Response::new(StatusCode::REQUEST_TIMEOUT)),
+            },
+            None => read.await,
+        };
+        Ok(matches!(read, Ok(length) if length > 0))
+    }
+
+    /// Reads the next request from the connection.
+    ///
+    /// Waits up to the keep-alive timeout for the request to start, then
+    /// the headers and the body each have their own timeout.
+    ///
+    /// Returns `Ok(None)` when the connection was closed or stayed idle
+    /// before a full request arrived, and `Err` with the response to send
+    /// when the request is malformed, too large or too slow.
+    pub async fn read_request(
+        &mut self,
+        options: &ServerOptions,
+    ) -> Result<Option<Request>, Response> {
+        // an idle connection is closed without a response
+        if self.buffer.is_empty() {
+            // don't hold on to the memory of a large request while idle
+            if self.buffer.capacity() > MAX_POOLED_BUFFER_SIZE {
+                self.buffer = BytesMut::with_capacity(READ_CHUNK_SIZE);
+            }
+            self.deadline = None;
+            let idle = tokio::time::timeout(options.keep_alive_timeout, self.fill_buffer()).await;
+            if !matches!(idle, Ok(Ok(true))) {
+                return Ok(None);
+            }
+        }
+
+        // read until the end of the headers
+        self.deadline = Some(Instant::now() + options.header_read_timeout);
+        let head_length = loop {
+            if let Some(position) = self
+                .buffer
+                .windows(4)
+                .position(|window| window == b"\r\n\r\n")
+            {
+                break position + 4;
+            }
+            check_head_limits(&self.buffer, options)?;
+            if !self.fill_buffer().await? {
+                return Ok(None);
+            }
+        };
+
+        let head = self.buffer.split_to(head_length);
+        check_head_limits(&head, options)?;
+        let mut request = parse_request_head(&head)?;
+
+        self.deadline = Some(Instant::now() + options.body_read_timeout);
+
+        let transfer_encoding = request.headers.get_joined("transfer-encoding");
+        let content_length = content_length(&request.headers)?;
+        let body = match (transfer_encoding, content_length) {
+            // a message with both is ambiguous and a request smuggling risk
+            (Some(_), Some(_)) => return Err(Response::new(StatusCode::BAD_REQUEST)),
+            (Some(encoding), None) => {
+                // chunked has to be the final encoding for us to find the end
+                let is_chunked = encoding
+                    .rsplit(',')
+                    .next()
+                    .is_some_and(|last| last.trim().eq_ignore_ascii_case("chunked"));
+                if !is_chunked {
+                    return Err(Response::new(StatusCode::BAD_REQUEST));
+                }
+                self.read_chunked_body(options).await?
+            }
+            (None, Some(length)) => {
+                if length > options.max_body_size {
+                    return Err(Response::new(StatusCode::PAYLOAD_TOO_LARGE));
+                }
+                self.read_exact(length).await?
+            }
+            (None, None) => Some(Vec::new()),
+        };
+        let body = match body {
+            Some(body) => body,
+            None => return Ok(None),
+        };
+
+        request.body = String::from_utf8_lossy(&body).to_string();
+        request.body_raw = body;
+
+        Ok(Some(request))
+    }
+
+    /// Reads exactly `length` bytes.
+    /// Returns `None` if the connection closes first.
+    async fn read_exact(&mut self, length: usize) -> Result<Option<Vec<u8>>, Response> {
+        while self.buffer.len() < length {
+            if !self.fill_buffer().await? {
+                return Ok(None);
+            }
+        }
+        Ok(Some(self.buffer.split_to(length).to_vec()))
+    }
+
+    /// Reads a line terminated by CRLF, without the terminator.
+    async fn read_line(&mut self, max_length: usize) -> Result<Option<Vec<u8>>, Response> {
+        loop {
+            if let Some(position) = self.buffer.windows(2).position(|window| window == b"\r\n") {
+                let line = self.buffer.split_to(position + 2);
+                return Ok(Some(line[..position].to_vec()));
+            }
+            if self.buffer.len() > max_length {
+                return Err(Response::new(StatusCode::BAD_REQUEST));
+            }
+            if !self.fill_buffer().await? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Reads and decodes a `Transfer-Encoding: chunked` body.
+    async fn read_chunked_body(
+        &mut self,
+        options: &ServerOptions,
+    ) -> Result<Option<Vec<u8>>, Response> {
+        let max_body_size = options.max_body_size;
+        let mut body = Vec::new();
+        loop {
+            // chunk size line, ex: 1a;name=value
+            let line = match self.read_line(options.max_request_line_size).await? {
+                Some(line) => line,
+                None => return Ok(None),
+            };
+            let line = String::from_utf8_lossy(&line);
+            let size_str = line.split(';').next().unwrap_or("").trim();
+            // only hex digits, from_str_radix would also take a sign
+            if size_str.is_empty() || !size_str.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(Response::new(StatusCode::BAD_REQUEST));
+            }
+            let size = match usize::from_str_radix(size_str, 16) {
+                Ok(size) => size,
+                Err(_) => return Err(Response::new(StatusCode::PAYLOAD_TOO_LARGE)),
+            };
+
+            if size == 0 {
+                // skip trailers until the empty line
+                loop {
+                    match self.read_line(options.max_header_size).await? {
+                        Some(line) if line.is_empty() => return Ok(Some(body)),
+                        Some(_) => continue,
+                        None => return Ok(None),
+                    }
+                }
+            }
+
+            if size > max_body_size - body.len() {
+                return Err(Response::new(StatusCode::PAYLOAD_TOO_LARGE));
+            }
+            let chunk = match self.read_exact(size + 2).await? {
+                Some(chunk) => chunk,
+                None => return Ok(None),
+            };
+            if !chunk.ends_with(b"\r\n") {
+                return Err(Response::new(StatusCode::BAD_REQUEST));
+            }
+            body.extend_from_slice(&chunk[..size]);
+        }
+    }
+
+    /// Writes a response to the connection, streaming the body when it
+    /// isn't known up front.
+    ///
+    /// Streams are sent with chunked encoding, or as they are when
+    /// `chunked` is false, for clients that only find the end of the body
+    /// when the connection closes.
+    ///
+    /// Every write has to finish within `write_timeout`, so a client that
+    /// stops reading can't hold the connection open forever.
+    pub async fn write_response(
+        &mut self,
+        mut response: Response,
+        chunked: bool,
+        write_timeout: Duration,
+    ) -> io::Result<()> {
+        let head = response.head_bytes(chunked);
+        match response.body {
+            Body::Full(body) => {
+                let mut bytes = head;
+                bytes.extend_from_slice(&body);
+                self.write(&bytes, write_timeout).await?;
+            }
+            Body::Stream(mut body) if !chunked => {
+                self.write(&head, write_timeout).await?;
+                while let Some(chunk) = body.next().await {
+                    self.write(&chunk, write_timeout).await?;
+                }
+            }
+            Body::Stream(mut body) => {
+                self.write(&head, write_timeout).await?;
+                while let Some(chunk) = body.next().await {
+                    // an empty chunk would end the body early
+                    if chunk.is_empty() {
+                        continue;
+                    }
+                    let mut bytes = format!("{:x}\r\n", chunk.len()).into_bytes();
+                    bytes.extend_from_slice(&chunk);
+                    bytes.extend_from_slice(b"\r\n");
+                    self.write(&bytes, write_timeout).await?;
+                }
+                self.write(b"0\r\n\r\n", write_timeout).await?;
+            }
+            Body::Sized(mut body, length) => {
+                self.write(&head, write_timeout).await?;
+                let mut written = 0;
+                while let Some(chunk) = body.next().await {
+                    written += chunk.len();
+                    self.write(&chunk, write_timeout).await?;
+                }
+                // the client can only tell a body was cut short when the
+                // connection closes
+                if written != length {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "body ended before its content length",
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes and flushes `bytes`, failing if it takes longer than `timeout`.
+    async fn write(&mut self, bytes: &[u8], timeout: Duration) -> io::Result<()> {
+        let write = async {
+            self.stream.write_all(bytes).await?;
+            self.stream.flush().await
+        };
+        match tokio::time::timeout(timeout, write).await {
+            Ok(result) => result,
+            Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "write timed out")),
+        }
+    }
+}
+impl<S> Drop for Connection<S> {
+    fn drop(&mut self) {
+        self.pool.give_back(std::mem::take(&mut self.buffer));
+    }
+}
+
+/// Checks a request head, or as much of it as has arrived, against the
+/// size limits in `options`.
+fn check_head_limits(head: &[u8], options: &ServerOptions) -> Result<(), Response> {
+    let line_end = head.windows(2).position(|window| window == b"\r\n");
+    if line_end.unwrap_or(head.len()) > options.max_request_line_size {
+        return Err(Response::new(StatusCode::URI_TOO_LONG));
+    }
+    let Some(line_end) = line_end else {
+        return Ok(());
+    };
+    let headers = &head[line_end + 2..];
+    let header_count = headers
+        .split(|byte| *byte == b'\n')
+        .filter(|line| !line.is_empty() && *line != b"\r")
+        .count();
+    if headers.len() > options.max_header_size || header_count > options.max_header_count {
+        return Err(Response::new(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE));
+    }
+    Ok(())
+}
+
+/// The body length from the `Content-Length` headers, `None` without one.
+///
+/// Repeated headers and lists like `5, 5` all have to agree, otherwise
+/// where the body ends would be up to interpretation, and a proxy in front
+/// picking another value than us lets requests be smuggled past it.
+fn content_length(headers: &HeaderMap) -> Result<Option<usize>, Response> {
+    let mut length = None;
+    for value in headers
+        .get_all("content-length")
+        .flat_map(|value| value.split(','))
+    {
+        let value = value.trim();
+        // only digits, parse would also take a sign like `+5`
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(Response::new(StatusCode::BAD_REQUEST));
+        }
+        let value = value
+            .parse::<usize>()
+            .map_err(|_| Response::new(StatusCode::PAYLOAD_TOO_LARGE))?;
+        if length.is_some_and(|length| length != value) {
+            return Err(Response::new(StatusCode::BAD_REQUEST));
+        }
+        length = Some(value);
+    }
+    Ok(length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads one request from `raw` with the default options.
+    async fn read(raw: &[u8]) -> Result<Option<Request>, Response> {
+        read_with(raw, &ServerOptions::default()).await
+    }
+
+    async fn read_with(raw: &[u8], options: &ServerOptions) -> Result<Option<Request>, Response> {
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        client.write_all(raw).await.unwrap();
+        client.shutdown().await.unwrap();
+        let mut connection = Connection::new(server, Arc::new(BufferPool::default()));
+        connection.read_request(options).await
+    }
+
+    fn status(result: Result<Option<Request>, Response>) -> u16 {
+        match result {
+            Ok(_) => 200,
+            Err(response) => response.status.as_u16(),
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_body_by_content_length() {
+        let request = read(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello world")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.body_raw, b"hello");
+    }
+
+    #[tokio::test]
+    async fn rejects_conflicting_content_lengths() {
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: 5\r\nContent-Length: 50\r\n\r\nhello";
+        assert_eq!(status(read(raw).await), 400);
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: 5, 50\r\n\r\nhello";
+        assert_eq!(status(read(raw).await), 400);
+    }
+
+    #[tokio::test]
+    async fn accepts_repeated_equal_content_lengths() {
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: 5\r\ncontent-length: 5\r\n\r\nhello";
+        let request = read(raw).await.unwrap().unwrap();
+        assert_eq!(request.body_raw, b"hello");
+    }
+
+    #[tokio::test]
+    async fn rejects_signed_content_length() {
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: +5\r\n\r\nhello";
+        assert_eq!(status(read(raw).await), 400);
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: -0\r\n\r\n";
+        assert_eq!(status(read(raw).await), 400);
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: \r\n\r\n";
+        assert_eq!(status(read(raw).await), 400);
+    }
+
+    #[tokio::test]
+    async fn decodes_chunked_body() {
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5;name=value\r\nhello\r\n6\r\n world\r\n0\r\nTrailer: x\r\n\r\n";
+        let request = read(raw).await.unwrap().unwrap();
+        assert_eq!(request.body_raw, b"hello world");
+    }
+
+    #[tokio::test]
+    async fn rejects_signed_chunk_size() {
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n+5\r\nhello\r\n0\r\n\r\n";
+        assert_eq!(status(read(raw).await), 400);
+    }
+
+    #[tokio::test]
+    async fn rejects_chunks_over_the_body_limit() {
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffff\r\n";
+        assert_eq!(status(read(raw).await), 413);
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nffffffffffffffffffff\r\n";
+        assert_eq!(status(read(raw).await), 413);
+    }
+
+    #[tokio::test]
+    async fn rejects_content_length_with_transfer_encoding() {
+        let raw = b"POST / HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n";
+        assert_eq!(status(read(raw).await), 400);
+    }
+
+    #[tokio::test]
+    async fn decodes_uppercase_hex_and_empty_trailers() {
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+            A\r\n0123456789\r\n0\r\n\r\n";
+        let request = read(raw).await.unwrap().unwrap();
+        assert_eq!(request.body_raw, b"0123456789");
+    }
+
+    #[tokio::test]
+    async fn requires_chunked_as_the_final_encoding() {
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n0\r\n\r\n";
+        assert!(read(raw).await.unwrap().is_some());
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked, gzip\r\n\r\n0\r\n\r\n";
+        assert_eq!(status(read(raw).await), 400);
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: gzip\r\n\r\n0\r\n\r\n";
+        assert_eq!(status(read(raw).await), 400);
+    }
+
+    #[tokio::test]
+    async fn rejects_chunks_without_crlf() {
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhelloXY0\r\n\r\n";
+        assert_eq!(status(read(raw).await), 400);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_chunk_sizes() {
+        for size in ["", "0x5", "5 5", "g"] {
+            let raw = format!(
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n{}\r\nhello\r\n0\r\n\r\n",
+                size
+            );
+            assert_eq!(status(read(raw.as_bytes()).await), 400, "{:?}", size);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_endless_chunk_size_lines() {
+        let options = ServerOptions {
+            max_request_line_size: 64,
+            ..Default::default()
+        };
+        let raw = format!(
+            "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5;{}",
+            "x".repeat(1024)
+        );
+        assert_eq!(status(read_with(raw.as_bytes(), &options).await), 400);
+    }
+
+    #[tokio::test]
+    async fn limits_the_sum_of_all_chunks() {
+        let options = ServerOptions {
+            max_body_size: 8,
+            ..Default::default()
+        };
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5\r\nhello\r\n3\r\nabc\r\n0\r\n\r\n";
+        let request = read_with(raw, &options).await.unwrap().unwrap();
+        assert_eq!(request.body_raw, b"helloabc");
+        let raw = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5\r\nhello\r\n4\r\nabcd\r\n0\r\n\r\n";
+        assert_eq!(status(read_with(raw, &options).await), 413);
+    }
+
+    #[tokio::test]
+    async fn truncated_chunked_bodies_close_the_connection() {
+        for raw in [
+            &b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhel"[..],
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n",
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\nTrailer: x\r\n",
+        ] {
+            assert!(read(raw).await.unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_the_next_request_after_a_chunked_body() {
+        let (mut client, server) = tokio::io::duplex(64 * 1024);
+        client
+            .write_all(
+                b"POST /a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                2\r\nhi\r\n0\r\n\r\nGET /b HTTP/1.1\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        client.shutdown().await.unwrap();
+        let mut connection = Connection::new(server, Arc::new(BufferPool::default()));
+        let options = ServerOptions::default();
+        let first = connection.read_request(&options).await.unwrap().unwrap();
+        assert_eq!(first.body_raw, b"hi");
+        let second = connection.read_request(&options).await.unwrap().unwrap();
+        assert_eq!(second.path, "/b");
+        assert!(second.body_raw.is_empty());
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+/// `SameSite` attribute of a cookie.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+impl SameSite {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+/// A cookie sent to the client with a `Set-Cookie` header.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub max_age: Option<Duration>,
+    pub path: Option<String>,
+    pub domain: Option<String>,
+    pub http_only: bool,
+    pub secure: bool,
+    pub same_site: Option<SameSite>,
+}
+impl Cookie {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Cookie {
+        Cookie {
+            name: name.into(),
+            value: value.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Cookie that tells the client to delete `name` right away.
+    pub fn removal(name: impl Into<String>) -> Cookie {
+        Cookie::new(name, "").with_max_age(Duration::ZERO)
+    }
+
+    pub fn with_max_age(mut self, max_age: Duration) -> Cookie {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn with_path(mut self, path: impl Into<String>) -> Cookie {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn with_domain(mut self, domain: impl Into<String>) -> Cookie {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    /// Hides the cookie from javascript.
+    pub fn http_only(mut self) -> Cookie {
+        self.http_only = true;
+        self
+    }
+
+    /// Only sends the cookie over https.
+    pub fn secure(mut self) -> Cookie {
+        self.secure = true;
+        self
+    }
+
+    pub fn with_same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+impl fmt::Display for Cookie {
+    /// Formats the cookie as a `Set-Cookie` header value.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", attribute(&self.name), attribute(&self.value))?;
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", attribute(path))?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", attribute(domain))?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+/// `value` without control characters and `;`, which would end the header
+/// or start another attribute.
+fn attribute(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| *c != ';' && !c.is_ascii_control())
+        .collect()
+}
+
+/// Parses a `Cookie` request header like `a=1; b=2` into a map.
+/// Surrounding double quotes around values are removed.
+pub fn parse_cookies(header: &str) -> HashMap<String, String> {
+    header
+        .split(';')
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=')?;
+            let name = name.trim();
+            if name.is_empty() {
+                return None;
+            }
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+            Some((name.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_set_cookie_values() {
+        let cookie = Cookie::new("session", "abc")
+            .with_max_age(Duration::from_secs(60))
+            .with_path("/")
+            .http_only()
+            .with_same_site(SameSite::Lax);
+        assert_eq!(
+            cookie.to_string(),
+            "session=abc; Max-Age=60; Path=/; HttpOnly; SameSite=Lax"
+        );
+    }
+
+    #[test]
+    fn cookies_cannot_add_attributes_or_headers() {
+        let cookie = Cookie::new("na;me", "v\r\nSet-Cookie: admin=1; Secure")
+            .with_path("/\0; Domain=evil.example")
+            .with_domain("example.com\n");
+        assert_eq!(
+            cookie.to_string(),
+            "name=vSet-Cookie: admin=1 Secure; Path=/ Domain=evil.example; Domain=example.com"
+        );
+    }
+}
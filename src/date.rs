@@ -0,0 +1,24 @@
+use std::cell::RefCell;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+thread_local! {
+    /// the current second and its formatted date, formatting only happens
+    /// once a second per thread
+    static CACHED_DATE: RefCell<(u64, String)> = const { RefCell::new((0, String::new())) };
+}
+
+/// The current time as an HTTP date for the `Date` header, like
+/// `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub(crate) fn http_date_now() -> String {
+    let now = SystemTime::now();
+    let second = now
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs());
+    CACHED_DATE.with(|cached| {
+        let mut cached = cached.borrow_mut();
+        if cached.0 != second || cached.1.is_empty() {
+            *cached = (second, httpdate::fmt_http_date(now));
+        }
+        cached.1.clone()
+    })
+}
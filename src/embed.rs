@@ -0,0 +1,133 @@
+use crate::{insert_static_entry, Server, StaticDirectoryEntry, VirtualHost};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+/// Files compiled into the binary, served like a static directory with
+/// `Server::serve_embedded` so a deployment is a single executable.
+///
+/// Build it from an `include_dir!` directory, or add files embedded with
+/// `include_bytes!` one by one.
+///
+/// ```ignore
+/// static ASSETS: include_dir::Dir = include_dir::include_dir!("$CARGO_MANIFEST_DIR/assets");
+///
+/// server.serve_embedded(String::from("assets"), EmbeddedFiles::from(&ASSETS));
+/// ```
+#[derive(Default, Clone, Eq, PartialEq, Hash)]
+pub struct EmbeddedFiles {
+    /// files by their path relative to the mount, without a leading `/`
+    files: BTreeMap<String, EmbeddedFile>,
+}
+
+#[derive(Clone, Eq, PartialEq, Hash)]
+pub(crate) struct EmbeddedFile {
+    pub(crate) contents: &'static [u8],
+    /// hashed once up front, embedded files never change
+    pub(crate) etag: String,
+}
+
+impl EmbeddedFiles {
+    pub fn new() -> EmbeddedFiles {
+        EmbeddedFiles::default()
+    }
+
+    /// Adds a file at `path`, relative to where the files are mounted,
+    /// like `css/site.css`.
+    pub fn insert(&mut self, path: impl Into<String>, contents: &'static [u8]) -> &mut Self {
+        let path = path.into().trim_start_matches('/').to_string();
+        self.files.insert(
+            path,
+            EmbeddedFile {
+                contents,
+                etag: contents_etag(contents),
+            },
+        );
+        self
+    }
+
+    /// The paths of every file, in order.
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.files.keys().map(|path| path.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    pub(crate) fn get(&self, path: &str) -> Option<&EmbeddedFile> {
+        self.files.get(path.trim_start_matches('/'))
+    }
+}
+impl fmt::Debug for EmbeddedFiles {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the contents would flood the output
+        f.debug_set().entries(self.paths()).finish()
+    }
+}
+impl From<&'static include_dir::Dir<'static>> for EmbeddedFiles {
+    /// Takes every file of the directory and its subdirectories.
+    fn from(dir: &'static include_dir::Dir<'static>) -> EmbeddedFiles {
+        let mut files = EmbeddedFiles::new();
+        let mut pending = vec![dir];
+        while let Some(dir) = pending.pop() {
+            for file in dir.files() {
+                // include_dir paths are relative to the embedded directory
+                let path = file.path().to_string_lossy().replace('\\', "/");
+                files.insert(path, file.contents());
+            }
+            pending.extend(dir.dirs());
+        }
+        files
+    }
+}
+
+/// Strong ETag for embedded contents from their length and hash.
+fn contents_etag(contents: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    contents.hash(&mut hasher);
+    format!("\"{:x}-{:x}\"", contents.len(), hasher.finish())
+}
+
+/// A static entry serving `files` instead of a directory on disk.
+fn embedded_entry(files: EmbeddedFiles) -> StaticDirectoryEntry {
+    StaticDirectoryEntry {
+        embedded: Some(Arc::new(files)),
+        ..Default::default()
+    }
+}
+
+impl Server {
+    /// Serves files compiled into the binary at the given endpoint, the
+    /// same way `serve` serves a directory. Uploads and directory listings
+    /// aren't available for embedded files.
+    pub fn serve_embedded(
+        &mut self,
+        path: String,
+        files: EmbeddedFiles,
+    ) -> &mut StaticDirectoryEntry {
+        insert_static_entry(
+            &mut self.registry.static_directories,
+            path,
+            embedded_entry(files),
+        )
+    }
+}
+
+impl VirtualHost {
+    /// Serves files compiled into the binary for this host, see
+    /// `Server::serve_embedded`.
+    pub fn serve_embedded(
+        &mut self,
+        path: String,
+        files: EmbeddedFiles,
+    ) -> &mut StaticDirectoryEntry {
+        insert_static_entry(&mut self.static_directories, path, embedded_entry(files))
+    }
+}
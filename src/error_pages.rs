@@ -0,0 +1,83 @@
+use crate::{Response, StatusCode};
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+/// Html pages sent in place of the empty body of error responses, like a
+/// custom 404 page, see `Server::set_error_pages`.
+///
+/// Pages are looked up by status, then by class, so a `5xx` page covers
+/// every server error without a page of its own. Responses that already
+/// have a body are left alone.
+#[derive(Debug, Default, Clone)]
+pub struct ErrorPages {
+    /// pages by status, or by class with the keys 4 and 5
+    pages: HashMap<u16, Bytes>,
+}
+impl ErrorPages {
+    pub fn new() -> ErrorPages {
+        ErrorPages::default()
+    }
+
+    /// Loads every `<status>.html` page in `directory`, like `404.html`,
+    /// plus `4xx.html` and `5xx.html` for the statuses without one.
+    pub fn from_directory(directory: impl AsRef<Path>) -> io::Result<ErrorPages> {
+        let mut pages = ErrorPages::new();
+        for dir_entry in std::fs::read_dir(directory)? {
+            let path = dir_entry?.path();
+            let Some(name) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(".html"))
+            else {
+                continue;
+            };
+            let key = match name {
+                "4xx" => 4,
+                "5xx" => 5,
+                status => match status.parse::<u16>() {
+                    Ok(status) if (400..600).contains(&status) => status,
+                    _ => continue,
+                },
+            };
+            pages.pages.insert(key, std::fs::read(&path)?.into());
+        }
+        Ok(pages)
+    }
+
+    /// Sets the page sent for `status`.
+    pub fn insert(&mut self, status: StatusCode, html: impl Into<Bytes>) -> &mut Self {
+        self.pages.insert(status.as_u16(), html.into());
+        self
+    }
+
+    /// Sets the page for every client error (4xx) or server error (5xx)
+    /// without a page of its own, `class` is 4 or 5.
+    pub fn insert_class(&mut self, class: u16, html: impl Into<Bytes>) -> &mut Self {
+        self.pages.insert(class, html.into());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    /// Gives an error response without a body its page, if there is one.
+    pub(crate) fn apply(&self, response: &mut Response) {
+        let status = response.status.as_u16();
+        if status < 400 || !response.body.is_empty() || self.pages.is_empty() {
+            return;
+        }
+        let Some(page) = self
+            .pages
+            .get(&status)
+            .or_else(|| self.pages.get(&(status / 100)))
+        else {
+            return;
+        };
+        response.headers.remove("Content-Length");
+        response.set_header("Content-Type", "text/html; charset=utf-8");
+        response.body = page.clone().into();
+    }
+}
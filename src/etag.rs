@@ -0,0 +1,36 @@
+use std::fs::Metadata;
+use std::time::UNIX_EPOCH;
+
+/// Strong ETag for a file built from its size and modification time,
+/// so it changes whenever the file is rewritten without hashing it.
+pub fn file_etag(metadata: &Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |modified| modified.as_nanos());
+    format!("\"{:x}-{:x}\"", metadata.len(), modified)
+}
+
+/// Whether an `If-None-Match` header value matches `etag`.
+///
+/// Uses the weak comparison from RFC 9110, so `W/` prefixes are ignored.
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    if_none_match.split(',').any(|candidate| {
+        let candidate = candidate.trim();
+        candidate == "*" || candidate.trim_start_matches("W/") == etag
+    })
+}
+
+/// Whether an `If-Match` header value matches `etag`.
+///
+/// Uses the strong comparison from RFC 9110, so weak tags never match
+/// since the file might have changed in a way they don't tell apart.
+pub fn etag_matches_strong(if_match: &str, etag: &str) -> bool {
+    !etag.starts_with("W/")
+        && if_match.split(',').any(|candidate| {
+            let candidate = candidate.trim();
+            candidate == "*" || candidate == etag
+        })
+}
@@ -0,0 +1,283 @@
+use crate::{HandlerFuture, IntoResponse, Json, Request, Response, StatusCode};
+use serde::de::value::{Error, MapDeserializer, StrDeserializer};
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Unexpected, Visitor};
+use serde::forward_to_deserialize_any;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+
+/// A value that can be pulled out of a request to be passed to a handler
+/// wrapped with `extract`.
+///
+/// Failing returns the response to send instead of calling the handler.
+pub trait FromRequest: Sized {
+    fn from_request(request: &Request) -> Result<Self, Response>;
+}
+
+/// Route params deserialized into `T`, either a struct with a field per
+/// param or a single value like `u32` when the route has one param.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct Path<T>(pub T);
+
+/// Query string parameters deserialized into `T`.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct Query<T>(pub T);
+
+/// Shared state registered with `Server::with_state`.
+#[derive(Debug, Clone)]
+pub struct State<T>(pub Arc<T>);
+
+impl<T: DeserializeOwned> FromRequest for Path<T> {
+    fn from_request(request: &Request) -> Result<Path<T>, Response> {
+        T::deserialize(ParamsDeserializer(&request.params))
+            .map(Path)
+            .map_err(|e| {
+                Response::new(StatusCode::BAD_REQUEST)
+                    .with_body(format!("invalid path params: {}", e))
+            })
+    }
+}
+
+impl<T: DeserializeOwned> FromRequest for Query<T> {
+    fn from_request(request: &Request) -> Result<Query<T>, Response> {
+        T::deserialize(ParamsDeserializer(&request.query))
+            .map(Query)
+            .map_err(|e| {
+                Response::new(StatusCode::BAD_REQUEST)
+                    .with_body(format!("invalid query string: {}", e))
+            })
+    }
+}
+
+impl<T: DeserializeOwned> FromRequest for Json<T> {
+    /// Extracts the JSON body of a request, see `Request::json`.
+    fn from_request(request: &Request) -> Result<Json<T>, Response> {
+        request.json().map(Json)
+    }
+}
+
+impl<T: Send + Sync + 'static> FromRequest for State<T> {
+    /// Fails with a 500 since missing state is a bug in the server setup.
+    fn from_request(request: &Request) -> Result<State<T>, Response> {
+        request.state().map(State).ok_or_else(|| {
+            Response::new(StatusCode::INTERNAL_SERVER_ERROR).with_body(format!(
+                "no state of type {} registered",
+                std::any::type_name::<T>()
+            ))
+        })
+    }
+}
+
+impl FromRequest for String {
+    /// The body as text, replacing invalid utf-8.
+    fn from_request(request: &Request) -> Result<String, Response> {
+        Ok(request.body.clone())
+    }
+}
+
+/// A handler whose arguments are all extracted from the request, see
+/// `extract`.
+pub trait ExtractHandler<Args>: Send + Sync + 'static {
+    fn call(&self, request: &Request) -> HandlerFuture;
+}
+
+macro_rules! extract_handler {
+    ($($arg:ident),+) => {
+        impl<F, Fut, $($arg),+> ExtractHandler<($($arg,)+)> for F
+        where
+            F: Fn($($arg),+) -> Fut + Send + Sync + 'static,
+            Fut: Future + Send + 'static,
+            Fut::Output: IntoResponse,
+            $($arg: FromRequest),+
+        {
+            #[allow(non_snake_case)]
+            fn call(&self, request: &Request) -> HandlerFuture {
+                $(
+                    let $arg = match <$arg as FromRequest>::from_request(request) {
+                        Ok(value) => value,
+                        Err(response) => return Box::pin(async move { response }),
+                    };
+                )+
+                let future = self($($arg),+);
+                Box::pin(async move { future.await.into_response() })
+            }
+        }
+    };
+}
+extract_handler!(A);
+extract_handler!(A, B);
+extract_handler!(A, B, C);
+extract_handler!(A, B, C, D);
+extract_handler!(A, B, C, D, E);
+extract_handler!(A, B, C, D, E, G);
+
+/// Turns a handler taking extractors like `Path<u32>`, `Query<Params>` or
+/// `Json<Body>` into one that can be registered on a route.
+///
+/// The arguments are extracted in order and the first one that fails
+/// answers the request instead of the handler.
+///
+/// ```ignore
+/// server.get(String::from("items/:id"), extract(|Path(id): Path<u32>| async move {
+///     format!("item {}", id)
+/// }));
+/// ```
+pub fn extract<H, Args>(handler: H) -> impl Fn(Request) -> HandlerFuture + Send + Sync + 'static
+where
+    H: ExtractHandler<Args>,
+    Args: 'static,
+{
+    move |request: Request| handler.call(&request)
+}
+
+/// Deserializes a map of decoded strings, like route params or the query
+/// string, into a struct or map, or into a single value when there is
+/// only one entry.
+struct ParamsDeserializer<'a>(&'a HashMap<String, String>);
+impl<'a> ParamsDeserializer<'a> {
+    fn single(&self) -> Result<ValueDeserializer<'a>, Error> {
+        let mut values = self.0.values();
+        match (values.next(), values.next()) {
+            (Some(value), None) => Ok(ValueDeserializer(value)),
+            _ => Err(de::Error::custom(format!(
+                "expected a single value but got {}",
+                self.0.len()
+            ))),
+        }
+    }
+}
+
+macro_rules! single_value {
+    ($($method:ident)*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+            self.single()?.$method(visitor)
+        }
+    )*};
+}
+
+impl<'de> de::Deserializer<'de> for ParamsDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let entries = self
+            .0
+            .iter()
+            .map(|(key, value)| (key.as_str(), ValueDeserializer(value)));
+        visitor.visit_map(MapDeserializer::new(entries))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        self.single()?.deserialize_enum(name, variants, visitor)
+    }
+
+    single_value! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_i128 deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+        deserialize_u128 deserialize_f32 deserialize_f64 deserialize_char deserialize_str
+        deserialize_string deserialize_bytes deserialize_byte_buf deserialize_option
+        deserialize_unit deserialize_identifier
+    }
+
+    forward_to_deserialize_any! {
+        unit_struct seq tuple tuple_struct ignored_any
+    }
+}
+
+/// Deserializes one decoded string, parsing it for numbers and bools.
+struct ValueDeserializer<'a>(&'a str);
+
+macro_rules! parse_value {
+    ($($method:ident => $visit:ident)*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+            match self.0.parse() {
+                Ok(value) => visitor.$visit(value),
+                Err(_) => Err(de::Error::invalid_value(Unexpected::Str(self.0), &visitor)),
+            }
+        }
+    )*};
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_borrowed_str(self.0)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error> {
+        let variant: StrDeserializer<'de, Error> = self.0.into_deserializer();
+        visitor.visit_enum(variant)
+    }
+
+    parse_value! {
+        deserialize_bool => visit_bool
+        deserialize_i8 => visit_i8
+        deserialize_i16 => visit_i16
+        deserialize_i32 => visit_i32
+        deserialize_i64 => visit_i64
+        deserialize_i128 => visit_i128
+        deserialize_u8 => visit_u8
+        deserialize_u16 => visit_u16
+        deserialize_u32 => visit_u32
+        deserialize_u64 => visit_u64
+        deserialize_u128 => visit_u128
+        deserialize_f32 => visit_f32
+        deserialize_f64 => visit_f64
+        deserialize_char => visit_char
+    }
+
+    forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+impl<'de> IntoDeserializer<'de, Error> for ValueDeserializer<'de> {
+    type Deserializer = ValueDeserializer<'de>;
+
+    fn into_deserializer(self) -> ValueDeserializer<'de> {
+        self
+    }
+}
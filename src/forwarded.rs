@@ -0,0 +1,275 @@
+use crate::Request;
+use serde::{de, Deserialize, Deserializer};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+/// A range of ip addresses like `10.0.0.0/8`, used to list the proxies
+/// whose forwarding headers are trusted, see `Server::set_trusted_proxies`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct IpCidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+impl IpCidr {
+    /// Parses `addr/prefix`, or a single address without a prefix.
+    pub fn parse(cidr: &str) -> Option<IpCidr> {
+        let (addr, prefix) = match cidr.trim().split_once('/') {
+            Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, Some(prefix)),
+            None => (cidr.trim().parse::<IpAddr>().ok()?, None),
+        };
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().ok().filter(|prefix| *prefix <= max)?,
+            None => max,
+        };
+        Some(IpCidr { addr, prefix })
+    }
+
+    /// Whether `ip` is in the range. IPv4 addresses mapped into IPv6, as
+    /// dual stack sockets report them, match IPv4 ranges.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            ip => ip,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(range), IpAddr::V4(ip)) => prefix_matches(
+                u32::from(range).into(),
+                u32::from(ip).into(),
+                32,
+                self.prefix,
+            ),
+            (IpAddr::V6(range), IpAddr::V6(ip)) => {
+                prefix_matches(u128::from(range), u128::from(ip), 128, self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+impl fmt::Display for IpCidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+impl<'de> Deserialize<'de> for IpCidr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<IpCidr, D::Error> {
+        let cidr = String::deserialize(deserializer)?;
+        IpCidr::parse(&cidr)
+            .ok_or_else(|| de::Error::custom(format!("invalid ip range {:?}", cidr)))
+    }
+}
+
+/// Whether the first `prefix` of `bits` bits are the same in both.
+fn prefix_matches(range: u128, ip: u128, bits: u32, prefix: u8) -> bool {
+    let shift = bits - u32::from(prefix);
+    shift >= bits || range >> shift == ip >> shift
+}
+
+/// Finds the ip of the client behind any trusted proxies.
+///
+/// The forwarding headers are only believed when the peer is a trusted
+/// proxy. The addresses they list are walked from the nearest hop back,
+/// and the first one that isn't trusted is the client. `Forwarded` is
+/// preferred over `X-Forwarded-For` when a proxy sends both.
+pub(crate) fn resolve_client_ip(request: &Request, trusted: &[IpCidr]) -> Option<IpAddr> {
+    let peer = request.remote_addr?.ip();
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|cidr| cidr.contains(ip));
+    if !is_trusted(peer) {
+        return Some(peer);
+    }
+
+    let hops = match request.headers.get_joined("forwarded") {
+        Some(forwarded) => forwarded_for(&forwarded),
+        None => match request.headers.get_joined("x-forwarded-for") {
+            Some(forwarded_for) => forwarded_for
+                .split(',')
+                .map(|hop| hop.trim().to_string())
+                .collect(),
+            None => Vec::new(),
+        },
+    };
+    let mut client = peer;
+    for hop in hops.iter().rev() {
+        // hidden or unknown hops end the chain we can vouch for
+        let Some(ip) = parse_node(hop) else {
+            break;
+        };
+        client = ip;
+        if !is_trusted(ip) {
+            break;
+        }
+    }
+    Some(client)
+}
+
+/// The `for` parameter of every element of a `Forwarded` header
+/// (RFC 7239), in order. Elements without one are left out.
+fn forwarded_for(header: &str) -> Vec<String> {
+    header
+        .split(',')
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (name, value) = pair.split_once('=')?;
+                name.trim()
+                    .eq_ignore_ascii_case("for")
+                    .then(|| value.trim().trim_matches('"').to_string())
+            })
+        })
+        .collect()
+}
+
+/// Parses a forwarded node like `192.0.2.60`, `192.0.2.60:8080` or
+/// `[2001:db8::17]:4711`.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    // a bracketed IPv6 address without a port
+    node.strip_prefix('[')?.strip_suffix(']')?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidrs(ranges: &[&str]) -> Vec<IpCidr> {
+        ranges
+            .iter()
+            .map(|range| IpCidr::parse(range).unwrap())
+            .collect()
+    }
+
+    /// A request from `peer` with the given headers.
+    fn request_from(peer: &str, headers: &[(&str, &str)]) -> Request {
+        let mut request = Request {
+            remote_addr: Some(SocketAddr::new(peer.parse().unwrap(), 4000)),
+            ..Default::default()
+        };
+        for (name, value) in headers {
+            request.headers.append(*name, *value);
+        }
+        request
+    }
+
+    fn client_ip(request: &Request, trusted: &[&str]) -> String {
+        resolve_client_ip(request, &cidrs(trusted))
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn parses_ip_ranges() {
+        assert_eq!(
+            IpCidr::parse("10.0.0.0/8").unwrap().to_string(),
+            "10.0.0.0/8"
+        );
+        assert_eq!(
+            IpCidr::parse(" 10.1.2.3 ").unwrap().to_string(),
+            "10.1.2.3/32"
+        );
+        assert_eq!(IpCidr::parse("::1").unwrap().to_string(), "::1/128");
+        assert_eq!(IpCidr::parse("fd00::/8").unwrap().to_string(), "fd00::/8");
+        for invalid in ["10.0.0.0/33", "::/129", "10.0.0/8", "10.0.0.0/x", "", "/8"] {
+            assert!(IpCidr::parse(invalid).is_none(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn ranges_contain_their_addresses() {
+        let range = IpCidr::parse("10.0.0.0/8").unwrap();
+        assert!(range.contains("10.255.0.1".parse().unwrap()));
+        assert!(!range.contains("11.0.0.1".parse().unwrap()));
+        // IPv4 mapped into IPv6, like from a dual stack socket
+        assert!(range.contains("::ffff:10.0.0.1".parse().unwrap()));
+        assert!(!range.contains("fd00::1".parse().unwrap()));
+
+        let everything = IpCidr::parse("0.0.0.0/0").unwrap();
+        assert!(everything.contains("203.0.113.9".parse().unwrap()));
+        let single = IpCidr::parse("fd00::1").unwrap();
+        assert!(single.contains("fd00::1".parse().unwrap()));
+        assert!(!single.contains("fd00::2".parse().unwrap()));
+    }
+
+    #[test]
+    fn untrusted_peers_cannot_spoof_their_ip() {
+        let request = request_from("203.0.113.9", &[("X-Forwarded-For", "1.2.3.4")]);
+        assert_eq!(client_ip(&request, &["10.0.0.0/8"]), "203.0.113.9");
+        assert_eq!(client_ip(&request, &[]), "203.0.113.9");
+    }
+
+    #[test]
+    fn walks_x_forwarded_for_back_to_the_first_untrusted_hop() {
+        // the client can put anything in front, only the hops added by
+        // trusted proxies count
+        let request = request_from(
+            "10.0.0.2",
+            &[("X-Forwarded-For", "6.6.6.6, 198.51.100.7, 10.0.0.1")],
+        );
+        assert_eq!(client_ip(&request, &["10.0.0.0/8"]), "198.51.100.7");
+
+        // repeated headers are one list
+        let request = request_from(
+            "10.0.0.2",
+            &[
+                ("X-Forwarded-For", "6.6.6.6"),
+                ("X-Forwarded-For", "198.51.100.7, 10.0.0.1"),
+            ],
+        );
+        assert_eq!(client_ip(&request, &["10.0.0.0/8"]), "198.51.100.7");
+    }
+
+    #[test]
+    fn all_trusted_hops_give_the_furthest_one() {
+        let request = request_from("10.0.0.2", &[("X-Forwarded-For", "10.0.0.5, 10.0.0.1")]);
+        assert_eq!(client_ip(&request, &["10.0.0.0/8"]), "10.0.0.5");
+        let request = request_from("10.0.0.2", &[]);
+        assert_eq!(client_ip(&request, &["10.0.0.0/8"]), "10.0.0.2");
+    }
+
+    #[test]
+    fn prefers_forwarded_over_x_forwarded_for() {
+        let request = request_from(
+            "10.0.0.2",
+            &[
+                ("X-Forwarded-For", "6.6.6.6"),
+                (
+                    "Forwarded",
+                    "for=192.0.2.60;proto=https, For=\"[2001:db8::17]:4711\";by=10.0.0.2",
+                ),
+            ],
+        );
+        assert_eq!(client_ip(&request, &["10.0.0.0/8"]), "2001:db8::17");
+        assert_eq!(
+            client_ip(&request, &["10.0.0.0/8", "2001:db8::/32"]),
+            "192.0.2.60"
+        );
+    }
+
+    #[test]
+    fn unknown_or_hidden_hops_end_the_chain() {
+        let request = request_from(
+            "10.0.0.2",
+            &[("Forwarded", "for=192.0.2.60, for=unknown, for=10.0.0.1")],
+        );
+        assert_eq!(client_ip(&request, &["10.0.0.0/8"]), "10.0.0.1");
+        let request = request_from("10.0.0.2", &[("X-Forwarded-For", "192.0.2.60, _hidden")]);
+        assert_eq!(client_ip(&request, &["10.0.0.0/8"]), "10.0.0.2");
+    }
+
+    #[test]
+    fn parses_forwarded_nodes() {
+        assert_eq!(parse_node("192.0.2.60"), "192.0.2.60".parse().ok());
+        assert_eq!(parse_node("192.0.2.60:8080"), "192.0.2.60".parse().ok());
+        assert_eq!(
+            parse_node("[2001:db8::17]:4711"),
+            "2001:db8::17".parse().ok()
+        );
+        assert_eq!(parse_node("[2001:db8::17]"), "2001:db8::17".parse().ok());
+        assert_eq!(parse_node("2001:db8::17"), "2001:db8::17".parse().ok());
+        assert_eq!(parse_node("unknown"), None);
+        assert_eq!(parse_node("_hidden"), None);
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+
+/// Headers of a request or response, allowing several values per name like
+/// repeated `Cookie`, `Accept` or `Set-Cookie` headers.
+///
+/// Names are compared ignoring case but keep the case they were added with,
+/// and headers are kept in the order they were added.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HeaderMap {
+    entries: Vec<(String, String)>,
+}
+impl HeaderMap {
+    pub fn new() -> HeaderMap {
+        HeaderMap::default()
+    }
+
+    /// The first value of `name`.
+    pub fn get(&self, name: &str) -> Option<&String> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    /// Every value of `name` in the order they were added.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a String> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    /// Every value of `name` joined with commas, which is how repeated list
+    /// headers like `Accept` or `X-Forwarded-For` combine into one.
+    pub fn get_joined(&self, name: &str) -> Option<String> {
+        let values: Vec<&str> = self.get_all(name).map(|value| value.as_str()).collect();
+        if values.is_empty() {
+            None
+        } else {
+            Some(values.join(", "))
+        }
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Sets a header, replacing every existing value with the same name.
+    /// Returns the first value that was replaced.
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) -> Option<String> {
+        let name = name.into();
+        let previous = self.remove(&name);
+        self.entries.push((name, value.into()));
+        previous
+    }
+
+    /// Adds a value, keeping any existing values with the same name.
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.entries.push((name.into(), value.into()));
+    }
+
+    /// Removes every value of `name`, returning the first one.
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let mut removed = None;
+        self.entries.retain_mut(|(key, value)| {
+            if !key.eq_ignore_ascii_case(name) {
+                return true;
+            }
+            if removed.is_none() {
+                removed = Some(std::mem::take(value));
+            }
+            false
+        });
+        removed
+    }
+
+    /// Keeps only the headers `keep` returns true for.
+    pub fn retain(&mut self, mut keep: impl FnMut(&String, &String) -> bool) {
+        self.entries.retain(|(name, value)| keep(name, value));
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, (String, String)> {
+        self.entries.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+impl<'a> IntoIterator for &'a HeaderMap {
+    type Item = &'a (String, String);
+    type IntoIter = std::slice::Iter<'a, (String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
+impl IntoIterator for HeaderMap {
+    type Item = (String, String);
+    type IntoIter = std::vec::IntoIter<(String, String)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+impl<K: Into<String>, V: Into<String>> Extend<(K, V)> for HeaderMap {
+    /// Appends every header, keeping existing values.
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, headers: I) {
+        for (name, value) in headers {
+            self.append(name, value);
+        }
+    }
+}
+impl<K: Into<String>, V: Into<String>> FromIterator<(K, V)> for HeaderMap {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(headers: I) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        map.extend(headers);
+        map
+    }
+}
+impl From<HashMap<String, String>> for HeaderMap {
+    fn from(headers: HashMap<String, String>) -> HeaderMap {
+        headers.into_iter().collect()
+    }
+}
@@ -0,0 +1,79 @@
+use crate::{Response, StatusCode};
+use futures_util::future::join_all;
+use serde_json::{json, Map, Value};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+
+/// Type-erased health check, resolves to an error message when unhealthy.
+pub type HealthCheck =
+    Arc<dyn Fn() -> Pin<Box<dyn Future<Output = Result<(), String>> + Send>> + Send + Sync>;
+
+/// Named checks behind `/healthz` and `/readyz`.
+#[derive(Default, Clone)]
+pub struct HealthChecks {
+    /// checks that the process is working at all
+    pub liveness: Vec<(String, HealthCheck)>,
+    /// checks that the server can take traffic, like a database connection
+    pub readiness: Vec<(String, HealthCheck)>,
+}
+impl std::fmt::Debug for HealthChecks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names = |checks: &[(String, HealthCheck)]| {
+            checks
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect::<Vec<_>>()
+        };
+        f.debug_struct("HealthChecks")
+            .field("liveness", &names(&self.liveness))
+            .field("readiness", &names(&self.readiness))
+            .finish()
+    }
+}
+
+/// Wraps a check function into the type stored in `HealthChecks`.
+pub(crate) fn into_health_check<F, Fut>(check: F) -> HealthCheck
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<(), String>> + Send + 'static,
+{
+    Arc::new(move || Box::pin(check()))
+}
+
+/// Runs every check at once and builds a json summary, 200 when all of
+/// them pass and 503 otherwise.
+pub(crate) async fn run_health_checks(
+    checks: &Arc<RwLock<HealthChecks>>,
+    select: fn(&HealthChecks) -> &Vec<(String, HealthCheck)>,
+) -> Response {
+    // clone the checks so the lock isn't held while they run
+    let checks = select(&checks.read().unwrap()).clone();
+    let results = join_all(checks.iter().map(|(_, check)| check())).await;
+
+    let mut healthy = true;
+    let mut summary = Map::new();
+    for ((name, _), result) in checks.iter().zip(results) {
+        let status = match result {
+            Ok(()) => json!({ "status": "ok" }),
+            Err(error) => {
+                healthy = false;
+                json!({ "status": "error", "error": error })
+            }
+        };
+        summary.insert(name.clone(), status);
+    }
+
+    let body = json!({
+        "status": if healthy { "ok" } else { "unavailable" },
+        "checks": Value::Object(summary),
+    });
+    let status = if healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    Response::json(&body)
+        .with_status(status)
+        .with_header("Cache-Control", "no-store")
+}
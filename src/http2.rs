@@ -0,0 +1,337 @@
+use crate::{
+    decode_path, service_unavailable, Body, ConnectionContext, ConnectionInfo, HeaderMap, HttpVerb,
+    HttpVersion, Request, Response, ServerOptions, ServerRegistry, StatusCode,
+};
+use bytes::Bytes;
+use futures_util::StreamExt;
+use h2::server::SendResponse;
+use h2::RecvStream;
+use h2::SendStream;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tracing::{warn, Instrument};
+
+/// headers that only make sense for http/1.1 and are forbidden in http/2
+const CONNECTION_HEADERS: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Serves an http/2 connection, handling each stream concurrently with the
+/// same registry used for http/1.1.
+pub(crate) async fn serve_h2<S>(
+    registry: Arc<ServerRegistry>,
+    stream: S,
+    context: ConnectionContext,
+) where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let ConnectionContext {
+        mut shutdown,
+        overloaded,
+        info,
+    } = context;
+    let handshake = h2::server::Builder::new()
+        .max_header_list_size(
+            registry
+                .options
+                .max_header_size
+                .try_into()
+                .unwrap_or(u32::MAX),
+        )
+        .handshake(stream);
+    let mut connection = match handshake.await {
+        Ok(connection) => connection,
+        Err(e) => {
+            warn!(error = ?e, "http/2 handshake failed");
+            return;
+        }
+    };
+    // streams already opened get a 503, new ones are refused
+    if overloaded {
+        connection.graceful_shutdown();
+    }
+
+    // every stream task holds a clone, so a count of one means idle
+    let open_streams = Arc::new(());
+    let mut served = 0;
+    let mut idle_shutdown = false;
+    loop {
+        let result = tokio::select! {
+            result = connection.accept() => match result {
+                Some(result) => result,
+                None => break,
+            },
+            // sends GOAWAY, open streams still finish
+            Ok(()) = shutdown.changed() => {
+                connection.graceful_shutdown();
+                continue;
+            }
+            _ = tokio::time::sleep(registry.options.keep_alive_timeout) => {
+                if Arc::strong_count(&open_streams) > 1 {
+                    idle_shutdown = false;
+                    continue;
+                }
+                // clients that ignore the GOAWAY are dropped the next time
+                if idle_shutdown {
+                    break;
+                }
+                connection.graceful_shutdown();
+                idle_shutdown = true;
+                continue;
+            }
+        };
+        let (request, mut respond) = match result {
+            Ok(stream) => stream,
+            Err(_) => break,
+        };
+        served += 1;
+        if registry
+            .options
+            .max_requests_per_connection
+            .is_some_and(|max| served >= max)
+        {
+            connection.graceful_shutdown();
+        }
+        let registry = registry.clone();
+        let open_streams = open_streams.clone();
+        let stream = async move {
+            let _open_streams = open_streams;
+            if overloaded {
+                let mut response = service_unavailable();
+                registry.options.error_pages.apply(&mut response);
+                registry.options.add_server_header(&mut response);
+                let _ = write_response(response, &mut respond).await;
+            } else {
+                handle_stream(registry, request, respond, info).await;
+            }
+        };
+        // keep the connection span for requests on this stream
+        tokio::spawn(stream.in_current_span());
+    }
+}
+
+async fn handle_stream(
+    registry: Arc<ServerRegistry>,
+    request: http::Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+    info: ConnectionInfo,
+) {
+    let read = tokio::time::timeout(
+        registry.options.body_read_timeout,
+        read_request(request, &registry.options),
+    )
+    .await;
+    let mut response = match read {
+        Ok(Ok(mut request)) => {
+            info.apply(&mut request);
+            registry.serve_request(request).await
+        }
+        Ok(Err(mut response)) => {
+            registry.options.error_pages.apply(&mut response);
+            response
+        }
+        Err(_) => {
+            let mut response = Response::new(StatusCode::REQUEST_TIMEOUT);
+            registry.options.error_pages.apply(&mut response);
+            response
+        }
+    };
+    registry.options.add_server_header(&mut response);
+    let _ = write_response(response, &mut respond).await;
+}
+
+/// Converts an h2 request into our `Request`, reading the whole body.
+async fn read_request(
+    request: http::Request<RecvStream>,
+    options: &ServerOptions,
+) -> Result<Request, Response> {
+    let (parts, mut body_stream) = request.into_parts();
+
+    let verb = HttpVerb::from_method(parts.method.as_str())
+        .ok_or_else(|| Response::new(StatusCode::NOT_IMPLEMENTED))?;
+    // the size of the headers is limited by the connection settings
+    if parts.headers.len() > options.max_header_count {
+        return Err(Response::new(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE));
+    }
+
+    let mut headers = HeaderMap::new();
+    for (name, value) in parts.headers.iter() {
+        if let Ok(value) = value.to_str() {
+            headers.append(name.as_str().to_lowercase(), value);
+        }
+    }
+    // handlers expect a host header like in http/1.1
+    if let Some(authority) = parts.uri.authority() {
+        if !headers.contains_key("host") {
+            headers.insert("host", authority.to_string());
+        }
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = body_stream.data().await {
+        let chunk = chunk.map_err(|_| Response::new(StatusCode::BAD_REQUEST))?;
+        let _ = body_stream.flow_control().release_capacity(chunk.len());
+        if body.len() + chunk.len() > options.max_body_size {
+            return Err(Response::new(StatusCode::PAYLOAD_TOO_LARGE));
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    let raw_path = parts.uri.path();
+    let path = decode_path(raw_path).ok_or_else(|| Response::new(StatusCode::BAD_REQUEST))?;
+
+    Ok(Request {
+        verb,
+        version: HttpVersion::Http2,
+        path,
+        raw_path: raw_path.to_string(),
+        query: crate::parse_query(parts.uri.query().unwrap_or("")),
+        raw_query: parts.uri.query().unwrap_or("").to_string(),
+        headers,
+        body: String::from_utf8_lossy(&body).to_string(),
+        body_raw: body,
+        ..Default::default()
+    })
+}
+
+async fn write_response(
+    mut response: Response,
+    respond: &mut SendResponse<Bytes>,
+) -> Result<(), h2::Error> {
+    response.fill_default_headers();
+
+    let mut builder = http::Response::builder().status(response.status.as_u16());
+    for (name, value) in response.headers.iter() {
+        let lowercase = name.to_lowercase();
+        if CONNECTION_HEADERS.contains(&lowercase.as_str()) {
+            continue;
+        }
+        builder = builder.header(lowercase, value);
+    }
+    for cookie in response.cookies.iter() {
+        builder = builder.header("set-cookie", cookie.to_string());
+    }
+    if let Some(length) = response.body.len() {
+        if response.header("Content-Length").is_none() {
+            builder = builder.header("content-length", length);
+        }
+    }
+    let head = match builder.body(()) {
+        Ok(head) => head,
+        // invalid header names or values from a handler
+        Err(_) => http::Response::builder().status(500).body(()).unwrap(),
+    };
+
+    match response.body {
+        Body::Full(body) if body.is_empty() => {
+            respond.send_response(head, true)?;
+        }
+        Body::Full(body) => {
+            let mut send = respond.send_response(head, false)?;
+            send_data(&mut send, body).await?;
+            send.send_data(Bytes::new(), true)?;
+        }
+        Body::Stream(mut body) | Body::Sized(mut body, _) => {
+            let mut send = respond.send_response(head, false)?;
+            while let Some(chunk) = body.next().await {
+                send_data(&mut send, chunk).await?;
+            }
+            send.send_data(Bytes::new(), true)?;
+        }
+    }
+    Ok(())
+}
+
+/// Sends `data` as fast as the client's flow control window allows.
+///
+/// h2 buffers whatever is sent past the window, so without waiting a slow
+/// or stalled client would make us hold a whole file or an endless event
+/// stream in memory. The next chunk is only pulled from the body once this
+/// one is sent.
+async fn send_data(send: &mut SendStream<Bytes>, mut data: Bytes) -> Result<(), h2::Error> {
+    while !data.is_empty() {
+        send.reserve_capacity(data.len());
+        let granted = match std::future::poll_fn(|cx| send.poll_capacity(cx)).await {
+            Some(granted) => granted?,
+            // the client reset the stream
+            None => return Err(h2::Reason::CANCEL.into()),
+        };
+        if granted == 0 {
+            continue;
+        }
+        let piece = data.split_to(granted.min(data.len()));
+        send.send_data(piece, false)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ConnectionInfo, Server};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    const CHUNK_SIZE: usize = 16 * 1024;
+    const CHUNKS: usize = 256;
+
+    #[tokio::test]
+    async fn streams_bodies_only_as_fast_as_the_client_reads() {
+        let pulled = Arc::new(AtomicUsize::new(0));
+        let counter = pulled.clone();
+        let mut server = Server::new(0);
+        server.get(String::from("big"), move |_| {
+            let counter = counter.clone();
+            async move {
+                let chunks = futures_util::stream::iter(0..CHUNKS).map(move |_| {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    Bytes::from(vec![b'x'; CHUNK_SIZE])
+                });
+                Response::stream(chunks)
+            }
+        });
+        let registry = Arc::new(server.registry);
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let (_shutdown, shutdown_signal) = tokio::sync::watch::channel(false);
+        let context = ConnectionContext {
+            shutdown: shutdown_signal,
+            overloaded: false,
+            info: ConnectionInfo::default(),
+        };
+        tokio::spawn(serve_h2(registry, server_io, context));
+
+        let (mut client, connection) = h2::client::Builder::new()
+            .initial_window_size(CHUNK_SIZE as u32)
+            .handshake::<_, Bytes>(client_io)
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+        let request = http::Request::get("http://localhost/big").body(()).unwrap();
+        let (response, _) = client.send_request(request, true).unwrap();
+        let response = response.await.unwrap();
+        assert_eq!(response.status(), 200);
+
+        // the client doesn't read, so little more than its window was pulled
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let pulled_while_stalled = pulled.load(Ordering::SeqCst);
+        assert!(
+            pulled_while_stalled <= 4,
+            "pulled {} chunks",
+            pulled_while_stalled
+        );
+
+        // reading releases the window and the rest follows
+        let mut body = response.into_body();
+        let mut received = 0;
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.unwrap();
+            received += chunk.len();
+            body.flow_control().release_capacity(chunk.len()).unwrap();
+        }
+        assert_eq!(received, CHUNKS * CHUNK_SIZE);
+    }
+}
@@ -0,0 +1,992 @@
+//! A small async HTTP/1.1 client for calling other services from
+//! handlers, also used by `Server::proxy`.
+//!
+//! ```ignore
+//! let client = Client::new();
+//! let response = client
+//!     .post("http://127.0.0.1:9000/users")
+//!     .json(&new_user)
+//!     .send()
+//!     .await?;
+//! let user: User = response.json().await?;
+//! ```
+//!
+//! Connections are kept open and reused for later requests to the same
+//! host, `https://` urls aren't supported.
+
+use crate::{HeaderMap, HttpVerb, StatusCode};
+use bytes::{Bytes, BytesMut};
+use futures_util::{Stream, StreamExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+/// How long connecting and waiting for the response head may take by
+/// default.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest response head accepted from a server.
+const MAX_RESPONSE_HEAD_SIZE: usize = 64 * 1024;
+
+/// Idle connections kept per host by default.
+const DEFAULT_MAX_IDLE_CONNECTIONS: usize = 8;
+
+/// How long an idle connection is kept, below the 5 second keep-alive
+/// timeout this server and many others close idle connections after.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(4);
+
+/// Why a request failed.
+#[derive(Debug, thiserror::Error)]
+pub enum ClientError {
+    #[error("invalid url {0}")]
+    InvalidUrl(String),
+    #[error("failed to connect: {0}")]
+    Connect(io::Error),
+    #[error("timed out waiting for the server")]
+    Timeout,
+    #[error("invalid response: {0}")]
+    InvalidResponse(String),
+    #[error("invalid json body: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Where a request goes, parsed from a url like
+/// `http://127.0.0.1:9000/users?page=2`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct Url {
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    /// path and query, starts with `/`
+    pub(crate) target: String,
+}
+impl Url {
+    pub(crate) fn parse(url: &str) -> Option<Url> {
+        let rest = url.strip_prefix("http://")?;
+        let (authority, target) = match rest.find(['/', '?']) {
+            Some(index) => rest.split_at(index),
+            None => (rest, ""),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            // a bracketed ipv6 address without a port also contains ':'
+            Some((host, port)) if !port.ends_with(']') => (host, port.parse().ok()?),
+            _ => (authority, 80),
+        };
+        if host.is_empty() {
+            return None;
+        }
+        let target = if target.starts_with('/') {
+            target.to_string()
+        } else {
+            format!("/{}", target)
+        };
+        Some(Url {
+            host: host.to_string(),
+            port,
+            target,
+        })
+    }
+
+    /// `Host` header value.
+    pub(crate) fn authority(&self) -> String {
+        if self.port == 80 {
+            self.host.clone()
+        } else {
+            format!("{}:{}", self.host, self.port)
+        }
+    }
+
+    fn socket_addr(&self) -> String {
+        let host = self.host.trim_start_matches('[').trim_end_matches(']');
+        if host.contains(':') {
+            format!("[{}]:{}", host, self.port)
+        } else {
+            format!("{}:{}", host, self.port)
+        }
+    }
+}
+
+/// A connection and when it was last used.
+type IdleConnection = (BufReader<TcpStream>, Instant);
+
+/// Idle keep-alive connections by host, shared by the clones of a
+/// `Client`.
+#[derive(Debug)]
+struct Pool {
+    max_idle: usize,
+    idle: Mutex<HashMap<String, Vec<IdleConnection>>>,
+}
+impl Pool {
+    fn new(max_idle: usize) -> Pool {
+        Pool {
+            max_idle,
+            idle: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The most recently used idle connection to `host`.
+    fn take(&self, host: &str) -> Option<BufReader<TcpStream>> {
+        let mut idle = self.idle.lock().unwrap();
+        let connections = idle.get_mut(host)?;
+        connections.retain(|(_, since)| since.elapsed() < IDLE_TIMEOUT);
+        connections.pop().map(|(connection, _)| connection)
+    }
+
+    /// Keeps a connection whose response was read completely for reuse.
+    fn put(&self, host: &str, connection: BufReader<TcpStream>) {
+        let mut idle = self.idle.lock().unwrap();
+        let connections = idle.entry(host.to_string()).or_default();
+        connections.retain(|(_, since)| since.elapsed() < IDLE_TIMEOUT);
+        if connections.len() < self.max_idle {
+            connections.push((connection, Instant::now()));
+        }
+    }
+}
+
+/// Sends requests to other http servers. Clones share their idle
+/// connections.
+#[derive(Debug, Clone)]
+pub struct Client {
+    timeout: Duration,
+    pool: Arc<Pool>,
+}
+impl Default for Client {
+    fn default() -> Self {
+        Client {
+            timeout: DEFAULT_TIMEOUT,
+            pool: Arc::new(Pool::new(DEFAULT_MAX_IDLE_CONNECTIONS)),
+        }
+    }
+}
+impl Client {
+    pub fn new() -> Client {
+        Client::default()
+    }
+
+    /// How long connecting and waiting for the response head may take,
+    /// 30 seconds by default. Reading the body isn't limited.
+    pub fn with_timeout(mut self, timeout: Duration) -> Client {
+        self.timeout = timeout;
+        self
+    }
+
+    /// How many idle connections are kept open per host for later
+    /// requests, 8 by default. With 0 every connection is closed after its
+    /// response.
+    pub fn with_max_idle_connections(mut self, max_idle: usize) -> Client {
+        self.pool = Arc::new(Pool::new(max_idle));
+        self
+    }
+
+    pub fn request(&self, verb: HttpVerb, url: &str) -> ClientRequest {
+        ClientRequest {
+            client: self.clone(),
+            verb,
+            url: url.to_string(),
+            headers: HeaderMap::new(),
+            body: Bytes::new(),
+        }
+    }
+
+    pub fn get(&self, url: &str) -> ClientRequest {
+        self.request(HttpVerb::GET, url)
+    }
+
+    pub fn head(&self, url: &str) -> ClientRequest {
+        self.request(HttpVerb::HEAD, url)
+    }
+
+    pub fn post(&self, url: &str) -> ClientRequest {
+        self.request(HttpVerb::POST, url)
+    }
+
+    pub fn put(&self, url: &str) -> ClientRequest {
+        self.request(HttpVerb::PUT, url)
+    }
+
+    pub fn patch(&self, url: &str) -> ClientRequest {
+        self.request(HttpVerb::PATCH, url)
+    }
+
+    pub fn delete(&self, url: &str) -> ClientRequest {
+        self.request(HttpVerb::DELETE, url)
+    }
+}
+
+/// A request being built by a `Client`, sent with `send`.
+pub struct ClientRequest {
+    client: Client,
+    verb: HttpVerb,
+    url: String,
+    headers: HeaderMap,
+    body: Bytes,
+}
+impl ClientRequest {
+    /// Adds a header, keeping earlier ones with the same name.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> ClientRequest {
+        self.headers.append(name, value);
+        self
+    }
+
+    /// Adds every header of `headers`.
+    pub fn headers(mut self, headers: HeaderMap) -> ClientRequest {
+        self.headers.extend(headers);
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Bytes>) -> ClientRequest {
+        self.body = body.into();
+        self
+    }
+
+    /// Sends `value` serialized as a JSON body.
+    pub fn json<T: Serialize + ?Sized>(self, value: &T) -> Result<ClientRequest, ClientError> {
+        let body = serde_json::to_vec(value)?;
+        Ok(self.header("Content-Type", "application/json").body(body))
+    }
+
+    /// Sends the request and reads the response head, the body is read
+    /// from the `ClientResponse` as it's needed.
+    ///
+    /// `Host`, `Content-Length` and `Connection` are set by the client.
+    pub async fn send(self) -> Result<ClientResponse, ClientError> {
+        let url = Url::parse(&self.url).ok_or_else(|| ClientError::InvalidUrl(self.url.clone()))?;
+        let timeout = self.client.timeout;
+        tokio::time::timeout(timeout, self.send_to(url))
+            .await
+            .unwrap_or(Err(ClientError::Timeout))
+    }
+
+    async fn send_to(self, url: Url) -> Result<ClientResponse, ClientError> {
+        let head = self.head(&url);
+        let host = url.socket_addr();
+        if let Some(mut connection) = self.client.pool.take(&host) {
+            // the server may have closed the idle connection in the
+            // meantime. When writing fails it never got the request and
+            // it's sent again on a new one. When the connection closes
+            // after the whole request was written the server may have
+            // handled it already, so only idempotent requests are resent.
+            match self.write_request(&mut connection, &head).await {
+                Ok(()) => match wait_for_response(&mut connection).await {
+                    Ok(()) => return self.read_response(connection, &host).await,
+                    Err(e) if is_stale_connection(&e) && is_idempotent(&self.verb) => {}
+                    Err(e) => return Err(response_error(e)),
+                },
+                Err(e) if is_stale_connection(&e) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        let stream = TcpStream::connect(&host)
+            .await
+            .map_err(ClientError::Connect)?;
+        let mut connection = BufReader::new(stream);
+        self.write_request(&mut connection, &head).await?;
+        wait_for_response(&mut connection)
+            .await
+            .map_err(response_error)?;
+        self.read_response(connection, &host).await
+    }
+
+    /// The request line and headers.
+    fn head(&self, url: &Url) -> String {
+        let mut head = format!("{} {} HTTP/1.1\r\n", self.verb, url.target);
+        for (name, value) in self.headers.iter() {
+            let set_by_client = ["host", "content-length", "connection", "transfer-encoding"]
+                .iter()
+                .any(|header| name.eq_ignore_ascii_case(header));
+            if !set_by_client {
+                head.push_str(&format!("{}: {}\r\n", name, value));
+            }
+        }
+        head.push_str(&format!("host: {}\r\n", url.authority()));
+        let sends_body = matches!(self.verb, HttpVerb::POST | HttpVerb::PUT | HttpVerb::PATCH);
+        if sends_body || !self.body.is_empty() {
+            head.push_str(&format!("content-length: {}\r\n", self.body.len()));
+        }
+        if self.client.pool.max_idle == 0 {
+            head.push_str("connection: close\r\n");
+        }
+        head.push_str("\r\n");
+        head
+    }
+
+    /// Sends the request on `connection`.
+    async fn write_request(
+        &self,
+        connection: &mut BufReader<TcpStream>,
+        head: &str,
+    ) -> io::Result<()> {
+        let stream = connection.get_mut();
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(&self.body).await?;
+        stream.flush().await
+    }
+
+    /// Reads the response head of a request sent with `write_request`.
+    async fn read_response(
+        &self,
+        mut connection: BufReader<TcpStream>,
+        host: &str,
+    ) -> Result<ClientResponse, ClientError> {
+        let (status, headers, keep_alive) = read_response_head(&mut connection).await?;
+        let reuse = (keep_alive && self.client.pool.max_idle > 0)
+            .then(|| (self.client.pool.clone(), host.to_string()));
+
+        let has_body =
+            self.verb != HttpVerb::HEAD && !matches!(status.as_u16(), 100..=199 | 204 | 304);
+        let body: BodyStream = if has_body {
+            Box::pin(body_stream(connection, BodyFraming::of(&headers), reuse))
+        } else {
+            if let Some((pool, host)) = reuse {
+                pool.put(&host, connection);
+            }
+            Box::pin(futures_util::stream::empty())
+        };
+        Ok(ClientResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+/// Waits for the first byte of the response. Fails with `UnexpectedEof`
+/// when the server closed the connection without answering.
+async fn wait_for_response(connection: &mut BufReader<TcpStream>) -> io::Result<()> {
+    if connection.fill_buf().await?.is_empty() {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(())
+}
+
+/// The error for a connection that failed before the response started.
+fn response_error(error: io::Error) -> ClientError {
+    if error.kind() == io::ErrorKind::UnexpectedEof {
+        ClientError::InvalidResponse(String::from("server closed the connection"))
+    } else {
+        error.into()
+    }
+}
+
+/// Whether sending `verb` twice has the same effect as sending it once, so
+/// it can be resent when the connection closed before the response.
+fn is_idempotent(verb: &HttpVerb) -> bool {
+    matches!(
+        verb,
+        HttpVerb::GET | HttpVerb::HEAD | HttpVerb::OPTIONS | HttpVerb::PUT | HttpVerb::DELETE
+    )
+}
+
+/// Whether sending on a pooled connection failed because the server closed
+/// it while it was idle, before any of the response arrived.
+fn is_stale_connection(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+    )
+}
+
+type BodyStream = Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>;
+
+/// A response whose head has been read. The body is read with `bytes`,
+/// `text`, `json` or `into_stream`.
+pub struct ClientResponse {
+    pub status: StatusCode,
+    /// headers as the server sent them
+    pub headers: HeaderMap,
+    body: BodyStream,
+}
+impl ClientResponse {
+    /// Looks up a header by name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&String> {
+        self.headers.get(name)
+    }
+
+    /// Reads the whole body.
+    pub async fn bytes(self) -> Result<Bytes, ClientError> {
+        let mut body = BytesMut::new();
+        let mut stream = self.body;
+        while let Some(chunk) = stream.next().await {
+            body.extend_from_slice(&chunk?);
+        }
+        Ok(body.freeze())
+    }
+
+    /// Reads the whole body as text, replacing invalid utf-8.
+    pub async fn text(self) -> Result<String, ClientError> {
+        let body = self.bytes().await?;
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+
+    /// Reads the whole body as JSON.
+    pub async fn json<T: DeserializeOwned>(self) -> Result<T, ClientError> {
+        let body = self.bytes().await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// The body as it arrives, with chunked framing removed.
+    pub fn into_stream(self) -> impl Stream<Item = io::Result<Bytes>> + Send {
+        self.body
+    }
+}
+impl std::fmt::Debug for ClientResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientResponse")
+            .field("status", &self.status)
+            .field("headers", &self.headers)
+            .finish()
+    }
+}
+
+/// Reads the status line and headers of a response, and whether the
+/// server keeps the connection open after it.
+async fn read_response_head(
+    reader: &mut BufReader<TcpStream>,
+) -> Result<(StatusCode, HeaderMap, bool), ClientError> {
+    let invalid = |message: &str| ClientError::InvalidResponse(message.to_string());
+
+    let mut line = String::new();
+    let mut head_size = 0;
+    let mut status = None;
+    let mut http_1_1 = false;
+    let mut headers = HeaderMap::new();
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line).await?;
+        if read == 0 {
+            return Err(invalid("server closed the connection"));
+        }
+        head_size += read;
+        if head_size > MAX_RESPONSE_HEAD_SIZE {
+            return Err(invalid("response head too large"));
+        }
+        let trimmed = line.trim_end_matches(['\r', '\n']);
+
+        let Some(code) = status else {
+            // HTTP/1.1 200 OK
+            let code = trimmed
+                .strip_prefix("HTTP/1.")
+                .and_then(|rest| rest.split(' ').nth(1))
+                .and_then(|code| code.parse::<u16>().ok())
+                .ok_or_else(|| invalid("invalid status line"))?;
+            status = Some(code);
+            http_1_1 = trimmed.starts_with("HTTP/1.1 ");
+            continue;
+        };
+        if trimmed.is_empty() {
+            // skip interim responses like 100 Continue
+            if (100..200).contains(&code) {
+                status = None;
+                headers.clear();
+                continue;
+            }
+            let close = headers
+                .get_joined("connection")
+                .is_some_and(|connection| connection.to_ascii_lowercase().contains("close"));
+            return Ok((StatusCode::from_u16(code), headers, http_1_1 && !close));
+        }
+        if let Some((name, value)) = trimmed.split_once(':') {
+            headers.append(name.trim(), value.trim());
+        }
+    }
+}
+
+/// How the end of a response body is found.
+enum BodyFraming {
+    Length(u64),
+    /// with the bytes left of the current chunk
+    Chunked(u64),
+    UntilClose,
+}
+impl BodyFraming {
+    fn of(headers: &HeaderMap) -> BodyFraming {
+        let chunked = headers
+            .get_joined("transfer-encoding")
+            .is_some_and(|encoding| encoding.to_ascii_lowercase().contains("chunked"));
+        if chunked {
+            return BodyFraming::Chunked(0);
+        }
+        match headers
+            .get("content-length")
+            .and_then(|length| length.trim().parse().ok())
+        {
+            Some(length) => BodyFraming::Length(length),
+            None => BodyFraming::UntilClose,
+        }
+    }
+}
+
+/// Streams a body as it arrives, removing chunked framing. With `reuse`
+/// the connection goes back to the pool once the body was read to its end.
+fn body_stream(
+    reader: BufReader<TcpStream>,
+    framing: BodyFraming,
+    reuse: Option<(Arc<Pool>, String)>,
+) -> impl Stream<Item = io::Result<Bytes>> + Send {
+    // connections whose body ends when they close can't be reused
+    let reuse = reuse.filter(|_| !matches!(framing, BodyFraming::UntilClose));
+    let release = move |reader| {
+        if let Some((pool, host)) = &reuse {
+            pool.put(host, reader);
+        }
+    };
+    futures_util::stream::unfold(Some((reader, framing, release)), |state| async move {
+        let (mut reader, framing, release) = state?;
+        let result = match framing {
+            BodyFraming::Length(0) => {
+                release(reader);
+                return None;
+            }
+            BodyFraming::Length(remaining) => {
+                read_some(&mut reader, remaining).await.and_then(|chunk| {
+                    if chunk.is_empty() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "body shorter than its content-length",
+                        ));
+                    }
+                    let remaining = remaining - chunk.len() as u64;
+                    Ok((chunk, BodyFraming::Length(remaining)))
+                })
+            }
+            BodyFraming::UntilClose => read_some(&mut reader, u64::MAX)
+                .await
+                .map(|chunk| (chunk, BodyFraming::UntilClose)),
+            BodyFraming::Chunked(remaining) => read_chunk(&mut reader, remaining)
+                .await
+                .map(|(chunk, remaining)| (chunk, BodyFraming::Chunked(remaining))),
+        };
+        match result {
+            Ok((chunk, BodyFraming::Chunked(_))) if chunk.is_empty() => {
+                release(reader);
+                None
+            }
+            Ok((chunk, _)) if chunk.is_empty() => None,
+            Ok((chunk, framing)) => Some((Ok(chunk), Some((reader, framing, release)))),
+            // nothing more can be read after an error
+            Err(e) => Some((Err(e), None)),
+        }
+    })
+}
+
+/// Reads whatever is available, up to `limit` bytes. Empty at the end.
+async fn read_some<R: AsyncRead + Unpin>(reader: &mut R, limit: u64) -> io::Result<Bytes> {
+    let mut buffer = BytesMut::with_capacity(8192);
+    let mut limited = reader.take(limit.min(64 * 1024));
+    limited.read_buf(&mut buffer).await?;
+    Ok(buffer.freeze())
+}
+
+/// Reads the next piece of a chunked body with `remaining` bytes left of
+/// the current chunk, and what's left after it. Empty after the last chunk
+/// and the trailers after it.
+///
+/// Chunks are read in pieces like other bodies, the size comes from the
+/// server and a huge one mustn't be allocated up front.
+async fn read_chunk<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    mut remaining: u64,
+) -> io::Result<(Bytes, u64)> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    if remaining == 0 {
+        // chunk extensions after ';' are ignored
+        let line = read_limited_line(reader).await?;
+        let size = line.split(';').next().unwrap_or("").trim();
+        // only hex digits, from_str_radix would also take a sign
+        if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid("invalid chunk size"));
+        }
+        remaining = u64::from_str_radix(size, 16).map_err(|_| invalid("chunk too large"))?;
+        if remaining == 0 {
+            // trailers end with an empty line
+            let mut trailers_size = 0;
+            loop {
+                let line = read_limited_line(reader).await?;
+                trailers_size += line.len();
+                if trailers_size > MAX_RESPONSE_HEAD_SIZE {
+                    return Err(invalid("trailers too large"));
+                }
+                if line.trim().is_empty() {
+                    return Ok((Bytes::new(), 0));
+                }
+            }
+        }
+    }
+
+    let piece = read_some(reader, remaining).await?;
+    if piece.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "body ended inside a chunk",
+        ));
+    }
+    remaining -= piece.len() as u64;
+    if remaining == 0 {
+        // the crlf after the chunk data
+        let mut crlf = [0; 2];
+        reader.read_exact(&mut crlf).await?;
+        if &crlf != b"\r\n" {
+            return Err(invalid("missing crlf after chunk"));
+        }
+    }
+    Ok((piece, remaining))
+}
+
+/// Reads a line of chunked framing, which can't be longer than a response
+/// head. Empty when the connection closed.
+async fn read_limited_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    let read = reader
+        .take(MAX_RESPONSE_HEAD_SIZE as u64)
+        .read_line(&mut line)
+        .await?;
+    if read > 0 && !line.ends_with('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "chunk line too long",
+        ));
+    }
+    Ok(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::net::TcpListener;
+
+    /// What a `scripted_server` does after reading a request.
+    #[derive(Clone, Copy)]
+    enum Answer {
+        /// a 200 and the connection stays open
+        KeepAlive,
+        /// a 200, then the connection is closed like by an idle timeout
+        ThenClose,
+        /// garbage instead of a response
+        Invalid,
+        /// the connection is closed without a response
+        Hangup,
+    }
+
+    /// A server answering the nth request it reads with `answers[n]`, and
+    /// counting the requests and connections it got.
+    async fn scripted_server(answers: Vec<Answer>) -> (String, Arc<AtomicUsize>, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let connections = Arc::new(AtomicUsize::new(0));
+        let (counted_requests, counted_connections) = (requests.clone(), connections.clone());
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                counted_connections.fetch_add(1, Ordering::SeqCst);
+                let requests = counted_requests.clone();
+                let answers = answers.clone();
+                tokio::spawn(async move {
+                    let mut reader = BufReader::new(socket);
+                    loop {
+                        // the request head, the tests send no bodies
+                        let mut line = String::new();
+                        loop {
+                            line.clear();
+                            match reader.read_line(&mut line).await {
+                                Ok(0) | Err(_) => return,
+                                Ok(_) if line == "\r\n" => break,
+                                Ok(_) => {}
+                            }
+                        }
+                        let n = requests.fetch_add(1, Ordering::SeqCst);
+                        let answer = answers.get(n).copied().unwrap_or(Answer::KeepAlive);
+                        let response: &[u8] = match answer {
+                            Answer::KeepAlive | Answer::ThenClose => {
+                                b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\nok"
+                            }
+                            Answer::Invalid => b"garbage\r\n\r\n",
+                            Answer::Hangup => b"",
+                        };
+                        let _ = reader.get_mut().write_all(response).await;
+                        if !matches!(answer, Answer::KeepAlive) {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        (format!("http://{}/", addr), requests, connections)
+    }
+
+    #[tokio::test]
+    async fn reuses_idle_connections() {
+        let (url, requests, connections) = scripted_server(vec![]).await;
+        let client = Client::new();
+        for _ in 0..3 {
+            let response = client.get(&url).send().await.unwrap();
+            assert_eq!(response.text().await.unwrap(), "ok");
+        }
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn retries_on_a_new_connection_when_idle_one_was_closed() {
+        let (url, requests, connections) = scripted_server(vec![Answer::ThenClose]).await;
+        let client = Client::new();
+        client
+            .get(&url)
+            .send()
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        // let the close arrive before the connection is reused
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "ok");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn does_not_resend_a_post_the_server_may_have_handled() {
+        let (url, requests, connections) =
+            scripted_server(vec![Answer::KeepAlive, Answer::Hangup]).await;
+        let client = Client::new();
+        client
+            .get(&url)
+            .send()
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        let result = client.post(&url).body("once").send().await;
+        assert!(result.is_err());
+        // the POST reached the server exactly once
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn resends_an_idempotent_request_the_server_hung_up_on() {
+        let (url, requests, connections) =
+            scripted_server(vec![Answer::KeepAlive, Answer::Hangup]).await;
+        let client = Client::new();
+        client
+            .get(&url)
+            .send()
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        let response = client.put(&url).send().await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "ok");
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_after_the_response_started() {
+        let (url, requests, connections) =
+            scripted_server(vec![Answer::KeepAlive, Answer::Invalid]).await;
+        let client = Client::new();
+        client
+            .get(&url)
+            .send()
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        let result = client.post(&url).body("once").send().await;
+        assert!(matches!(result, Err(ClientError::InvalidResponse(_))));
+        // the POST reached the server exactly once
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_after_a_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counted = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((socket, _)) = listener.accept().await {
+                let counted = counted.clone();
+                tokio::spawn(async move {
+                    let mut reader = BufReader::new(socket);
+                    let mut line = String::new();
+                    loop {
+                        line.clear();
+                        match reader.read_line(&mut line).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(_) if line == "\r\n" => {
+                                // answer the first request, then hang
+                                if counted.fetch_add(1, Ordering::SeqCst) == 0 {
+                                    let _ = reader
+                                        .get_mut()
+                                        .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\n\r\n")
+                                        .await;
+                                }
+                            }
+                            Ok(_) => {}
+                        }
+                    }
+                });
+            }
+        });
+        let client = Client::new().with_timeout(Duration::from_millis(200));
+        client.get(&url).send().await.unwrap();
+        let result = client.post(&url).send().await;
+        assert!(matches!(result, Err(ClientError::Timeout)));
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+    }
+
+    /// Decodes a whole chunked body, the way `body_stream` reads it.
+    async fn decode_chunked(mut raw: &[u8]) -> io::Result<Vec<u8>> {
+        let mut body = Vec::new();
+        let mut remaining = 0;
+        loop {
+            let (piece, left) = read_chunk(&mut raw, remaining).await?;
+            if piece.is_empty() {
+                return Ok(body);
+            }
+            body.extend_from_slice(&piece);
+            remaining = left;
+        }
+    }
+
+    #[tokio::test]
+    async fn decodes_chunked_body_with_trailers() {
+        let raw = b"5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nx-trailer: 1\r\n\r\n";
+        assert_eq!(decode_chunked(raw).await.unwrap(), b"hello world");
+    }
+
+    #[tokio::test]
+    async fn reads_huge_chunks_in_pieces() {
+        // the size is only trusted as far as the data actually arrives
+        let raw = b"ffffffffffff\r\nhello";
+        let (piece, remaining) = read_chunk(&mut &raw[..], 0).await.unwrap();
+        assert_eq!(&piece[..], b"hello");
+        assert_eq!(remaining, 0xffffffffffff - 5);
+        let error = decode_chunked(raw).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_chunk_sizes() {
+        for raw in [
+            &b"+5\r\nhello\r\n0\r\n\r\n"[..],
+            b"-5\r\nhello\r\n0\r\n\r\n",
+            b"zz\r\nhello\r\n0\r\n\r\n",
+            b"\r\nhello\r\n0\r\n\r\n",
+            // more than fits in a u64
+            b"10000000000000000\r\nhello\r\n0\r\n\r\n",
+        ] {
+            let error = decode_chunked(raw).await.unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{:?}", raw);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_endless_chunk_lines() {
+        let mut raw = b"5;".to_vec();
+        raw.resize(MAX_RESPONSE_HEAD_SIZE * 2, b'a');
+        let error = decode_chunked(&raw).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn rejects_chunk_without_crlf() {
+        let error = decode_chunked(b"5\r\nhelloXX0\r\n\r\n").await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn parses_urls() {
+        let url = Url::parse("http://example.com:8080/a/b?c=d").unwrap();
+        assert_eq!(
+            (url.host.as_str(), url.port, url.target.as_str()),
+            ("example.com", 8080, "/a/b?c=d")
+        );
+        assert_eq!(url.authority(), "example.com:8080");
+
+        let url = Url::parse("http://example.com?q").unwrap();
+        assert_eq!((url.port, url.target.as_str()), (80, "/?q"));
+        assert_eq!(url.authority(), "example.com");
+
+        let url = Url::parse("http://[::1]:9000/").unwrap();
+        assert_eq!(url.socket_addr(), "[::1]:9000");
+        assert_eq!(
+            Url::parse("http://[::1]/").unwrap().socket_addr(),
+            "[::1]:80"
+        );
+
+        assert!(Url::parse("https://example.com/").is_none());
+        assert!(Url::parse("http:///path").is_none());
+        assert!(Url::parse("http://example.com:port/").is_none());
+    }
+
+    /// A server writing `response` to the first connection after reading
+    /// the request head, then closing it.
+    async fn one_shot_server(response: &'static [u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut reader = BufReader::new(socket);
+            let mut line = String::new();
+            while reader.read_line(&mut line).await.unwrap() > 0 && !line.ends_with("\r\n\r\n") {}
+            reader.get_mut().write_all(response).await.unwrap();
+        });
+        format!("http://{}/", addr)
+    }
+
+    #[tokio::test]
+    async fn reads_chunked_responses() {
+        let url = one_shot_server(
+            b"HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n\
+              4\r\nwiki\r\n5\r\npedia\r\n0\r\n\r\n",
+        )
+        .await;
+        let response = Client::new().get(&url).send().await.unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "wikipedia");
+    }
+
+    #[tokio::test]
+    async fn reads_responses_until_close_without_length() {
+        let url = one_shot_server(b"HTTP/1.0 200 OK\r\n\r\nuntil close").await;
+        let response = Client::new().get(&url).send().await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "until close");
+    }
+
+    #[tokio::test]
+    async fn skips_interim_responses() {
+        let url = one_shot_server(
+            b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 201 Created\r\ncontent-length: 2\r\n\r\nok",
+        )
+        .await;
+        let response = Client::new().post(&url).body("x").send().await.unwrap();
+        assert_eq!(response.status, StatusCode::CREATED);
+        assert_eq!(response.text().await.unwrap(), "ok");
+    }
+
+    #[tokio::test]
+    async fn fails_on_truncated_bodies() {
+        let url = one_shot_server(b"HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\nshort").await;
+        let response = Client::new().get(&url).send().await.unwrap();
+        assert!(matches!(response.bytes().await, Err(ClientError::Io(_))));
+    }
+}
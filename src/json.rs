@@ -0,0 +1,62 @@
+use crate::{IntoResponse, Request, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// A JSON body, deserialized from a request with `Request::json` or
+/// serialized into a response when returned from a handler.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct Json<T>(pub T);
+
+/// Returns true for `application/json` and `application/*+json` media types,
+/// ignoring parameters like `charset`.
+pub(crate) fn is_json_content_type(content_type: &str) -> bool {
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_lowercase();
+    media_type == "application/json"
+        || (media_type.starts_with("application/") && media_type.ends_with("+json"))
+}
+
+impl Request {
+    /// Deserializes the body as JSON.
+    ///
+    /// Fails with a 415 response when the request isn't marked as JSON and
+    /// with a 400 response describing the problem when the body doesn't
+    /// deserialize into `T`.
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T, Response> {
+        let is_json = self
+            .headers
+            .get("content-type")
+            .is_some_and(|content_type| is_json_content_type(content_type));
+        if !is_json {
+            return Err(Response::new(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+                .with_body("expected a Content-Type of application/json"));
+        }
+
+        serde_json::from_slice(&self.body_raw).map_err(|e| {
+            Response::new(StatusCode::BAD_REQUEST).with_body(format!("invalid json body: {}", e))
+        })
+    }
+}
+
+impl Response {
+    /// 200 response with `value` serialized as the JSON body.
+    /// Responds with a 500 if `value` can't be serialized.
+    pub fn json<T: Serialize + ?Sized>(value: &T) -> Response {
+        match serde_json::to_vec(value) {
+            Ok(body) => Response::new(StatusCode::OK)
+                .with_header("Content-Type", "application/json")
+                .with_body(body),
+            Err(e) => Response::new(StatusCode::INTERNAL_SERVER_ERROR)
+                .with_body(format!("failed to serialize json: {}", e)),
+        }
+    }
+}
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        Response::json(&self.0)
+    }
+}
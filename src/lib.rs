@@ -1,20 +1,158 @@
-use nom::AsBytes;
+use bytes::BytesMut;
+use futures_util::FutureExt;
+use std::any::Any;
+use std::any::TypeId;
 use std::collections::HashMap;
+use std::future::Future;
 use std::io;
-use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::panic::AssertUnwindSafe;
+use std::path::Component;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::RwLock;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use tokio::io::AsyncRead;
 use tokio::io::AsyncReadExt;
-use tokio::io::AsyncWriteExt;
-use tokio::net::TcpListener;
-use tokio::net::TcpStream;
+use tokio::io::AsyncSeekExt;
+use tokio::io::AsyncWrite;
+use tokio::sync::watch;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tracing::{debug, error, field, info, info_span, trace, warn, Instrument, Span};
 
-const MAX_REQUEST_SIZE: usize = 102400;
+use compression::{compress_response, decompress_request};
+use connection::{BufferPool, Connection};
+use http2::serve_h2;
+use listing::directory_listing;
+use middleware::into_middleware;
+use precompressed::{serve_embedded_precompressed, serve_precompressed};
+use proxy::UpstreamGroup;
+use rewrite::apply_rewrites;
+mod access_log;
+mod auth;
+mod cache;
+mod circuit_breaker;
+mod compression;
+mod config;
+mod connection;
+mod cookie;
+mod date;
+mod embed;
+mod error_pages;
+mod etag;
+mod extensions;
+mod extract;
+mod forwarded;
+mod headers;
+mod health;
+mod http2;
+pub mod http_client;
+mod json;
+mod listener;
+mod listing;
+mod middleware;
+mod mime;
+mod multipart;
+mod negotiate;
+mod precompressed;
+mod proxy;
+mod range;
+mod ratelimit;
+mod response;
+mod response_cache;
+mod rewrite;
+mod router;
+mod service;
+mod shutdown;
+mod sse;
+mod static_auth;
+#[cfg(feature = "templates")]
+mod templates;
+mod testing;
+mod tls;
+mod url;
+mod vhost;
+use access_log::AccessLogEntry;
+pub use access_log::{AccessLog, LogFormat};
+pub use auth::{
+    basic_auth, jwt_auth, parse_basic_auth, parse_bearer_token, verify_jwt, JwtClaims, JwtError,
+};
+use cache::glob_match;
+pub use cache::CachePolicy;
+pub use circuit_breaker::{CircuitBreaker, CircuitState, CircuitStats};
+pub use compression::{choose_encoding, parse_accept_encoding, ContentEncoding};
+pub use config::{
+    CompressionConfig, LoggingConfig, RewriteConfig, ServerConfig, StaticMountConfig, TcpConfig,
+    TimeoutConfig,
+};
+pub use cookie::{parse_cookies, Cookie, SameSite};
+pub use embed::EmbeddedFiles;
+pub use error_pages::ErrorPages;
+pub use etag::{etag_matches, etag_matches_strong, file_etag};
+pub use extensions::Extensions;
+pub use extract::{extract, ExtractHandler, FromRequest, Path, Query, State};
+use forwarded::resolve_client_ip;
+pub use forwarded::IpCidr;
+pub use headers::HeaderMap;
+use health::{into_health_check, run_health_checks};
+pub use health::{HealthCheck, HealthChecks};
+pub use json::Json;
+use listener::{Accept, BoundListener};
+pub use listener::{Listener, ListenerConfig};
+pub use middleware::{Middleware, Next};
+pub use mime::{builtin_mime_type, MimeTypes};
+pub use multipart::{multipart_boundary, parse_multipart, Part};
+pub use negotiate::{negotiate, parse_accept, MediaRange, Negotiated};
+pub use proxy::{LoadBalancing, UpstreamHealthCheck};
+pub use range::{parse_range, parse_ranges, ByteRange};
+pub use ratelimit::{rate_limit, RateLimit};
+pub use response::*;
+pub use response_cache::{ResponseCache, ResponseCacheStats};
+pub use rewrite::{RewriteAction, RewritePattern, RewriteRule};
+use router::match_route;
+pub use router::{Route, RouteTree, Router};
+pub use service::{BoxError, NextService, ServerService};
+pub use shutdown::shutdown_signal;
+pub use sse::{Event, Sse};
+pub use static_auth::StaticAuthorizer;
+pub use testing::{TestClient, TestRequest, TestResponse};
+pub use url::{decode_path, parse_query, percent_decode, percent_encode};
+pub use vhost::VirtualHost;
 
-#[derive(Debug, Default, Eq, PartialEq, Hash, Clone)]
+const DEFAULT_MAX_REQUEST_LINE_SIZE: usize = 8 * 1024;
+const DEFAULT_MAX_HEADER_SIZE: usize = 64 * 1024;
+const DEFAULT_MAX_HEADER_COUNT: usize = 100;
+const DEFAULT_MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
+const DEFAULT_COMPRESSION_MIN_SIZE: usize = 1024;
+/// largest static file read into memory at once, larger files are streamed
+const MAX_BUFFERED_FILE_SIZE: usize = 1024 * 1024;
+/// size of the chunks streamed static files are read in
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+const DEFAULT_KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_HEADER_READ_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_BODY_READ_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_WRITE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Boxed future returned by every registered handler.
+pub type HandlerFuture = Pin<Box<dyn Future<Output = Response> + Send>>;
+
+/// Type-erased async endpoint handler stored in the registry.
+pub type Handler = Arc<dyn Fn(Request) -> HandlerFuture + Send + Sync>;
+
+/// Shared application state, keyed by type.
+pub type StateMap = HashMap<TypeId, Arc<dyn Any + Send + Sync>>;
+
+#[derive(Debug, Default, Eq, PartialEq, Hash, Clone, PartialOrd, Ord)]
 pub enum HttpVerb {
     #[default]
     GET,
     POST,
     PUT,
+    PATCH,
     DELETE,
     HEAD,
     OPTIONS,
@@ -22,355 +160,2911 @@ pub enum HttpVerb {
     CONNECT,
 }
 
+impl HttpVerb {
+    /// Parses a request method, which is case sensitive.
+    pub fn from_method(method: &str) -> Option<HttpVerb> {
+        match method {
+            "GET" => Some(HttpVerb::GET),
+            "POST" => Some(HttpVerb::POST),
+            "PUT" => Some(HttpVerb::PUT),
+            "PATCH" => Some(HttpVerb::PATCH),
+            "DELETE" => Some(HttpVerb::DELETE),
+            "HEAD" => Some(HttpVerb::HEAD),
+            "OPTIONS" => Some(HttpVerb::OPTIONS),
+            "TRACE" => Some(HttpVerb::TRACE),
+            "CONNECT" => Some(HttpVerb::CONNECT),
+            _ => None,
+        }
+    }
+
+    /// Method name as sent on the request line.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HttpVerb::GET => "GET",
+            HttpVerb::POST => "POST",
+            HttpVerb::PUT => "PUT",
+            HttpVerb::PATCH => "PATCH",
+            HttpVerb::DELETE => "DELETE",
+            HttpVerb::HEAD => "HEAD",
+            HttpVerb::OPTIONS => "OPTIONS",
+            HttpVerb::TRACE => "TRACE",
+            HttpVerb::CONNECT => "CONNECT",
+        }
+    }
+}
+impl std::fmt::Display for HttpVerb {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Protocol version a request was sent with.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub enum HttpVersion {
+    Http10,
+    #[default]
+    Http11,
+    Http2,
+}
+impl HttpVersion {
+    /// Parses the version of an HTTP/1 request line. Later 1.x versions
+    /// are treated as 1.1 since they have to stay compatible with it.
+    pub fn from_request_line(version: &str) -> Option<HttpVersion> {
+        match version.strip_prefix("HTTP/1.")?.as_bytes() {
+            b"0" => Some(HttpVersion::Http10),
+            [minor] if minor.is_ascii_digit() => Some(HttpVersion::Http11),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HttpVersion::Http10 => "HTTP/1.0",
+            HttpVersion::Http11 => "HTTP/1.1",
+            HttpVersion::Http2 => "HTTP/2",
+        }
+    }
+}
+impl std::fmt::Display for HttpVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Whether a request came in over plain tcp or tls.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Scheme {
+    #[default]
+    Http,
+    Https,
+}
+impl Scheme {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scheme::Http => "http",
+            Scheme::Https => "https",
+        }
+    }
+}
+impl std::fmt::Display for Scheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Hash, Clone)]
 pub struct EndpointKey {
     verb: HttpVerb,
     path: String,
 }
 
-#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+/// Wraps a handler function into the type stored in the registry.
+pub(crate) fn into_handler<F, Fut>(handler: F) -> Handler
+where
+    F: Fn(Request) -> Fut + Send + Sync + 'static,
+    Fut: Future + Send + 'static,
+    Fut::Output: IntoResponse,
+{
+    Arc::new(move |request| {
+        let future = handler(request);
+        Box::pin(async move { future.await.into_response() })
+    })
+}
+
+/// Makes sure a route path starts with a `/`.
+pub(crate) fn normalize_path(path: String) -> String {
+    if path.starts_with('/') {
+        path
+    } else {
+        format!("/{}", path)
+    }
+}
+
+/// A registered route handler and the middleware that only applies to it.
+#[derive(Clone)]
+pub struct Endpoint {
+    handler: Handler,
+    middleware: Arc<Vec<Middleware>>,
+}
+impl Endpoint {
+    pub(crate) fn new(handler: Handler) -> Endpoint {
+        Endpoint {
+            handler,
+            middleware: Arc::new(Vec::new()),
+        }
+    }
+
+    /// Adds a middleware that only runs for this route, after the global
+    /// middleware.
+    pub fn with<F, Fut>(&mut self, middleware: F) -> &mut Endpoint
+    where
+        F: Fn(Request, Next) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        Arc::make_mut(&mut self.middleware).push(into_middleware(middleware));
+        self
+    }
+
+    /// Runs the request through this route's middleware and handler.
+    fn call(&self, request: Request) -> HandlerFuture {
+        Next::new(self.middleware.clone(), self.handler.clone()).run(request)
+    }
+}
+
+/// Which symbolic links inside a static directory are followed.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SymlinkPolicy {
+    /// paths going through a symlink are refused with a 403
+    Deny,
+    /// links are followed as long as they point inside the directory
+    #[default]
+    WithinRoot,
+    /// links are followed wherever they point
+    AllowAll,
+}
+
+#[derive(Debug, Default, Eq, PartialEq, Hash, Clone)]
 pub struct StaticDirectoryEntry {
+    /// empty for embedded files
     pub directory: String,
+    /// files compiled into the binary, served instead of `directory`
+    pub embedded: Option<Arc<EmbeddedFiles>>,
     pub allow_upload: bool,
+    /// replace or create files with PUT
+    pub allow_put: bool,
+    /// remove files with DELETE
+    pub allow_delete: bool,
+    /// create missing parent directories of uploaded files
+    pub create_directories: bool,
+    /// largest file POST and PUT may store, in bytes
+    pub max_upload_size: Option<usize>,
+    /// extensions like `.png` and media types like `image/*` uploads may
+    /// have, anything goes when empty
+    pub upload_types: Vec<String>,
+    /// cache policies by glob, the first matching glob wins
+    pub cache_rules: Vec<(String, CachePolicy)>,
+    /// cache policy for files no rule matches
+    pub default_cache: Option<CachePolicy>,
+    /// generate an index page when a directory is requested
+    pub list_directories: bool,
+    /// serve `index.html` for paths that don't exist, for single-page apps
+    pub spa_fallback: bool,
+    /// serve `.br` and `.gz` copies of files to clients accepting them
+    pub precompressed: bool,
+    /// serve files and directories whose name starts with a dot
+    pub allow_dotfiles: bool,
+    /// globs of paths that are never served, see `ignore`
+    pub ignore_rules: Vec<String>,
+    /// which symlinks inside the directory are followed
+    pub symlinks: SymlinkPolicy,
+    /// checks requests before they are served, see `authorize`
+    pub authorizer: Option<StaticAuthorizer>,
+}
+impl StaticDirectoryEntry {
+    /// Serves the directory's `index.html` instead of a 404 for any path
+    /// that isn't a file, so client-side routes of single-page apps load.
+    pub fn spa_fallback(&mut self, enabled: bool) -> &mut Self {
+        self.spa_fallback = enabled;
+        self
+    }
+
+    /// Serves `style.css.br` or `style.css.gz` in place of `style.css`,
+    /// when the copy exists and the client accepts that encoding, so
+    /// static files don't have to be compressed on every request.
+    pub fn precompressed(&mut self, enabled: bool) -> &mut Self {
+        self.precompressed = enabled;
+        self
+    }
+
+    /// Serves files and directories whose name starts with a dot, like
+    /// `.env` or `.git/`. They get a 404 by default, except for
+    /// `.well-known`.
+    pub fn allow_dotfiles(&mut self, enabled: bool) -> &mut Self {
+        self.allow_dotfiles = enabled;
+        self
+    }
+
+    /// Never serves paths matching `pattern`, like `*.secret` or
+    /// `private/*`, nor anything inside a matching directory. They get a
+    /// 404, are left out of directory listings and can't be uploaded.
+    /// Can be called several times.
+    pub fn ignore(&mut self, pattern: impl Into<String>) -> &mut Self {
+        self.ignore_rules.push(pattern.into());
+        self
+    }
+
+    /// Whether a path relative to the directory is hidden by the dotfile
+    /// or ignore rules, checking every directory on the way.
+    fn is_ignored(&self, path: &str) -> bool {
+        let path = path.trim_matches('/');
+        let mut end = 0;
+        for segment in path.split('/') {
+            end += segment.len();
+            let prefix = &path[..end];
+            end += 1;
+            if segment.starts_with('.')
+                && !self.allow_dotfiles
+                && !matches!(segment, "." | ".well-known")
+            {
+                return true;
+            }
+            let matches_rule = self
+                .ignore_rules
+                .iter()
+                .any(|pattern| glob_match(pattern.trim_end_matches('/'), prefix));
+            if matches_rule {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Sets which symbolic links inside the directory are followed, by
+    /// default only those pointing inside it. The directory itself may
+    /// always be a link.
+    pub fn symlinks(&mut self, policy: SymlinkPolicy) -> &mut Self {
+        self.symlinks = policy;
+        self
+    }
+
+    /// Turns generated directory listings on or off.
+    pub fn list_directories(&mut self, enabled: bool) -> &mut Self {
+        self.list_directories = enabled;
+        self
+    }
+
+    /// Lets PUT requests store their body at the requested path, answering
+    /// 201 when the file is new and 200 when it replaced one.
+    ///
+    /// Writes honour `If-Match` and `If-Unmodified-Since`, so clients can
+    /// send the ETag they got to not overwrite someone else's changes.
+    pub fn allow_put(&mut self, enabled: bool) -> &mut Self {
+        self.allow_put = enabled;
+        self
+    }
+
+    /// Creates missing directories for POST and PUT uploads, like `a/` for
+    /// `a/b.txt`. Without it those uploads get a 409.
+    pub fn create_directories(&mut self, enabled: bool) -> &mut Self {
+        self.create_directories = enabled;
+        self
+    }
+
+    /// Rejects uploaded files larger than `size` bytes with a 413. Every
+    /// request body is also held to `Server::set_max_body_size`.
+    pub fn max_upload_size(&mut self, size: usize) -> &mut Self {
+        self.max_upload_size = Some(size);
+        self
+    }
+
+    /// Only accepts uploads of files matching `pattern`, either an extension
+    /// like `.png` or a media type like `image/png` or `image/*`, and
+    /// rejects the rest with a 415. Can be called several times.
+    ///
+    /// Media types are checked against the type the file will be served
+    /// with, going by its extension, since the `Content-Type` a client
+    /// sends proves nothing.
+    pub fn allow_upload_type(&mut self, pattern: impl Into<String>) -> &mut Self {
+        self.upload_types.push(pattern.into().to_lowercase());
+        self
+    }
+
+    /// Lets DELETE requests remove the file at the requested path,
+    /// answering 204.
+    pub fn allow_delete(&mut self, enabled: bool) -> &mut Self {
+        self.allow_delete = enabled;
+        self
+    }
+
+    /// Sets the `Cache-Control` policy for files matching `pattern`,
+    /// like `*.css` or `assets/*`.
+    pub fn cache(&mut self, pattern: impl Into<String>, policy: CachePolicy) -> &mut Self {
+        self.cache_rules.push((pattern.into(), policy));
+        self
+    }
+
+    /// Sets the `Cache-Control` policy for files no other rule matches.
+    pub fn default_cache(&mut self, policy: CachePolicy) -> &mut Self {
+        self.default_cache = Some(policy);
+        self
+    }
+
+    /// Checks a file of `size` bytes about to be stored at `file_path`
+    /// against the upload limits.
+    fn check_upload(
+        &self,
+        file_path: &str,
+        size: usize,
+        mime_types: &MimeTypes,
+    ) -> Result<(), Response> {
+        // multipart file names aren't part of the requested path
+        let relative_path = file_path
+            .strip_prefix(self.directory.trim_end_matches('/'))
+            .unwrap_or(file_path);
+        if self.is_ignored(relative_path) {
+            return Err(Response::new(StatusCode::FORBIDDEN));
+        }
+        if self.max_upload_size.is_some_and(|max| size > max) {
+            return Err(Response::new(StatusCode::PAYLOAD_TOO_LARGE));
+        }
+        if self.upload_types.is_empty() {
+            return Ok(());
+        }
+        let file_name = file_path.rsplit('/').next().unwrap_or(file_path);
+        let extension = file_name
+            .rsplit_once('.')
+            .map(|(_, extension)| extension.to_lowercase());
+        let media_type = mime_types
+            .for_path(file_path)
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim()
+            .to_lowercase();
+        let allowed = self.upload_types.iter().any(|pattern| {
+            if let Some(allowed_extension) = pattern.strip_prefix('.') {
+                extension.as_deref() == Some(allowed_extension)
+            } else if let Some(kind) = pattern.strip_suffix("/*") {
+                media_type.split('/').next() == Some(kind)
+            } else {
+                *pattern == media_type
+            }
+        });
+        if allowed {
+            Ok(())
+        } else {
+            Err(Response::new(StatusCode::UNSUPPORTED_MEDIA_TYPE))
+        }
+    }
+
+    /// Finds the cache policy for a path relative to the directory.
+    fn cache_policy(&self, path: &str) -> Option<&CachePolicy> {
+        self.cache_rules
+            .iter()
+            .find(|(pattern, _)| glob_match(pattern, path))
+            .map(|(_, policy)| policy)
+            .or(self.default_cache.as_ref())
+    }
 }
 
 #[derive(Debug, Default)]
 pub struct Request {
     pub verb: HttpVerb,
-    /// full requested path
+    pub version: HttpVersion,
+    /// requested path without the query string, percent-decoded
     pub path: String,
-    /// key will always be lowercase
-    pub headers: HashMap<String, String>,
+    /// requested path as the client sent it, still percent-encoded
+    pub raw_path: String,
+    /// decoded query string parameters
+    pub query: HashMap<String, String>,
+    /// query string as the client sent it, without the `?`, still
+    /// percent-encoded and with repeated keys
+    pub raw_query: String,
+    /// named parameters captured from the route, like `id` in `/users/:id`
+    pub params: HashMap<String, String>,
+    /// names are lowercase when parsed from a connection, lookups ignore
+    /// case either way
+    pub headers: HeaderMap,
+    /// cookies sent in the `Cookie` headers
+    pub cookies: HashMap<String, String>,
     /// body of the request
     pub body: String,
+    /// raw bytes of the body, `body` is the lossy utf-8 version of this
+    pub body_raw: Vec<u8>,
+    /// values added by middleware for later middleware and the handler
+    pub extensions: Extensions,
+    /// address of the client, `None` when serving a socket passed to
+    /// `ServerRegistry::handle_socket`
+    pub remote_addr: Option<SocketAddr>,
+    /// address of the server the client connected to, `None` the same way
+    /// as `remote_addr`
+    pub local_addr: Option<SocketAddr>,
+    /// `Https` for connections accepted by `listen_tls`
+    pub scheme: Scheme,
+    /// client behind the trusted proxies, see `client_ip`
+    client_ip: Option<IpAddr>,
+    /// state registered with `Server::with_state`
+    state: Arc<StateMap>,
+}
+impl Request {
+    /// A request for `target`, a path that may end in a query string, for
+    /// handing to a `ServerService` directly. Other fields can be set
+    /// afterwards.
+    pub fn new(verb: HttpVerb, target: &str) -> Request {
+        let (raw_path, raw_query) = target.split_once('?').unwrap_or((target, ""));
+        Request {
+            verb,
+            path: decode_path(raw_path).unwrap_or_else(|| raw_path.to_string()),
+            raw_path: raw_path.to_string(),
+            query: parse_query(raw_query),
+            raw_query: raw_query.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Returns the shared state of type `T` registered with `Server::with_state`.
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.state
+            .get(&TypeId::of::<T>())
+            .and_then(|state| state.clone().downcast::<T>().ok())
+    }
+
+    /// The ip of the client. When the peer is a trusted proxy, see
+    /// `Server::set_trusted_proxies`, this is the address it forwarded in
+    /// `Forwarded` or `X-Forwarded-For`, otherwise the peer's address.
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.client_ip
+            .or_else(|| self.remote_addr.map(|address| address.ip()))
+    }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct Server {
-    port: u16,
+    addr: SocketAddr,
+    listeners: Vec<Listener>,
     registry: ServerRegistry,
 }
+impl Default for Server {
+    fn default() -> Self {
+        Server::new(0)
+    }
+}
 impl Server {
+    /// Server listening on `port` of the loopback interface only.
+    /// Use `new_with_addr` to be reachable from other machines.
+    /// Port `0` picks a free port, see `bind` to find out which.
     pub fn new(port: u16) -> Server {
+        Server::new_with_addr(SocketAddr::from(([127, 0, 0, 1], port)))
+    }
+
+    /// Server listening on `addr`, like `0.0.0.0:8080` for every ipv4
+    /// interface or `[::]:8080` for ipv6.
+    pub fn new_with_addr(addr: SocketAddr) -> Server {
         Server {
-            port,
+            addr,
+            listeners: Vec::new(),
             registry: ServerRegistry::new(),
         }
     }
 
+    /// Registers shared state that handlers can access with `Request::state`.
+    /// Only one value is kept per type; registering the same type twice
+    /// replaces the previous value.
+    pub fn with_state<T: Send + Sync + 'static>(mut self, state: T) -> Server {
+        Arc::make_mut(&mut self.registry.state).insert(TypeId::of::<T>(), Arc::new(state));
+        self
+    }
+
+    /// Sets how long an idle keep-alive connection waits for the next
+    /// request before it is closed.
+    pub fn set_keep_alive_timeout(&mut self, timeout: Duration) {
+        self.registry.options.keep_alive_timeout = timeout;
+    }
+
+    /// Sets how long a client has to send the request headers before it
+    /// gets a 408.
+    pub fn set_header_read_timeout(&mut self, timeout: Duration) {
+        self.registry.options.header_read_timeout = timeout;
+    }
+
+    /// Sets how long a client has to send the request body before it
+    /// gets a 408.
+    pub fn set_body_read_timeout(&mut self, timeout: Duration) {
+        self.registry.options.body_read_timeout = timeout;
+    }
+
+    /// Sets how long a single write to a client may take before the
+    /// connection is dropped.
+    pub fn set_write_timeout(&mut self, timeout: Duration) {
+        self.registry.options.write_timeout = timeout;
+    }
+
+    /// Sets the largest request body the server accepts, larger ones
+    /// are answered with a `413 Payload Too Large`.
+    pub fn set_max_body_size(&mut self, size: usize) {
+        self.registry.options.max_body_size = size;
+    }
+
+    /// Sets the longest request line, method, target and version, the
+    /// server accepts. Longer ones are answered with a `414 URI Too Long`.
+    pub fn set_max_request_line_size(&mut self, size: usize) {
+        self.registry.options.max_request_line_size = size;
+    }
+
+    /// Sets the largest headers block the server accepts, larger ones are
+    /// answered with a `431 Request Header Fields Too Large`.
+    pub fn set_max_header_size(&mut self, size: usize) {
+        self.registry.options.max_header_size = size;
+    }
+
+    /// Sets how many headers a request may have, requests with more are
+    /// answered with a `431 Request Header Fields Too Large`.
+    pub fn set_max_header_count(&mut self, count: usize) {
+        self.registry.options.max_header_count = count;
+    }
+
+    /// Enables or disables compressing responses based on `Accept-Encoding`.
+    pub fn set_compression(&mut self, enabled: bool) {
+        self.registry.options.compression = enabled;
+    }
+
+    /// Sets the smallest response body that gets compressed.
+    pub fn set_compression_min_size(&mut self, size: usize) {
+        self.registry.options.compression_min_size = size;
+    }
+
+    /// Enables or disables decoding request bodies sent with a `gzip`,
+    /// `deflate` or `br` `Content-Encoding` before they reach handlers,
+    /// on by default. Decoded bodies are held to `set_max_body_size`.
+    pub fn set_request_decompression(&mut self, enabled: bool) {
+        self.registry.options.request_decompression = enabled;
+    }
+
+    /// Sets the content type static files with `extension` are served as,
+    /// replacing the built-in mapping if there is one.
+    pub fn register_mime_type(&mut self, extension: &str, mime_type: &str) {
+        self.registry.mime_types.insert(extension, mime_type);
+    }
+
+    /// Sets how long shutdown waits for in-flight connections to finish.
+    pub fn set_shutdown_timeout(&mut self, timeout: Duration) {
+        self.registry.options.shutdown_timeout = timeout;
+    }
+
+    /// Records every request to `log`.
+    pub fn set_access_log(&mut self, log: AccessLog) {
+        self.registry.access_log = Some(log);
+    }
+
+    /// Limits how many connections are served at once. Further clients
+    /// wait to be accepted, see `set_reject_when_busy` to answer them
+    /// with a 503 instead.
+    pub fn set_max_connections(&mut self, limit: usize) {
+        self.registry.options.max_connections = Some(limit);
+    }
+
+    /// Closes a persistent connection after it served `limit` requests,
+    /// sending `Connection: close` on the last response.
+    /// Idle connections are closed after the keep-alive timeout.
+    pub fn set_max_requests_per_connection(&mut self, limit: usize) {
+        self.registry.options.max_requests_per_connection = Some(limit);
+    }
+
+    /// Responds to connections over the connection limit with a
+    /// `503 Service Unavailable` instead of making them wait.
+    pub fn set_reject_when_busy(&mut self, reject: bool) {
+        self.registry.options.reject_when_busy = reject;
+    }
+
+    /// Trusts the forwarding headers of peers in these ranges, like a
+    /// load balancer in front of the server, so `Request::client_ip`
+    /// returns the client they forwarded for.
+    pub fn set_trusted_proxies(&mut self, proxies: Vec<IpCidr>) {
+        self.registry.options.trusted_proxies = proxies;
+    }
+
+    /// Sets the `Server` header sent with every response that doesn't set
+    /// its own, `None` leaves it out. Defaults to the crate name.
+    pub fn set_server_header(&mut self, server: Option<String>) {
+        self.registry.options.server_header = server;
+    }
+
+    /// Sends these pages with error responses the server or a handler
+    /// produced without a body, like a 404 for a missing file or a 413 for
+    /// a request that's too large. See `ErrorPages`.
+    pub fn set_error_pages(&mut self, pages: ErrorPages) {
+        self.registry.options.error_pages = pages;
+    }
+
+    /// Sets the page sent with error responses of `status` that have no
+    /// body, see `set_error_pages`.
+    pub fn set_error_page(&mut self, status: StatusCode, html: impl Into<bytes::Bytes>) {
+        self.registry.options.error_pages.insert(status, html);
+    }
+
     pub async fn listen(self) -> io::Result<()> {
-        let port = self.port;
-        let listener = TcpListener::bind(format!("127.0.0.1:{port}"))
+        self.listen_with_shutdown(std::future::pending()).await
+    }
+
+    /// Like `listen` but stops once `signal` resolves.
+    ///
+    /// New connections are no longer accepted, idle keep-alive connections
+    /// are closed and in-flight requests get up to the shutdown timeout to
+    /// finish before this returns.
+    pub async fn listen_with_shutdown<F>(self, signal: F) -> io::Result<()>
+    where
+        F: Future<Output = ()>,
+    {
+        let listener = Listener::Tcp(self.addr)
+            .bind(&self.registry.options.listener)
+            .await?;
+        let limiter = self.registry.connection_limiter();
+        Arc::new(self.registry)
+            .serve_listener(listener, signal, limiter)
             .await
-            .unwrap();
+    }
 
-        println!("Server started on port {port}!");
+    /// Like `listen` but serves HTTPS using the PEM encoded certificate
+    /// chain and private key at the given paths.
+    pub async fn listen_tls(self, cert_path: &str, key_path: &str) -> io::Result<()> {
+        self.listen_tls_with_shutdown(cert_path, key_path, std::future::pending())
+            .await
+    }
 
-        loop {
-            match listener.accept().await {
-                Ok((socket, _)) => {
-                    let handler = self.registry.clone();
-                    tokio::spawn(async move {
-                        handler.handle_socket(socket).await;
-                    });
-                }
-                Err(e) => {
-                    println!("failed to accept socket; error = {:?}", e);
-                }
-            }
+    /// Like `listen_tls` but stops once `signal` resolves, the same way as
+    /// `listen_with_shutdown`.
+    pub async fn listen_tls_with_shutdown<F>(
+        self,
+        cert_path: &str,
+        key_path: &str,
+        signal: F,
+    ) -> io::Result<()>
+    where
+        F: Future<Output = ()>,
+    {
+        let listener = Listener::Tls {
+            addr: self.addr,
+            cert_path: cert_path.to_string(),
+            key_path: key_path.to_string(),
+        }
+        .bind(&self.registry.options.listener)
+        .await?;
+        let limiter = self.registry.connection_limiter();
+        Arc::new(self.registry)
+            .serve_listener(listener, signal, limiter)
+            .await
+    }
+
+    /// Like `listen` but accepts connections on a unix domain socket at
+    /// `path`, for running behind a local reverse proxy.
+    #[cfg(unix)]
+    pub async fn listen_unix(self, path: impl AsRef<std::path::Path>) -> io::Result<()> {
+        self.listen_unix_with_shutdown(path, std::future::pending())
+            .await
+    }
+
+    /// Like `listen_unix` but stops once `signal` resolves, the same way as
+    /// `listen_with_shutdown`.
+    #[cfg(unix)]
+    pub async fn listen_unix_with_shutdown<F>(
+        self,
+        path: impl AsRef<std::path::Path>,
+        signal: F,
+    ) -> io::Result<()>
+    where
+        F: Future<Output = ()>,
+    {
+        let listener = Listener::Unix(path.as_ref().to_path_buf())
+            .bind(&self.registry.options.listener)
+            .await?;
+        let limiter = self.registry.connection_limiter();
+        Arc::new(self.registry)
+            .serve_listener(listener, signal, limiter)
+            .await
+    }
+
+    /// Sets the socket options of tcp listeners and the connections they
+    /// accept, like `TCP_NODELAY` or `SO_REUSEPORT`.
+    pub fn set_listener_config(&mut self, config: ListenerConfig) {
+        self.registry.options.listener = config;
+    }
+
+    /// Adds a socket for `listen_all` to accept connections on, like plain
+    /// http on one port and https on another.
+    pub fn add_listener(&mut self, listener: Listener) {
+        self.listeners.push(listener);
+    }
+
+    /// Serves every listener added with `add_listener` at the same time,
+    /// or only the server's own address when none were added.
+    pub async fn listen_all(self) -> io::Result<()> {
+        self.listen_all_with_shutdown(std::future::pending()).await
+    }
+
+    /// Like `listen_all` but stops every listener once `signal` resolves,
+    /// the same way as `listen_with_shutdown`.
+    ///
+    /// All listeners share the same endpoints and connection limit.
+    pub async fn listen_all_with_shutdown<F>(self, signal: F) -> io::Result<()>
+    where
+        F: Future<Output = ()>,
+    {
+        self.bind().await?.serve_with_shutdown(signal).await
+    }
+
+    /// Binds the same listeners as `listen_all` without serving them yet.
+    ///
+    /// Useful with port `0`, where the os picks a free port that can be
+    /// read back with `BoundServer::local_addr` before serving.
+    pub async fn bind(self) -> io::Result<BoundServer> {
+        let listeners = if self.listeners.is_empty() {
+            vec![Listener::Tcp(self.addr)]
+        } else {
+            self.listeners
+        };
+        // bind everything up front so a bad address fails before serving
+        let mut bound = Vec::with_capacity(listeners.len());
+        for listener in &listeners {
+            bound.push(listener.bind(&self.registry.options.listener).await?);
         }
+        Ok(BoundServer {
+            listeners: bound,
+            registry: Arc::new(self.registry),
+        })
     }
 
     /// Registers a new endpoint with the server.
     /// Consider using `get` instead.
-    pub fn register_endpoint(
+    ///
+    /// Paths can contain named parameters (`/users/:id`) which are available
+    /// in `Request::params`, and may end with `*` to match any suffix.
+    ///
+    /// The handler is an async function (or closure returning a future)
+    /// that is awaited for every matching request. It can return anything
+    /// that implements `IntoResponse`.
+    ///
+    /// Returns the registered endpoint so route specific middleware can be
+    /// added with `with`.
+    pub fn register_endpoint<F, Fut>(
         &mut self,
         verb: HttpVerb,
         path: String,
-        handler: fn(Request) -> String,
-    ) {
-        let mut normalized_path = path;
-        if !normalized_path.starts_with("/") {
-            normalized_path = format!("/{}", normalized_path);
-        }
+        handler: F,
+    ) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
         let endpoint_key = EndpointKey {
             verb,
-            path: normalized_path,
+            path: normalize_path(path),
         };
         self.registry
             .endpoints
-            .insert(endpoint_key, Box::new(handler));
+            .insert(endpoint_key, Endpoint::new(into_handler(handler)))
     }
 
-    pub fn get(&mut self, path: String, handler: fn(Request) -> String) {
-        self.register_endpoint(HttpVerb::GET, path, handler);
+    /// Registers every route of a `Router` under the given path prefix.
+    /// The router's middleware runs before each route's own middleware.
+    pub fn mount(&mut self, prefix: String, router: Router) {
+        for (key, endpoint) in router.into_endpoints(&prefix) {
+            self.registry.endpoints.insert(key, endpoint);
+        }
     }
 
-    pub fn post(&mut self, path: String, handler: fn(Request) -> String) {
-        self.register_endpoint(HttpVerb::POST, path, handler);
+    /// Adds a middleware that runs for every request, in the order they
+    /// were added. It gets the request and a `Next` to continue the chain
+    /// and can return early or change the response.
+    pub fn use_middleware<F, Fut>(&mut self, middleware: F)
+    where
+        F: Fn(Request, Next) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        Arc::make_mut(&mut self.registry.middleware).push(into_middleware(middleware));
     }
 
-    /// Serves a directory of static files at the given endpoint.
-    /// leave the endpoint empty to serve the directory at the root.
-    pub fn serve(&mut self, path: String, directory: String, allow_upload: bool) {
-        if directory.is_empty() {
-            return;
-        }
-        let mut normalized_path = path;
-        if !normalized_path.starts_with("/") {
-            normalized_path = format!("/{}", normalized_path);
-        }
-        self.registry.static_directories.insert(
-            normalized_path,
-            StaticDirectoryEntry {
-                directory,
-                allow_upload,
-            },
-        );
+    /// Requires Basic auth for every request matching `path`, which can use
+    /// the same patterns as routes (`/admin/*`). This covers routes and
+    /// static files alike.
+    pub fn protect<F>(&mut self, path: String, validator: F)
+    where
+        F: Fn(&str, &str) -> bool + Send + Sync + 'static,
+    {
+        let path = normalize_path(path);
+        let auth = basic_auth("Restricted", validator);
+        self.use_middleware(move |request: Request, next: Next| {
+            if match_route(&path, &request.path).is_some() {
+                auth(request, next)
+            } else {
+                next.run(request)
+            }
+        });
     }
 
-    pub fn respond(
-        status: Option<u16>,
-        body: Option<String>,
-        headers: Option<HashMap<String, String>>,
-    ) -> String {
-        let status_code = status.unwrap_or(200);
-        let status_message = match status_code {
-            200 => "OK",
-            201 => "Created",
-            400 => "Bad Request",
-            401 => "Unauthorized",
-            403 => "Forbidden",
-            404 => "Not Found",
-            _ => "Unknown",
-        };
-        let body_string = body.unwrap_or(String::from(""));
+    /// Limits how often each client can make requests. To limit a single
+    /// route add the `rate_limit` middleware to it with `Endpoint::with`.
+    pub fn rate_limit(&mut self, limit: RateLimit) {
+        self.use_middleware(rate_limit(limit));
+    }
 
-        // build headers block
-        let mut header_map = headers.unwrap_or(HashMap::new());
-        if !body_string.is_empty() {
-            // we only add this if they aren't already in the headers
-            header_map
-                .entry(String::from("Content-Type"))
-                .or_insert(String::from("text/plain"));
-            header_map
-                .entry(String::from("Content-Length"))
-                .or_insert(body_string.len().to_string());
-        }
+    /// Registers `/healthz` and `/readyz`, which run the checks added with
+    /// `health_check` and `readiness_check` and answer with a json summary.
+    /// They respond with a 503 when any check fails.
+    pub fn enable_health_checks(&mut self) {
+        let checks = self.registry.health_checks.clone();
+        self.get(String::from("healthz"), move |_request| {
+            let checks = checks.clone();
+            async move { run_health_checks(&checks, |checks| &checks.liveness).await }
+        });
+        let checks = self.registry.health_checks.clone();
+        self.get(String::from("readyz"), move |_request| {
+            let checks = checks.clone();
+            async move { run_health_checks(&checks, |checks| &checks.readiness).await }
+        });
+    }
 
-        let headers_string = header_map
-            .iter()
-            .map(|(k, v)| format!("{}: {}", k, v))
-            .collect::<Vec<String>>()
-            .join("\r\n");
-        let status_code_string = status.unwrap_or(200).to_string();
-        return format!("HTTP/1.1 {status_code_string} {status_message}\r\n{headers_string}\r\n\r\n{body_string}");
+    /// Adds a check to `/healthz`, for problems only a restart fixes.
+    pub fn health_check<F, Fut>(&mut self, name: &str, check: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        let mut checks = self.registry.health_checks.write().unwrap();
+        checks
+            .liveness
+            .push((name.to_string(), into_health_check(check)));
     }
-}
 
-#[derive(Debug, Default, Clone)]
-pub struct ServerRegistry {
-    // map of endpoint to directory
-    pub endpoints: HashMap<EndpointKey, Box<fn(Request) -> String>>,
-    pub static_directories: HashMap<String, StaticDirectoryEntry>,
-}
-impl ServerRegistry {
-    pub fn new() -> ServerRegistry {
-        ServerRegistry {
-            endpoints: HashMap::new(),
-            static_directories: HashMap::new(),
-        }
+    /// Adds a check to `/readyz`, for things the server needs before it can
+    /// take traffic, like a database connection.
+    pub fn readiness_check<F, Fut>(&mut self, name: &str, check: F)
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        let mut checks = self.registry.health_checks.write().unwrap();
+        checks
+            .readiness
+            .push((name.to_string(), into_health_check(check)));
     }
 
-    pub async fn handle_socket(self, mut stream: TcpStream) {
-        let mut buffer = [0u8; MAX_REQUEST_SIZE];
-        stream.read(&mut buffer).await.unwrap();
-        let response = self.handle_request(buffer);
-        stream.write(response.as_bytes()).await.unwrap();
-        stream.flush().await.unwrap();
+    pub fn get<F, Fut>(&mut self, path: String, handler: F) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.register_endpoint(HttpVerb::GET, path, handler)
     }
 
-    fn handle_request(self, stream: [u8; MAX_REQUEST_SIZE]) -> String {
-        // read the request and split it into lines
-        let request_str = String::from_utf8_lossy(&stream);
+    pub fn post<F, Fut>(&mut self, path: String, handler: F) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.register_endpoint(HttpVerb::POST, path, handler)
+    }
 
-        // write request to file
-        // let mut file1 = std::fs::File::create("request.txt").unwrap();
-        // file1.write_all(request_str.as_bytes()).unwrap();
+    pub fn put<F, Fut>(&mut self, path: String, handler: F) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.register_endpoint(HttpVerb::PUT, path, handler)
+    }
 
-        let request_lines: Vec<&str> = request_str.split("\r\n").collect();
+    pub fn patch<F, Fut>(&mut self, path: String, handler: F) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.register_endpoint(HttpVerb::PATCH, path, handler)
+    }
 
-        if request_lines.len() == 0 {
-            return Server::respond(Some(400), None, None);
-        }
+    pub fn delete<F, Fut>(&mut self, path: String, handler: F) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.register_endpoint(HttpVerb::DELETE, path, handler)
+    }
 
-        // parse the first line
-        // ex: GET / HTTP/1.1
-        let first_line = request_lines[0];
-        let first_line_split: Vec<&str> = first_line.split(" ").collect();
+    /// Routes HEAD requests separately, without one they are answered
+    /// by the GET route with the body left out.
+    pub fn head<F, Fut>(&mut self, path: String, handler: F) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.register_endpoint(HttpVerb::HEAD, path, handler)
+    }
 
-        if first_line_split.len() != 3 {
-            return Server::respond(Some(400), None, None);
-        }
+    pub fn options<F, Fut>(&mut self, path: String, handler: F) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.register_endpoint(HttpVerb::OPTIONS, path, handler)
+    }
 
-        let verb = match first_line_split[0] {
-            "GET" => HttpVerb::GET,
-            "POST" => HttpVerb::POST,
-            "PUT" => HttpVerb::PUT,
-            "DELETE" => HttpVerb::DELETE,
-            "HEAD" => HttpVerb::HEAD,
-            "OPTIONS" => HttpVerb::OPTIONS,
-            "TRACE" => HttpVerb::TRACE,
-            "CONNECT" => HttpVerb::CONNECT,
-            _ => HttpVerb::GET,
-        };
-        let requested_path = first_line_split[1];
+    /// Routes every verb of `path` that has no route of its own to
+    /// `handler`, no matter if the other routes are added before or after.
+    pub fn any<F, Fut>(&mut self, path: String, handler: F) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.registry
+            .endpoints
+            .insert_any(normalize_path(path), Endpoint::new(into_handler(handler)))
+    }
 
-        if !requested_path.starts_with("/") {
-            return Server::respond(Some(200), None, None);
-        }
+    /// Registers handlers for several verbs of the same path, see `Route`.
+    pub fn route(&mut self, path: String) -> Route<'_> {
+        Route::new(&mut self.registry.endpoints, path)
+    }
 
-        let requested_path_split: Vec<&str> = requested_path
-            .split("/")
-            // filter out the empty strings
-            // this means // will be treated as /
-            .filter(|s| s.len() > 0)
-            .collect();
+    /// Handles requests that no route or static directory matched, like a
+    /// custom 404 page. Without a fallback they get an empty 404.
+    pub fn fallback<F, Fut>(&mut self, handler: F)
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.registry.fallback = Some(into_handler(handler));
+    }
 
-        // respond with 200 when the path is empty
-        if requested_path_split.len() == 0 {
-            return Server::respond(Some(200), None, None);
-        }
+    /// Permanently redirects GET and HEAD requests for `from` to `to`.
+    pub fn redirect(&mut self, from: String, to: impl Into<String>) -> &mut Endpoint {
+        let to = to.into();
+        self.get(from, move |_| {
+            let to = to.clone();
+            async move { Response::permanent_redirect(to) }
+        })
+    }
 
-        // parse headers
-        let mut headers: HashMap<String, String> = HashMap::new();
-        // for each line after the first
-        let mut i = 1;
-        while i < request_lines.len() {
-            let line = request_lines[i];
-            if line.is_empty() {
-                break;
-            }
-            let line_split: Vec<&str> = line.split(":").collect();
-            if line_split.len() == 2 {
-                headers.insert(
-                    String::from(line_split[0].trim().to_lowercase()),
-                    String::from(line_split[1].trim()),
-                );
-            }
-            i += 1;
-        }
-
-        // parse body
-        let mut body = String::from("");
-        let mut body_raw: &[u8] = &[];
-        i += 1;
-        if i < request_lines.len() {
-            let request_bin = &stream;
-            // find first instance of \r\n\r\n
-            let mut body_start = 0;
-            for j in 0..(request_bin.len() - 3) {
-                if request_bin[j] == '\r' as u8
-                    && request_bin[j + 1] == '\n' as u8
-                    && request_bin[j + 2] == '\r' as u8
-                    && request_bin[j + 3] == '\n' as u8
-                {
-                    body_start = j + 4;
-                    break;
-                }
-            }
+    /// Serves a single file at `path`, like `favicon.ico` or `robots.txt`,
+    /// without mounting the directory it is in. See `Response::file`.
+    pub fn serve_file(&mut self, path: String, file_path: String) -> &mut Endpoint {
+        self.get(path, move |request| {
+            let file_path = file_path.clone();
+            async move { Response::file(&request, &file_path).await }
+        })
+    }
 
-            if body_start > 0 {
-                let content_length = match headers.get("content-length") {
-                    Some(length) => length.parse::<usize>().unwrap_or(0),
-                    None => 0,
-                };
+    /// Serves a directory of static files at the given endpoint.
+    /// leave the endpoint empty to serve the directory at the root.
+    ///
+    /// Returns the entry so caching can be configured, or `None` when no
+    /// directory is given.
+    pub fn serve(
+        &mut self,
+        path: String,
+        directory: String,
+        allow_upload: bool,
+    ) -> Option<&mut StaticDirectoryEntry> {
+        insert_static_directory(
+            &mut self.registry.static_directories,
+            path,
+            directory,
+            allow_upload,
+        )
+    }
 
-                body = String::from_utf8_lossy(
-                    &request_bin[body_start..(body_start + content_length)],
-                )
-                .to_string();
-                body_raw = request_bin[body_start..(body_start + content_length)].as_bytes();
-            }
+    pub fn respond(
+        status: Option<u16>,
+        body: Option<String>,
+        headers: Option<HashMap<String, String>>,
+    ) -> Response {
+        // Content-Type and Content-Length are filled in when the response is
+        // written if they aren't already in the headers
+        Response {
+            status: StatusCode::from_u16(status.unwrap_or(200)),
+            headers: headers.map(HeaderMap::from).unwrap_or_default(),
+            body: body.unwrap_or_default().into(),
+            ..Default::default()
         }
-        println!("body length: {}", body.len());
+    }
+}
 
-        // match endpoints
-        for (key, handler) in self.endpoints.iter() {
-            if key.verb != verb {
-                continue;
-            }
+/// Adds a static directory mount for `Server::serve` and
+/// `VirtualHost::serve`.
+pub(crate) fn insert_static_directory(
+    static_directories: &mut HashMap<String, StaticDirectoryEntry>,
+    path: String,
+    directory: String,
+    allow_upload: bool,
+) -> Option<&mut StaticDirectoryEntry> {
+    if directory.is_empty() {
+        return None;
+    }
+    let entry = StaticDirectoryEntry {
+        directory,
+        allow_upload,
+        ..Default::default()
+    };
+    Some(insert_static_entry(static_directories, path, entry))
+}
 
-            if !key.path.starts_with(requested_path)
-                && !(key.path.ends_with("*")
-                    && requested_path.starts_with(&key.path[..key.path.len() - 1]))
-            {
-                continue;
-            }
+/// Mounts `entry` at `path`, replacing whatever was mounted there.
+pub(crate) fn insert_static_entry(
+    static_directories: &mut HashMap<String, StaticDirectoryEntry>,
+    path: String,
+    entry: StaticDirectoryEntry,
+) -> &mut StaticDirectoryEntry {
+    let mut normalized_path = path;
+    if !normalized_path.starts_with('/') {
+        normalized_path = format!("/{}", normalized_path);
+    }
+    static_directories
+        .entry(normalized_path)
+        .insert_entry(entry)
+        .into_mut()
+}
+
+/// What the accept loop tells a connection about the server's state.
+pub(crate) struct ConnectionContext {
+    /// changes to true when the server starts shutting down
+    pub(crate) shutdown: watch::Receiver<bool>,
+    /// the connection limit is reached, requests get a 503
+    pub(crate) overloaded: bool,
+    pub(crate) info: ConnectionInfo,
+}
+
+/// Where a connection came from, copied onto each of its requests.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct ConnectionInfo {
+    pub(crate) remote_addr: Option<SocketAddr>,
+    pub(crate) local_addr: Option<SocketAddr>,
+    pub(crate) scheme: Scheme,
+}
+impl ConnectionInfo {
+    pub(crate) fn apply(&self, request: &mut Request) {
+        request.remote_addr = self.remote_addr;
+        request.local_addr = self.local_addr;
+        request.scheme = self.scheme;
+    }
+}
+
+/// Mount paths that could serve `path`, longest first. Mounts only match
+/// whole segments, so "/filesystem" isn't inside the "/files" mount.
+fn mount_candidates(path: &str) -> Vec<&str> {
+    let mut candidates = vec![path];
+    for (index, _) in path.rmatch_indices('/') {
+        for candidate in [&path[..index + 1], &path[..index]] {
+            if !candidate.is_empty() && candidates.last() != Some(&candidate) {
+                candidates.push(candidate);
+            }
+        }
+    }
+    candidates
+}
+
+/// Text a panic was started with, for logging.
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+/// Response for requests on connections over the connection limit.
+pub(crate) fn service_unavailable() -> Response {
+    Response::new(StatusCode::SERVICE_UNAVAILABLE).with_header("Retry-After", "1")
+}
+
+/// A `Server` whose listeners are bound but not accepting connections yet,
+/// created with `Server::bind`.
+pub struct BoundServer {
+    listeners: Vec<BoundListener>,
+    registry: Arc<ServerRegistry>,
+}
+impl BoundServer {
+    /// Address of the first tcp listener, with the actual port when the
+    /// server was bound to port `0`.
+    /// `None` when only unix sockets are bound.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.listeners.iter().find_map(BoundListener::local_addr)
+    }
+
+    pub async fn serve(self) -> io::Result<()> {
+        self.serve_with_shutdown(std::future::pending()).await
+    }
+
+    /// Like `serve` but stops every listener once `signal` resolves, the
+    /// same way as `Server::listen_with_shutdown`.
+    pub async fn serve_with_shutdown<F>(self, signal: F) -> io::Result<()>
+    where
+        F: Future<Output = ()>,
+    {
+        let limiter = self.registry.connection_limiter();
+        let (stop_sender, stop) = watch::channel(false);
+        let accept_loops = self.listeners.into_iter().map(|listener| {
+            let mut stop = stop.clone();
+            let signal = async move {
+                let _ = stop.wait_for(|stop| *stop).await;
+            };
+            self.registry
+                .clone()
+                .serve_listener(listener, signal, limiter.clone())
+        });
+        let accept_loops = futures_util::future::try_join_all(accept_loops);
+        tokio::pin!(accept_loops);
+
+        tokio::select! {
+            result = &mut accept_loops => return result.map(|_| ()),
+            _ = signal => {}
+        }
+        let _ = stop_sender.send(true);
+        accept_loops.await.map(|_| ())
+    }
+}
 
-            return handler(Request {
-                verb,
-                path: requested_path.to_string(),
-                headers: headers.clone(),
-                body,
+/// Connection level settings shared by every socket.
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    /// how long an idle persistent connection is kept open
+    pub keep_alive_timeout: Duration,
+    /// most requests served on one connection before it is closed,
+    /// unlimited when `None`
+    pub max_requests_per_connection: Option<usize>,
+    /// how long a client has to send the request line and headers once
+    /// it started sending them
+    pub header_read_timeout: Duration,
+    /// how long a client has to send the whole request body
+    pub body_read_timeout: Duration,
+    /// how long a single write to the client may take
+    pub write_timeout: Duration,
+    /// longest accepted request line, longer ones get a 414
+    pub max_request_line_size: usize,
+    /// largest accepted headers block after the request line, larger
+    /// ones get a 431
+    pub max_header_size: usize,
+    /// most headers in a request, more get a 431
+    pub max_header_count: usize,
+    /// largest accepted request body, larger requests get a 413
+    pub max_body_size: usize,
+    /// compress response bodies when the client sends `Accept-Encoding`
+    pub compression: bool,
+    /// smallest body that is worth compressing
+    pub compression_min_size: usize,
+    /// decode request bodies sent with a `Content-Encoding`
+    pub request_decompression: bool,
+    /// how long shutdown waits for open connections before dropping them
+    pub shutdown_timeout: Duration,
+    /// most connections served at once, unlimited when `None`
+    pub max_connections: Option<usize>,
+    /// answer connections over the limit with a 503 instead of waiting
+    /// to accept them
+    pub reject_when_busy: bool,
+    /// socket options for tcp listeners
+    pub listener: ListenerConfig,
+    /// peers whose `Forwarded` and `X-Forwarded-For` headers are trusted
+    pub trusted_proxies: Vec<IpCidr>,
+    /// value of the `Server` header added to responses that don't have
+    /// one, no header when `None`
+    pub server_header: Option<String>,
+    /// pages sent with error responses that have no body
+    pub error_pages: ErrorPages,
+}
+impl Default for ServerOptions {
+    fn default() -> Self {
+        ServerOptions {
+            keep_alive_timeout: DEFAULT_KEEP_ALIVE_TIMEOUT,
+            max_requests_per_connection: None,
+            header_read_timeout: DEFAULT_HEADER_READ_TIMEOUT,
+            body_read_timeout: DEFAULT_BODY_READ_TIMEOUT,
+            write_timeout: DEFAULT_WRITE_TIMEOUT,
+            max_request_line_size: DEFAULT_MAX_REQUEST_LINE_SIZE,
+            max_header_size: DEFAULT_MAX_HEADER_SIZE,
+            max_header_count: DEFAULT_MAX_HEADER_COUNT,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            compression: true,
+            compression_min_size: DEFAULT_COMPRESSION_MIN_SIZE,
+            request_decompression: true,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            max_connections: None,
+            reject_when_busy: false,
+            listener: ListenerConfig::default(),
+            trusted_proxies: Vec::new(),
+            server_header: Some(String::from(env!("CARGO_PKG_NAME"))),
+            error_pages: ErrorPages::default(),
+        }
+    }
+}
+impl ServerOptions {
+    /// Adds the `Server` header right before a response is written.
+    pub(crate) fn add_server_header(&self, response: &mut Response) {
+        if let Some(server) = &self.server_header {
+            if response.header("Server").is_none() {
+                response.set_header("Server", server.as_str());
+            }
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct ServerRegistry {
+    // map of endpoint to directory
+    pub endpoints: RouteTree,
+    pub static_directories: HashMap<String, StaticDirectoryEntry>,
+    pub state: Arc<StateMap>,
+    pub middleware: Arc<Vec<Middleware>>,
+    pub options: ServerOptions,
+    pub mime_types: MimeTypes,
+    pub access_log: Option<AccessLog>,
+    pub health_checks: Arc<RwLock<HealthChecks>>,
+    /// handles requests no route or static directory matched
+    pub fallback: Option<Handler>,
+    /// routes and static directories of other hostnames, by lowercase
+    /// hostname
+    pub vhosts: HashMap<String, VirtualHost>,
+    /// rules checked before routing, see `Server::add_rewrite`
+    pub rewrites: Vec<RewriteRule>,
+    /// upstreams of every proxied path prefix
+    pub(crate) proxies: HashMap<String, Arc<UpstreamGroup>>,
+    /// read buffers shared by every connection
+    pub(crate) buffer_pool: Arc<BufferPool>,
+}
+impl std::fmt::Debug for ServerRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServerRegistry")
+            .field("endpoints", &self.endpoints)
+            .field("static_directories", &self.static_directories)
+            .field("state", &self.state.len())
+            .field("middleware", &self.middleware.len())
+            .field("options", &self.options)
+            .field("mime_types", &self.mime_types)
+            .field("access_log", &self.access_log)
+            .field("health_checks", &self.health_checks)
+            .field("fallback", &self.fallback.is_some())
+            .field("vhosts", &self.vhosts)
+            .field("rewrites", &self.rewrites)
+            .finish()
+    }
+}
+impl ServerRegistry {
+    pub fn new() -> ServerRegistry {
+        ServerRegistry {
+            endpoints: RouteTree::new(),
+            static_directories: HashMap::new(),
+            state: Arc::new(HashMap::new()),
+            middleware: Arc::new(Vec::new()),
+            options: ServerOptions::default(),
+            mime_types: MimeTypes::default(),
+            access_log: None,
+            health_checks: Arc::new(RwLock::new(HealthChecks::default())),
+            fallback: None,
+            vhosts: HashMap::new(),
+            rewrites: Vec::new(),
+            proxies: HashMap::new(),
+            buffer_pool: Arc::new(BufferPool::default()),
+        }
+    }
+
+    /// Serves requests from a connection until it is closed.
+    /// Works with any transport, like a plain `TcpStream` or a tls stream.
+    ///
+    /// The registry is shared, so serving many connections only clones
+    /// the `Arc`.
+    pub async fn handle_socket<S>(self: Arc<Self>, stream: S)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        // the sender is dropped right away so shutdown never fires
+        let (_, shutdown) = watch::channel(false);
+        let context = ConnectionContext {
+            shutdown,
+            overloaded: false,
+            info: ConnectionInfo::default(),
+        };
+        self.serve_connection(stream, context).await;
+    }
+
+    /// Applies the per connection `ListenerConfig` options to an accepted
+    /// tcp socket.
+    fn configure_socket(&self, socket: &tokio::net::TcpStream) {
+        if let Err(e) = self.options.listener.configure_stream(socket) {
+            warn!(error = ?e, "failed to set socket options");
+        }
+    }
+
+    /// Semaphore enforcing `max_connections`, shared by every listener.
+    fn connection_limiter(&self) -> Option<Arc<Semaphore>> {
+        self.options
+            .max_connections
+            .map(|limit| Arc::new(Semaphore::new(limit)))
+    }
+
+    /// Runs the accept loop of a bound listener until `signal` resolves.
+    async fn serve_listener<F>(
+        self: Arc<Self>,
+        listener: BoundListener,
+        signal: F,
+        limiter: Option<Arc<Semaphore>>,
+    ) -> io::Result<()>
+    where
+        F: Future<Output = ()>,
+    {
+        match listener {
+            BoundListener::Tcp(listener) => {
+                info!(addr = %listener.local_addr()?, "server started");
+
+                self.accept_until(
+                    listener,
+                    signal,
+                    limiter,
+                    |registry, socket, context| async move {
+                        registry.configure_socket(&socket);
+                        registry.serve_connection(socket, context).await;
+                    },
+                )
+                .await
+            }
+            BoundListener::Tls(listener, acceptor) => {
+                info!(addr = %listener.local_addr()?, "server started with tls");
+
+                self.accept_until(
+                    listener,
+                    signal,
+                    limiter,
+                    move |registry, socket, mut context| {
+                        let acceptor = acceptor.clone();
+                        context.info.scheme = Scheme::Https;
+                        async move {
+                            registry.configure_socket(&socket);
+                            match acceptor.accept(socket).await {
+                                Ok(stream) => {
+                                    // speak http/2 when the client picked it with alpn
+                                    let is_h2 = stream.get_ref().1.alpn_protocol() == Some(b"h2");
+                                    if is_h2 {
+                                        serve_h2(registry, stream, context).await;
+                                    } else {
+                                        registry.serve_connection(stream, context).await;
+                                    }
+                                }
+                                Err(e) => warn!(error = ?e, "tls handshake failed"),
+                            }
+                        }
+                    },
+                )
+                .await
+            }
+            #[cfg(unix)]
+            BoundListener::Unix(listener, path) => {
+                info!(path = %path.display(), "server started on unix socket");
+
+                let result = self
+                    .accept_until(
+                        listener,
+                        signal,
+                        limiter,
+                        |registry, socket, context| async move {
+                            registry.serve_connection(socket, context).await;
+                        },
+                    )
+                    .await;
+                let _ = std::fs::remove_file(&path);
+                result
+            }
+        }
+    }
+
+    /// Accepts connections until `signal` resolves, then drains them.
+    async fn accept_until<L, F, C, Fut>(
+        self: Arc<Self>,
+        listener: L,
+        signal: F,
+        limiter: Option<Arc<Semaphore>>,
+        handle_connection: C,
+    ) -> io::Result<()>
+    where
+        L: Accept,
+        F: Future<Output = ()>,
+        C: Fn(Arc<ServerRegistry>, L::Stream, ConnectionContext) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let (shutdown_sender, shutdown) = watch::channel(false);
+        let mut connections = JoinSet::new();
+        let reject_when_busy = self.options.reject_when_busy;
+        tokio::pin!(signal);
+
+        loop {
+            // wait for a free slot before accepting, unless clients over
+            // the limit get a 503 instead
+            let permit = match &limiter {
+                Some(limiter) if !reject_when_busy => tokio::select! {
+                    permit = limiter.clone().acquire_owned() => permit.ok(),
+                    _ = &mut signal => break,
+                },
+                _ => None,
+            };
+
+            tokio::select! {
+                accepted = listener.accept_connection() => match accepted {
+                    Ok((socket, info)) => {
+                        let permit = permit.or_else(|| {
+                            limiter.as_ref()?.clone().try_acquire_owned().ok()
+                        });
+                        let context = ConnectionContext {
+                            shutdown: shutdown.clone(),
+                            overloaded: limiter.is_some() && permit.is_none(),
+                            info,
+                        };
+                        let span = info_span!("connection", remote_addr = field::Empty);
+                        if let Some(remote_addr) = info.remote_addr {
+                            span.record("remote_addr", field::display(remote_addr));
+                        }
+                        let connection = handle_connection(self.clone(), socket, context)
+                            .instrument(span);
+                        connections.spawn(async move {
+                            connection.await;
+                            // frees the slot for the next connection
+                            drop(permit);
+                        });
+                    }
+                    Err(e) => {
+                        error!(error = ?e, "failed to accept socket");
+                    }
+                },
+                // forget finished connections so the set doesn't keep growing
+                Some(_) = connections.join_next(), if !connections.is_empty() => {}
+                _ = &mut signal => break,
+            }
+        }
+
+        drop(listener);
+        info!(connections = connections.len(), "shutting down");
+        let _ = shutdown_sender.send(true);
+        let drained = tokio::time::timeout(self.options.shutdown_timeout, async {
+            while connections.join_next().await.is_some() {}
+        })
+        .await;
+        if drained.is_err() {
+            warn!("shutdown timed out, closing remaining connections");
+            connections.shutdown().await;
+        }
+        Ok(())
+    }
+
+    /// Like `handle_socket` but stops reusing the connection on shutdown
+    /// and answers with a 503 when the server is overloaded.
+    pub(crate) async fn serve_connection<S>(self: Arc<Self>, stream: S, context: ConnectionContext)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let ConnectionContext {
+            mut shutdown,
+            overloaded,
+            info,
+        } = context;
+        let mut connection = Connection::new(stream, self.buffer_pool.clone());
+        let mut served = 0;
+        loop {
+            // wait for the next request on this connection, idle
+            // connections are closed right away on shutdown
+            let read = tokio::select! {
+                read = connection.read_request(&self.options) => read,
+                Ok(()) = shutdown.changed() => break,
+            };
+
+            let (mut response, keep_alive, version) = match read {
+                Ok(Some(_)) if overloaded => {
+                    let mut response = service_unavailable();
+                    self.options.error_pages.apply(&mut response);
+                    (response, false, HttpVersion::Http11)
+                }
+                Ok(Some(mut request)) => {
+                    info.apply(&mut request);
+                    served += 1;
+                    let version = request.version;
+                    let keep_alive = wants_keep_alive(&request)
+                        && self
+                            .options
+                            .max_requests_per_connection
+                            .is_none_or(|max| served < max);
+                    (self.serve_request(request).await, keep_alive, version)
+                }
+                Ok(None) => break,
+                Err(mut response) => {
+                    self.options.error_pages.apply(&mut response);
+                    (response, false, HttpVersion::Http11)
+                }
+            };
+            // HTTP/1.0 has no chunked encoding, so a body of unknown length
+            // ends when the connection closes
+            let chunked = version != HttpVersion::Http10;
+            let keep_alive =
+                keep_alive && !*shutdown.borrow() && (chunked || response.body.len().is_some());
+
+            if !keep_alive {
+                response.set_header("Connection", "close");
+            } else if version == HttpVersion::Http10 {
+                response.set_header("Connection", "keep-alive");
+            }
+            self.options.add_server_header(&mut response);
+            let written = connection
+                .write_response(response, chunked, self.options.write_timeout)
+                .await;
+            if written.is_err() || !keep_alive {
+                break;
+            }
+        }
+    }
+
+    /// Runs a parsed request through routing and the response filters
+    /// that apply to every protocol, like compression.
+    pub(crate) async fn serve_request(self: &Arc<Self>, mut request: Request) -> Response {
+        for cookie_header in request.headers.get_all("cookie") {
+            request.cookies.extend(parse_cookies(cookie_header));
+        }
+        request.client_ip = resolve_client_ip(&request, &self.options.trusted_proxies);
+        let accept_encoding = request.headers.get_joined("accept-encoding");
+        let is_head = request.verb == HttpVerb::HEAD;
+        let started = Instant::now();
+        let span = info_span!(
+            "request",
+            method = %request.verb,
+            path = %request.path,
+            status = field::Empty,
+            latency_ms = field::Empty,
+        );
+        let log_entry = self.access_log.as_ref().map(|_| AccessLogEntry {
+            remote_addr: request.remote_addr,
+            method: request.verb.to_string(),
+            path: request.path.clone(),
+            status: 0,
+            size: None,
+            latency: Duration::ZERO,
+            time: SystemTime::now(),
+        });
+
+        let decompressed = match self.options.request_decompression {
+            true => decompress_request(&mut request, self.options.max_body_size),
+            false => Ok(()),
+        };
+        let mut response = match decompressed.map(|()| apply_rewrites(&self.rewrites, &mut request))
+        {
+            Err(response) => response,
+            Ok(Some(redirect)) => redirect,
+            Ok(None) => self.run_middleware(request, &span).await,
+        };
+        self.options.error_pages.apply(&mut response);
+        if self.options.compression {
+            compress_response(
+                &mut response,
+                accept_encoding.as_deref(),
+                self.options.compression_min_size,
+            );
+        }
+        if is_head {
+            response.strip_body();
+        }
+        let latency = started.elapsed();
+        span.record("status", response.status.as_u16());
+        span.record("latency_ms", latency.as_secs_f64() * 1000.0);
+        span.in_scope(|| debug!("finished request"));
+        if let (Some(access_log), Some(entry)) = (&self.access_log, log_entry) {
+            access_log.record(AccessLogEntry {
+                status: response.status.as_u16(),
+                size: response.body.len(),
+                latency,
+                ..entry
             });
         }
+        response
+    }
+
+    /// Runs a request through the global middleware and routing.
+    async fn run_middleware(self: &Arc<Self>, request: Request, span: &Span) -> Response {
+        // global middleware wraps routing, static files and the 404
+        let registry = self.clone();
+        let endpoint: Handler = Arc::new(move |request| {
+            let registry = registry.clone();
+            Box::pin(async move { registry.handle_request(request).await })
+        });
+        // a panicking handler only fails its own request
+        let result = AssertUnwindSafe(Next::new(self.middleware.clone(), endpoint).run(request))
+            .catch_unwind()
+            .instrument(span.clone())
+            .await;
+        result.unwrap_or_else(|panic| {
+            span.in_scope(|| error!(panic = panic_message(&*panic), "handler panicked"));
+            Response::new(StatusCode::INTERNAL_SERVER_ERROR)
+        })
+    }
+
+    async fn handle_request(&self, request: Request) -> Response {
+        let verb = request.verb.clone();
+        let requested_path = request.path.clone();
+
+        // a 2xx to CONNECT tells the client its tunnel is open, and
+        // tunnels aren't supported
+        if verb == HttpVerb::CONNECT {
+            return Response::new(StatusCode::NOT_IMPLEMENTED);
+        }
+        if !requested_path.starts_with('/') {
+            // only `*` gets here, asking about the server as a whole
+            if verb == HttpVerb::OPTIONS && requested_path == "*" {
+                return Response::new(StatusCode::OK);
+            }
+            return Response::new(StatusCode::BAD_REQUEST);
+        }
+
+        let requested_path_split: Vec<&str> = requested_path
+            .split('/')
+            // filter out the empty strings
+            // this means // will be treated as /
+            .filter(|s| !s.is_empty())
+            .collect();
 
-        // match for static file serving
-        for (path, entry) in self.static_directories.iter() {
-            if !requested_path.starts_with(path) {
-                // println!("path doesn't start with {}", path);
+        // respond with 200 when the path is empty
+        if requested_path_split.is_empty() {
+            return Response::new(StatusCode::OK);
+        }
+
+        // requests for a virtual host only see its routes and files
+        let (endpoints, static_directories, fallback) = match self.vhost_for(&request) {
+            Some(vhost) => (&vhost.endpoints, &vhost.static_directories, &vhost.fallback),
+            None => (&self.endpoints, &self.static_directories, &self.fallback),
+        };
+
+        // match endpoints, on a miss this has the verbs of the routes that
+        // match the path but not the verb, used to respond with 405
+        let mut allowed_verbs = match endpoints.find(&verb, &requested_path) {
+            Ok((endpoint, params)) => {
+                return endpoint
+                    .call(Request {
+                        params,
+                        state: self.state.clone(),
+                        ..request
+                    })
+                    .await;
+            }
+            Err(allowed_verbs) => allowed_verbs,
+        };
+
+        // match for static file serving, the deepest mount first
+        for path in mount_candidates(&requested_path) {
+            let Some(entry) = static_directories.get(path) else {
+                continue;
+            };
+            let relative_path = &requested_path[path.len()..];
+
+            let mut mount_verbs = vec![HttpVerb::GET, HttpVerb::HEAD];
+            // embedded files can't be changed
+            let writable = entry.embedded.is_none();
+            if entry.allow_upload && writable {
+                mount_verbs.push(HttpVerb::POST);
+            }
+            if entry.allow_put && writable {
+                mount_verbs.push(HttpVerb::PUT);
+            }
+            if entry.allow_delete && writable {
+                mount_verbs.push(HttpVerb::DELETE);
+            }
+            if !mount_verbs.contains(&verb) {
+                allowed_verbs.extend(mount_verbs);
+                continue;
+            }
+            // traversal attempts are refused before the ignore rules would
+            // answer them like missing dotfiles
+            if !is_plain_relative_path(relative_path) {
+                trace!(relative_path, "refused static path");
+                return refused_static_path(&verb);
+            }
+            if entry.is_ignored(relative_path) {
+                trace!(relative_path, "ignored static path");
+                continue;
+            }
+
+            if let Some(embedded) = &entry.embedded {
+                trace!(relative_path, "serving embedded file");
+                if let Some(authorizer) = &entry.authorizer {
+                    let embedded_path = relative_path.trim_start_matches('/');
+                    if let Err(response) = authorizer.check(&request, embedded_path).await {
+                        return response;
+                    }
+                }
+                let mut found = embedded
+                    .get(relative_path)
+                    .map(|file| (relative_path, file));
+                if found.is_none() && entry.spa_fallback {
+                    found = embedded.get("index.html").map(|file| ("index.html", file));
+                }
+                let Some((file_path, file)) = found else {
+                    continue;
+                };
+                let file = StaticFile {
+                    source: FileSource::Embedded(file.contents),
+                    length: file.contents.len(),
+                    etag: file.etag.clone(),
+                    modified: None,
+                };
+                let content_type = self.mime_types.for_path(file_path);
+                let cache_policy = entry.cache_policy(file_path);
+                if entry.precompressed {
+                    if let Some(response) = serve_embedded_precompressed(
+                        &request,
+                        embedded,
+                        file_path,
+                        content_type,
+                        cache_policy,
+                    )
+                    .await
+                    {
+                        return response.with_vary("Accept-Encoding");
+                    }
+                }
+                if let Some(mut response) =
+                    file_response(&request, file, content_type, cache_policy).await
+                {
+                    if entry.precompressed {
+                        response.add_vary("Accept-Encoding");
+                    }
+                    return response;
+                }
                 continue;
             }
 
             let dir = entry.directory.clone();
 
-            let file_path = format!("{}{}", dir, &requested_path[path.len()..]);
+            let writes = matches!(verb, HttpVerb::POST | HttpVerb::PUT | HttpVerb::DELETE);
+            let file_path =
+                match resolve_static_path(&dir, relative_path, entry.symlinks, writes).await {
+                    Some(file_path) => file_path,
+                    None => return refused_static_path(&verb),
+                };
+            if let Some(authorizer) = &entry.authorizer {
+                if let Err(response) = authorizer.check(&request, &file_path).await {
+                    return response;
+                }
+            }
+            // browser form uploads wrap the file in a multipart body
+            let is_multipart = verb == HttpVerb::POST
+                && request
+                    .headers
+                    .get("content-type")
+                    .and_then(|content_type| multipart_boundary(content_type))
+                    .is_some();
+            if !is_multipart && matches!(verb, HttpVerb::POST | HttpVerb::PUT | HttpVerb::DELETE) {
+                if let Err(response) = check_write_preconditions(&request, &file_path).await {
+                    return response;
+                }
+            }
+            if matches!(verb, HttpVerb::POST | HttpVerb::PUT) {
+                // multipart files are checked one by one when saved
+                if !is_multipart && !file_path.ends_with('/') {
+                    if let Err(response) =
+                        entry.check_upload(&file_path, request.body_raw.len(), &self.mime_types)
+                    {
+                        return response;
+                    }
+                }
+                if let Err(response) =
+                    prepare_upload_path(&file_path, entry.create_directories).await
+                {
+                    return response;
+                }
+            }
 
-            if verb == HttpVerb::GET {
-                // println!("file path: {}", file_path);
+            if verb == HttpVerb::GET || verb == HttpVerb::HEAD {
+                trace!(file_path, "serving static file");
                 // try to load the file
                 // todo would be cool to cache these files
-                let file_path2 = file_path.clone();
-                let file_contents = std::fs::read_to_string(file_path);
-                match file_contents {
-                    Ok(contents) => {
-                        let file_length = contents.len();
-
-                        let file_type = match file_path2.split(".").last() {
-                            Some("html") => "text/html",
-                            Some("css") => "text/css",
-                            Some("js") => "text/javascript",
-                            Some("png") => "image/png",
-                            _ => "application/octet-stream",
-                        };
-
-                        return Server::respond(
-                            Some(200),
-                            Some(contents),
-                            Some(
-                                [
-                                    (String::from("Content-Type"), file_type.to_string()),
-                                    (String::from("Content-Length"), file_length.to_string()),
-                                ]
-                                .iter()
-                                .cloned()
-                                .collect(),
-                            ),
-                        );
+                let cache_policy = entry.cache_policy(relative_path);
+                let content_type = self.mime_types.for_path(&file_path);
+                if entry.precompressed {
+                    if let Some(response) =
+                        serve_precompressed(&request, &file_path, content_type, cache_policy).await
+                    {
+                        return response.with_vary("Accept-Encoding");
                     }
-                    Err(_) => {
-                        // continue
+                }
+                if let Some(mut response) =
+                    serve_file(&request, &file_path, content_type, cache_policy).await
+                {
+                    if entry.precompressed {
+                        response.add_vary("Accept-Encoding");
+                    }
+                    return response;
+                }
+                if entry.list_directories {
+                    let show_parent =
+                        requested_path.trim_end_matches('/') != path.trim_end_matches('/');
+                    let relative_directory = relative_path.trim_end_matches('/');
+                    let hidden =
+                        |name: &str| entry.is_ignored(&format!("{}/{}", relative_directory, name));
+                    if let Some(response) = directory_listing(
+                        &request,
+                        &file_path,
+                        &request.raw_path,
+                        show_parent,
+                        hidden,
+                    )
+                    .await
+                    {
+                        return response;
+                    }
+                }
+                if entry.spa_fallback {
+                    let index_path = std::path::Path::new(&dir).join("index.html");
+                    let cache_policy = entry.cache_policy("index.html");
+                    if let Some(response) = serve_file(
+                        &request,
+                        &index_path.to_string_lossy(),
+                        self.mime_types.for_path("index.html"),
+                        cache_policy,
+                    )
+                    .await
+                    {
+                        return response;
                     }
                 }
             } else if verb == HttpVerb::POST && entry.allow_upload {
-                let mut file = std::fs::File::create(file_path).unwrap();
-                file.write_all(body_raw.as_bytes()).unwrap();
-                // println!("created file");
-                return Server::respond(Some(201), None, None);
+                if is_multipart {
+                    return save_multipart_upload(
+                        &request,
+                        &file_path,
+                        relative_path,
+                        entry,
+                        &self.mime_types,
+                    )
+                    .await;
+                }
+
+                if file_path.ends_with('/') {
+                    return Response::new(StatusCode::CONFLICT);
+                }
+                if let Err(e) = tokio::fs::write(&file_path, &request.body_raw).await {
+                    warn!(file_path, error = ?e, "failed to save upload");
+                    return Response::new(StatusCode::INTERNAL_SERVER_ERROR);
+                }
+                trace!(file_path, "created file");
+                return with_file_etag(Response::new(StatusCode::CREATED), &file_path).await;
+            } else if verb == HttpVerb::PUT {
+                return put_static_file(&request, &file_path).await;
+            } else if verb == HttpVerb::DELETE {
+                return delete_static_file(&file_path).await;
             }
         }
 
-        return Server::respond(Some(404), None, None);
+        if !allowed_verbs.is_empty() {
+            // every GET route also answers HEAD
+            if allowed_verbs.contains(&HttpVerb::GET) {
+                allowed_verbs.insert(HttpVerb::HEAD);
+            }
+            let allow = allowed_verbs
+                .iter()
+                .map(|verb| verb.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Response::new(StatusCode::METHOD_NOT_ALLOWED).with_header("Allow", allow);
+        }
+
+        if let Some(fallback) = fallback {
+            return fallback(Request {
+                state: self.state.clone(),
+                ..request
+            })
+            .await;
+        }
+
+        Response::new(StatusCode::NOT_FOUND)
+    }
+}
+
+/// Whether the client wants to reuse the connection after this request.
+/// HTTP/1.1 connections persist unless closed, HTTP/1.0 ones only when
+/// the client asks for it.
+fn wants_keep_alive(request: &Request) -> bool {
+    let has_token = |token: &str| {
+        request
+            .headers
+            .get_joined("connection")
+            .is_some_and(|value| {
+                value
+                    .split(',')
+                    .any(|option| option.trim().eq_ignore_ascii_case(token))
+            })
+    };
+    match request.version {
+        HttpVersion::Http10 => has_token("keep-alive"),
+        _ => !has_token("close"),
+    }
+}
+
+/// Whether a decoded path relative to a static mount stays below it going
+/// by its text alone, without `..` segments, backslashes, NUL bytes or
+/// empty segments like in `//etc/passwd`.
+fn is_plain_relative_path(relative_path: &str) -> bool {
+    if relative_path.contains(['\0', '\\']) || relative_path.contains("//") {
+        return false;
+    }
+    // a leading slash would otherwise make the path absolute
+    std::path::Path::new(relative_path.trim_start_matches('/'))
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+}
+
+/// The answer to a static path that leads outside its mount.
+fn refused_static_path(verb: &HttpVerb) -> Response {
+    // a bad upload path is the client's mistake, not a secret
+    if matches!(verb, HttpVerb::POST | HttpVerb::PUT) {
+        Response::new(StatusCode::BAD_REQUEST)
+    } else {
+        Response::new(StatusCode::FORBIDDEN)
+    }
+}
+
+/// Joins a decoded path relative to a static mount onto its directory.
+///
+/// Returns `None` when the path would end up outside the directory, either
+/// through `..` segments or a symlink pointing elsewhere, or when it goes
+/// through a symlink `symlinks` doesn't follow. Paths a request `writes`
+/// to can't be a symlink themselves, whatever it points at.
+async fn resolve_static_path(
+    directory: &str,
+    relative_path: &str,
+    symlinks: SymlinkPolicy,
+    writes: bool,
+) -> Option<String> {
+    if !is_plain_relative_path(relative_path) {
+        return None;
+    }
+    let relative_path = relative_path.trim_start_matches('/');
+    let file_path = format!("{}/{}", directory.trim_end_matches('/'), relative_path);
+    if symlinks == SymlinkPolicy::AllowAll {
+        return Some(file_path);
+    }
+
+    // nothing can escape a directory that doesn't exist
+    let root = match tokio::fs::canonicalize(directory).await {
+        Ok(root) => root,
+        Err(_) => return Some(file_path),
+    };
+    // check every link on the way without following it, a dangling one
+    // would otherwise look like a missing file and let uploads create its
+    // target wherever it points
+    let components: Vec<Component> = std::path::Path::new(relative_path).components().collect();
+    let mut current = std::path::PathBuf::from(directory);
+    for (index, component) in components.iter().enumerate() {
+        current.push(component);
+        let metadata = match tokio::fs::symlink_metadata(&current).await {
+            Ok(metadata) => metadata,
+            // nothing below a missing path exists either
+            Err(_) => break,
+        };
+        if !metadata.is_symlink() {
+            continue;
+        }
+        let is_target = index + 1 == components.len();
+        if symlinks == SymlinkPolicy::Deny || (writes && is_target) {
+            return None;
+        }
+        let resolved = tokio::fs::canonicalize(&current).await.ok()?;
+        if !resolved.starts_with(&root) {
+            return None;
+        }
+    }
+    Some(file_path)
+}
+
+/// Reads a static file and builds its response, see `file_response`.
+/// Returns `None` when the file can't be read.
+async fn serve_file(
+    request: &Request,
+    file_path: &str,
+    content_type: &str,
+    cache_policy: Option<&CachePolicy>,
+) -> Option<Response> {
+    let metadata = tokio::fs::metadata(file_path).await.ok()?;
+    if !metadata.is_file() {
+        return None;
+    }
+    let file = StaticFile {
+        source: FileSource::Disk(file_path),
+        length: metadata.len() as usize,
+        etag: file_etag(&metadata),
+        modified: metadata.modified().ok(),
+    };
+    file_response(request, file, content_type, cache_policy).await
+}
+
+/// Where the bytes of a static file come from.
+enum FileSource<'a> {
+    Disk(&'a str),
+    Embedded(&'static [u8]),
+}
+
+/// A static file about to be sent, from disk or embedded.
+struct StaticFile<'a> {
+    source: FileSource<'a>,
+    length: usize,
+    etag: String,
+    /// unknown for embedded files
+    modified: Option<SystemTime>,
+}
+
+/// Builds the response for a static file, honouring the `Range`,
+/// `If-None-Match` and `If-Modified-Since` headers. Returns `None` when the
+/// file can't be read.
+async fn file_response(
+    request: &Request,
+    file: StaticFile<'_>,
+    content_type: &str,
+    cache_policy: Option<&CachePolicy>,
+) -> Option<Response> {
+    let etag = file.etag;
+    let last_modified = file.modified.map(httpdate::fmt_http_date);
+
+    // If-Modified-Since is only used by clients that don't send an ETag
+    let not_modified = match request.headers.get_joined("if-none-match") {
+        Some(if_none_match) => etag_matches(&if_none_match, &etag),
+        None => request
+            .headers
+            .get("if-modified-since")
+            .and_then(|since| httpdate::parse_http_date(since).ok())
+            .zip(file.modified)
+            // http dates only have whole seconds
+            .is_some_and(|(since, modified)| {
+                modified
+                    .duration_since(since)
+                    .map_or(true, |newer| newer.as_secs() == 0)
+            }),
+    };
+    if not_modified {
+        let mut response = Response::new(StatusCode::NOT_MODIFIED).with_header("ETag", etag);
+        if let Some(last_modified) = last_modified {
+            response.set_header("Last-Modified", last_modified);
+        }
+        if let Some(policy) = cache_policy {
+            response.set_header("Cache-Control", policy.header_value());
+        }
+        return Some(response);
+    }
+
+    let mut response = Response::new(StatusCode::OK)
+        .with_header("Content-Type", content_type)
+        .with_header("Accept-Ranges", "bytes")
+        .with_header("ETag", etag);
+    if let Some(last_modified) = last_modified {
+        response.set_header("Last-Modified", last_modified);
+    }
+    if let Some(policy) = cache_policy {
+        response.set_header("Cache-Control", policy.header_value());
+    }
+
+    let length = file.length;
+    let ranges = request
+        .headers
+        .get("range")
+        .and_then(|range| parse_ranges(range, length));
+    let (start, end) = match ranges.as_deref() {
+        Some(&[ByteRange::Satisfiable { start, end }]) => {
+            response.set_header(
+                "Content-Range",
+                format!("bytes {}-{}/{}", start, end, length),
+            );
+            (start, end)
+        }
+        Some([ByteRange::Unsatisfiable]) => {
+            return Some(
+                Response::new(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .with_header("Content-Range", format!("bytes */{}", length)),
+            );
+        }
+        // several ranges are sent as parts of a multipart body
+        Some(ranges) => {
+            let boundary = byteranges_boundary();
+            response.status = StatusCode::PARTIAL_CONTENT;
+            response.set_header(
+                "Content-Type",
+                format!("multipart/byteranges; boundary={}", boundary),
+            );
+            let body = byteranges_body(file.source, ranges, content_type, length, &boundary);
+            return Some(response.with_body(body));
+        }
+        None if length == 0 => return Some(response),
+        None => (0, length - 1),
+    };
+    if ranges.is_some() {
+        response.status = StatusCode::PARTIAL_CONTENT;
+    }
+    let body = match file.source {
+        FileSource::Disk(file_path) => read_file_range(file_path, start, end - start + 1)
+            .await
+            .ok()?,
+        FileSource::Embedded(contents) => {
+            Body::Full(bytes::Bytes::from_static(&contents[start..=end]))
+        }
+    };
+    Some(response.with_body(body))
+}
+
+impl Response {
+    /// Responds with the file at `file_path` the way static directories
+    /// do, with an `ETag`, conditional requests and ranges. The
+    /// `Content-Type` comes from the extension using the built-in types,
+    /// set the header afterwards to override it. Answers 404 when the file
+    /// can't be read.
+    pub async fn file(request: &Request, file_path: &str) -> Response {
+        let content_type = MimeTypes::default().for_path(file_path).to_string();
+        match serve_file(request, file_path, &content_type, None).await {
+            Some(response) => response,
+            None => Response::new(StatusCode::NOT_FOUND),
+        }
+    }
+}
+
+/// Reads `length` bytes of a file starting at `start`.
+///
+/// Small files are read at once so they can still be compressed, larger
+/// ones are streamed in chunks as they are sent instead of being held in
+/// memory.
+async fn read_file_range(file_path: &str, start: usize, length: usize) -> io::Result<Body> {
+    let mut file = tokio::fs::File::open(file_path).await?;
+    if start > 0 {
+        file.seek(io::SeekFrom::Start(start as u64)).await?;
+    }
+    let mut file = file.take(length as u64);
+    if length <= MAX_BUFFERED_FILE_SIZE {
+        // read raw bytes so binary files like images aren't mangled
+        let mut contents = Vec::with_capacity(length);
+        file.read_to_end(&mut contents).await?;
+        return Ok(contents.into());
+    }
+
+    let chunks = futures_util::stream::unfold(file, |mut file| async move {
+        let mut chunk = BytesMut::with_capacity(FILE_CHUNK_SIZE);
+        match file.read_buf(&mut chunk).await {
+            Ok(read) if read > 0 => Some((chunk.freeze(), file)),
+            // a shorter body makes the connection close
+            _ => None,
+        }
+    });
+    Ok(Body::Sized(Box::pin(chunks), length))
+}
+
+/// A random boundary for a `multipart/byteranges` body, long enough that
+/// it won't show up in the file.
+fn byteranges_boundary() -> String {
+    use std::hash::{BuildHasher, Hasher};
+    // RandomState is seeded randomly, which saves a dependency on rand
+    let random = |n: u64| {
+        let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+        hasher.write_u64(n);
+        hasher.finish()
+    };
+    format!("{:016x}{:016x}", random(0), random(1))
+}
+
+/// The `multipart/byteranges` body for several `ranges` of a file that is
+/// `length` bytes long, every part with its own `Content-Range`.
+///
+/// Parts of files on disk are read as they are sent, so asking for many
+/// ranges doesn't hold the file in memory.
+fn byteranges_body(
+    source: FileSource<'_>,
+    ranges: &[ByteRange],
+    content_type: &str,
+    length: usize,
+    boundary: &str,
+) -> Body {
+    use futures_util::{stream, StreamExt};
+    let ranges: Vec<(usize, usize)> = ranges
+        .iter()
+        .filter_map(|range| match *range {
+            ByteRange::Satisfiable { start, end } => Some((start, end)),
+            ByteRange::Unsatisfiable => None,
+        })
+        .collect();
+    let part_head = |(start, end): (usize, usize)| {
+        bytes::Bytes::from(format!(
+            "--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+            boundary, content_type, start, end, length
+        ))
+    };
+    let closing = bytes::Bytes::from(format!("--{}--\r\n", boundary));
+    let body_length = ranges
+        .iter()
+        .map(|&(start, end)| part_head((start, end)).len() + end - start + 1 + 2)
+        .sum::<usize>()
+        + closing.len();
+
+    match source {
+        FileSource::Embedded(contents) => {
+            let mut body = BytesMut::with_capacity(body_length);
+            for (start, end) in ranges {
+                body.extend_from_slice(&part_head((start, end)));
+                body.extend_from_slice(&contents[start..=end]);
+                body.extend_from_slice(b"\r\n");
+            }
+            body.extend_from_slice(&closing);
+            Body::Full(body.freeze())
+        }
+        FileSource::Disk(file_path) => {
+            let file_path = file_path.to_string();
+            let parts: Vec<_> = ranges
+                .into_iter()
+                .map(|range| (part_head(range), range))
+                .collect();
+            let chunks = stream::iter(parts)
+                .then(move |(head, (start, end))| {
+                    let file_path = file_path.clone();
+                    async move {
+                        let contents =
+                            match read_file_range(&file_path, start, end - start + 1).await {
+                                Ok(body) => body.into_stream(),
+                                Err(e) => {
+                                    // a shorter body makes the connection close
+                                    warn!(file_path, error = ?e, "failed to read file range");
+                                    Box::pin(stream::empty())
+                                }
+                            };
+                        stream::once(async move { head })
+                            .chain(contents)
+                            .chain(stream::once(async { bytes::Bytes::from_static(b"\r\n") }))
+                    }
+                })
+                .flatten()
+                .chain(stream::once(async move { closing }));
+            Body::Sized(Box::pin(chunks), body_length)
+        }
+    }
+}
+
+/// Saves the files of a multipart upload to a static directory.
+///
+/// Posting to a directory (a path ending in `/`) stores every file under its
+/// own file name, otherwise the first file is stored at `file_path`.
+/// Nothing is stored when any of the files breaks the upload limits of
+/// `entry`.
+///
+/// File names come from the client, so they go through the same checks as
+/// a requested path, relative to the mount like `relative_path`.
+async fn save_multipart_upload(
+    request: &Request,
+    file_path: &str,
+    relative_path: &str,
+    entry: &StaticDirectoryEntry,
+    mime_types: &MimeTypes,
+) -> Response {
+    let parts = match request.multipart() {
+        Ok(parts) => parts,
+        Err(response) => return response,
+    };
+    let files: Vec<&Part> = parts.iter().filter(|part| part.is_file()).collect();
+    if files.is_empty() {
+        return Response::new(StatusCode::BAD_REQUEST).with_body("no files in upload");
+    }
+
+    let mut targets: Vec<(&Part, String)> = Vec::new();
+    if file_path.ends_with('/') {
+        for part in files {
+            let Some(filename) = part.safe_filename() else {
+                return Response::new(StatusCode::BAD_REQUEST).with_body("invalid file name");
+            };
+            let relative_target = format!("{}{}", relative_path, filename);
+            let Some(target) =
+                resolve_static_path(&entry.directory, &relative_target, entry.symlinks, true).await
+            else {
+                return Response::new(StatusCode::BAD_REQUEST).with_body("invalid file name");
+            };
+            targets.push((part, target));
+        }
+    } else {
+        targets.push((files[0], file_path.to_string()));
+    }
+    for (part, target) in &targets {
+        if let Err(response) = entry.check_upload(target, part.data.len(), mime_types) {
+            return response;
+        }
+        if let Err(response) = prepare_upload_path(target, entry.create_directories).await {
+            return response;
+        }
+    }
+
+    let mut result = Ok(());
+    for (part, target) in &targets {
+        result = part.save(target).await;
+        if result.is_err() {
+            break;
+        }
+    }
+
+    match result {
+        Ok(()) => Response::new(StatusCode::CREATED),
+        Err(_) => Response::new(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+/// Makes sure an upload to `file_path` has a directory to go into,
+/// creating missing ones when `create_directories` is set. A path ending
+/// in `/` is the directory itself, like for multipart uploads.
+///
+/// Fails with a 409 when the directory is missing or something in the way
+/// isn't a directory.
+async fn prepare_upload_path(file_path: &str, create_directories: bool) -> Result<(), Response> {
+    let directory = if file_path.ends_with('/') {
+        std::path::Path::new(file_path)
+    } else {
+        if tokio::fs::metadata(file_path)
+            .await
+            .is_ok_and(|metadata| metadata.is_dir())
+        {
+            return Err(Response::new(StatusCode::CONFLICT));
+        }
+        match std::path::Path::new(file_path).parent() {
+            Some(parent) => parent,
+            None => return Ok(()),
+        }
+    };
+    match tokio::fs::metadata(directory).await {
+        Ok(metadata) if metadata.is_dir() => return Ok(()),
+        Ok(_) => return Err(Response::new(StatusCode::CONFLICT)),
+        Err(_) if !create_directories => return Err(Response::new(StatusCode::CONFLICT)),
+        Err(_) => {}
+    }
+    // resolve_static_path already checked that the closest existing
+    // ancestor is inside the mount, and new directories stay below it
+    match tokio::fs::create_dir_all(directory).await {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            warn!(directory = %directory.display(), error = ?e, "failed to create directory");
+            // a file where a directory should be
+            if matches!(
+                e.kind(),
+                io::ErrorKind::AlreadyExists | io::ErrorKind::NotADirectory
+            ) {
+                Err(Response::new(StatusCode::CONFLICT))
+            } else {
+                Err(Response::new(StatusCode::INTERNAL_SERVER_ERROR))
+            }
+        }
+    }
+}
+
+/// Stores the body of a PUT request at `file_path`, replacing the file if
+/// there is one.
+async fn put_static_file(request: &Request, file_path: &str) -> Response {
+    if file_path.ends_with('/') {
+        return Response::new(StatusCode::CONFLICT);
+    }
+    let existed = match tokio::fs::metadata(file_path).await {
+        Ok(metadata) if metadata.is_dir() => return Response::new(StatusCode::CONFLICT),
+        Ok(_) => true,
+        Err(_) => false,
+    };
+    if let Err(e) = tokio::fs::write(file_path, &request.body_raw).await {
+        warn!(file_path, error = ?e, "failed to save upload");
+        // like a missing parent directory
+        if e.kind() == io::ErrorKind::NotFound {
+            return Response::new(StatusCode::CONFLICT);
+        }
+        return Response::new(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+    let response = if existed {
+        trace!(file_path, "replaced file");
+        Response::new(StatusCode::OK)
+    } else {
+        trace!(file_path, "created file");
+        Response::new(StatusCode::CREATED)
+    };
+    with_file_etag(response, file_path).await
+}
+
+/// Checks the `If-Match` and `If-Unmodified-Since` headers of a request
+/// changing the file at `file_path`, so clients only overwrite the version
+/// they saw. Returns a 412 response when they don't hold.
+///
+/// If-Unmodified-Since is only used by clients that don't send an ETag,
+/// and both fail for a file that doesn't exist, except for `If-Match: *`
+/// which only asks for the file to exist.
+async fn check_write_preconditions(request: &Request, file_path: &str) -> Result<(), Response> {
+    let if_match = request.headers.get_joined("if-match");
+    let if_unmodified_since = request.headers.get("if-unmodified-since");
+    if if_match.is_none() && if_unmodified_since.is_none() {
+        return Ok(());
+    }
+    let metadata = tokio::fs::metadata(file_path)
+        .await
+        .ok()
+        .filter(|metadata| metadata.is_file());
+
+    let passed = match (if_match, metadata) {
+        (Some(if_match), Some(metadata)) => etag_matches_strong(&if_match, &file_etag(&metadata)),
+        (Some(_), None) => false,
+        (None, metadata) => {
+            // a date that doesn't parse is ignored
+            match if_unmodified_since.and_then(|since| httpdate::parse_http_date(since).ok()) {
+                None => true,
+                Some(since) => metadata
+                    .and_then(|metadata| metadata.modified().ok())
+                    // http dates only have whole seconds
+                    .is_some_and(|modified| {
+                        modified
+                            .duration_since(since)
+                            .map_or(true, |newer| newer.as_secs() == 0)
+                    }),
+            }
+        }
+    };
+    if passed {
+        Ok(())
+    } else {
+        trace!(file_path, "write precondition failed");
+        Err(Response::new(StatusCode::PRECONDITION_FAILED))
+    }
+}
+
+/// Adds the ETag of the file just written to `response`, so clients can
+/// send it in `If-Match` with their next change.
+async fn with_file_etag(mut response: Response, file_path: &str) -> Response {
+    if let Ok(metadata) = tokio::fs::metadata(file_path).await {
+        response.set_header("ETag", file_etag(&metadata));
+    }
+    response
+}
+
+/// Removes the file at `file_path` for a DELETE request. Directories are
+/// left alone.
+async fn delete_static_file(file_path: &str) -> Response {
+    match tokio::fs::metadata(file_path).await {
+        Ok(metadata) if metadata.is_dir() => return Response::new(StatusCode::CONFLICT),
+        Ok(_) => {}
+        Err(_) => return Response::new(StatusCode::NOT_FOUND),
+    }
+    match tokio::fs::remove_file(file_path).await {
+        Ok(()) => {
+            trace!(file_path, "deleted file");
+            Response::new(StatusCode::NO_CONTENT)
+        }
+        Err(e) => {
+            warn!(file_path, error = ?e, "failed to delete file");
+            Response::new(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Whether `value` is a token, the syntax of methods and header names.
+pub(crate) fn is_token(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// Splits a header line into its lowercase name and its value without
+/// the surrounding whitespace.
+///
+/// Only the first colon separates them since values like hosts, dates and
+/// urls have more. Returns `None` for lines that aren't a valid header,
+/// like whitespace before the colon, obsolete line folding or control
+/// characters in the value.
+fn parse_header_line(line: &str) -> Option<(String, String)> {
+    let (name, value) = line.split_once(':')?;
+    // folded lines start with whitespace, which isn't part of a token
+    if !is_token(name) {
+        return None;
+    }
+    let value = value.trim_matches([' ', '\t']);
+    if value.chars().any(|c| c.is_control() && c != '\t') {
+        return None;
+    }
+    Some((name.to_lowercase(), value.to_string()))
+}
+
+/// Whether a request line version looks like `HTTP/x.y`, even if it's one
+/// we don't speak.
+fn is_http_version(version: &str) -> bool {
+    matches!(
+        version.strip_prefix("HTTP/").map(str::as_bytes),
+        Some([major, b'.', minor]) if major.is_ascii_digit() && minor.is_ascii_digit()
+    )
+}
+
+/// The path and query of a request target.
+///
+/// Besides the usual `/path?query`, the absolute form `http://host/path`
+/// sent to proxies, `*` for server wide OPTIONS requests and the
+/// `host:port` of a CONNECT are accepted, CONNECT is answered with a 501
+/// later on. Returns `None` for anything else.
+fn request_target_path<'a>(verb: &HttpVerb, target: &'a str) -> Option<&'a str> {
+    if target.starts_with('/') {
+        return Some(target);
+    }
+    match verb {
+        HttpVerb::OPTIONS if target == "*" => return Some(target),
+        HttpVerb::CONNECT => return Some(target),
+        _ => {}
+    }
+    let (scheme, rest) = target.split_once("://")?;
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return None;
+    }
+    match rest.find(['/', '?']) {
+        Some(start) if rest[start..].starts_with('/') => Some(&rest[start..]),
+        _ => Some("/"),
+    }
+}
+
+/// Parses the request line and headers of a raw HTTP/1.1 request.
+/// The body is read separately by the connection.
+/// Returns the error response to send when the request is malformed.
+pub(crate) fn parse_request_head(head: &[u8]) -> Result<Request, Response> {
+    // read the request and split it into lines
+    let request_str = String::from_utf8_lossy(head);
+
+    let request_lines: Vec<&str> = request_str.split("\r\n").collect();
+
+    if request_lines.is_empty() {
+        return Err(Response::new(StatusCode::BAD_REQUEST));
+    }
+
+    // parse the first line
+    // ex: GET / HTTP/1.1
+    let first_line = request_lines[0];
+    let first_line_split: Vec<&str> = first_line.split(' ').collect();
+
+    if first_line_split.len() != 3 {
+        return Err(Response::new(StatusCode::BAD_REQUEST));
+    }
+    let (method, target, version) = (
+        first_line_split[0],
+        first_line_split[1],
+        first_line_split[2],
+    );
+
+    // anything that isn't shaped like a request line is a 400, a valid
+    // line we can't serve is a 505 or a 501
+    if !is_token(method) || target.is_empty() || target.bytes().any(|b| b.is_ascii_control()) {
+        return Err(Response::new(StatusCode::BAD_REQUEST));
+    }
+    let version = match HttpVersion::from_request_line(version) {
+        Some(version) => version,
+        None if is_http_version(version) => {
+            return Err(Response::new(StatusCode::HTTP_VERSION_NOT_SUPPORTED))
+        }
+        None => return Err(Response::new(StatusCode::BAD_REQUEST)),
+    };
+    let verb =
+        HttpVerb::from_method(method).ok_or_else(|| Response::new(StatusCode::NOT_IMPLEMENTED))?;
+    let target =
+        request_target_path(&verb, target).ok_or_else(|| Response::new(StatusCode::BAD_REQUEST))?;
+
+    // split off the query string, it isn't part of route matching
+    let (requested_path, raw_query) = target.split_once('?').unwrap_or((target, ""));
+
+    // parse headers
+    let mut headers = HeaderMap::new();
+    // for each line after the first
+    for line in request_lines.iter().skip(1) {
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) =
+            parse_header_line(line).ok_or_else(|| Response::new(StatusCode::BAD_REQUEST))?;
+        headers.append(name, value);
+    }
+    let path = decode_path(requested_path).ok_or_else(|| Response::new(StatusCode::BAD_REQUEST))?;
+
+    Ok(Request {
+        verb,
+        version,
+        path,
+        raw_path: requested_path.to_string(),
+        query: parse_query(raw_query),
+        raw_query: raw_query.to_string(),
+        headers,
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// An empty directory of its own for a test to upload into.
+    fn upload_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("http-server-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn multipart_body(filename: &str) -> Vec<u8> {
+        format!(
+            "--b\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\
+             Content-Type: text/plain\r\n\r\nuploaded\r\n--b--\r\n",
+            filename
+        )
+        .into_bytes()
+    }
+
+    async fn upload(directory: &std::path::Path, filename: &str) -> StatusCode {
+        let mut server = Server::new(0);
+        server.serve(
+            String::from("files"),
+            directory.to_string_lossy().to_string(),
+            true,
+        );
+        server
+            .test()
+            .post("/files/")
+            .header("Content-Type", "multipart/form-data; boundary=b")
+            .body(multipart_body(filename))
+            .send()
+            .await
+            .status
+    }
+
+    #[tokio::test]
+    async fn multipart_upload_stores_file_under_its_name() {
+        let directory = upload_directory("multipart-name");
+        assert_eq!(upload(&directory, "a.txt").await, StatusCode::CREATED);
+        assert_eq!(std::fs::read(directory.join("a.txt")).unwrap(), b"uploaded");
+    }
+
+    #[tokio::test]
+    async fn multipart_upload_rejects_path_file_names() {
+        let directory = upload_directory("multipart-path");
+        for filename in ["../escaped.txt", "sub/a.txt", "sub\\a.txt", ".."] {
+            assert_eq!(upload(&directory, filename).await, StatusCode::BAD_REQUEST);
+        }
+        assert!(!directory.parent().unwrap().join("escaped.txt").exists());
+    }
+
+    #[tokio::test]
+    async fn multipart_upload_follows_ignore_rules() {
+        let directory = upload_directory("multipart-dotfile");
+        assert_eq!(upload(&directory, ".htaccess").await, StatusCode::FORBIDDEN);
+        assert!(!directory.join(".htaccess").exists());
+    }
+
+    /// A client for a server with `directory` mounted at `/files` with
+    /// uploads, PUT and DELETE allowed.
+    fn writable_mount(directory: &std::path::Path) -> testing::TestClient {
+        let mut server = Server::new(0);
+        server
+            .serve(
+                String::from("files"),
+                directory.to_string_lossy().to_string(),
+                true,
+            )
+            .unwrap()
+            .allow_put(true)
+            .allow_delete(true);
+        server.test()
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn uploads_do_not_write_through_dangling_symlinks() {
+        let directory = upload_directory("dangling-symlink");
+        let outside = upload_directory("dangling-symlink-outside").join("created.txt");
+        std::os::unix::fs::symlink(&outside, directory.join("evil")).unwrap();
+        let client = writable_mount(&directory);
+
+        let response = client.post("/files/evil").body("evil").send().await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        let response = client.put("/files/evil").body("evil").send().await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        assert!(!outside.exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn symlinks_are_followed_only_inside_the_root() {
+        let directory = upload_directory("symlink-root");
+        let outside_directory = upload_directory("symlink-root-outside");
+        std::fs::write(directory.join("real.txt"), "inside").unwrap();
+        std::fs::write(outside_directory.join("secret.txt"), "secret").unwrap();
+        std::os::unix::fs::symlink(directory.join("real.txt"), directory.join("alias.txt"))
+            .unwrap();
+        std::os::unix::fs::symlink(&outside_directory, directory.join("outside")).unwrap();
+        let client = writable_mount(&directory);
+
+        let response = client.get("/files/alias.txt").send().await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.text(), "inside");
+        let response = client.get("/files/outside/secret.txt").send().await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        // a link inside the root still can't be written through
+        let response = client.put("/files/alias.txt").body("changed").send().await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        let response = client
+            .put("/files/outside/new.txt")
+            .body("new")
+            .send()
+            .await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        assert!(!outside_directory.join("new.txt").exists());
+        assert_eq!(
+            std::fs::read(directory.join("real.txt")).unwrap(),
+            b"inside"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn multipart_upload_does_not_write_through_symlinks() {
+        let directory = upload_directory("multipart-symlink");
+        let outside = upload_directory("multipart-symlink-outside").join("target.txt");
+        std::fs::write(&outside, "original").unwrap();
+        std::os::unix::fs::symlink(&outside, directory.join("link.txt")).unwrap();
+
+        assert_eq!(
+            upload(&directory, "link.txt").await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(std::fs::read(&outside).unwrap(), b"original");
+    }
+
+    #[tokio::test]
+    async fn static_paths_stay_inside_the_directory() {
+        let parent = upload_directory("traversal");
+        let directory = parent.join("served");
+        std::fs::create_dir(&directory).unwrap();
+        std::fs::write(directory.join("inside.txt"), "inside").unwrap();
+        std::fs::write(parent.join("x"), "secret").unwrap();
+        let client = writable_mount(&directory);
+
+        assert_eq!(
+            client.get("/files/inside.txt").send().await.text(),
+            "inside"
+        );
+        for target in [
+            "/files/../x",
+            "/files/%2e%2e/x",
+            "/files/%2e%2e%2fx",
+            "/files/%2E%2E/x",
+            "/files/sub/../../x",
+            "/files//etc/passwd",
+            "/files/%2fetc%2fpasswd",
+            "/files/..%5cx",
+            "/files/%5c..%5cx",
+        ] {
+            let response = client.get(target).send().await;
+            assert_eq!(response.status, StatusCode::FORBIDDEN, "GET {}", target);
+            let response = client.put(target).body("overwritten").send().await;
+            assert_eq!(response.status, StatusCode::BAD_REQUEST, "PUT {}", target);
+            let response = client.post(target).body("overwritten").send().await;
+            assert_eq!(response.status, StatusCode::BAD_REQUEST, "POST {}", target);
+            let response = client.delete(target).send().await;
+            assert_eq!(response.status, StatusCode::FORBIDDEN, "DELETE {}", target);
+        }
+        // NUL bytes are refused before routing
+        for verb in [HttpVerb::GET, HttpVerb::PUT] {
+            let response = client
+                .request(verb, "/files/inside.txt%00.png")
+                .send()
+                .await;
+            assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        }
+        assert_eq!(std::fs::read(parent.join("x")).unwrap(), b"secret");
+    }
+
+    /// A client for a server with `directory` mounted read only at `/files`.
+    fn static_mount(directory: &std::path::Path) -> testing::TestClient {
+        let mut server = Server::new(0);
+        server.serve(
+            String::from("files"),
+            directory.to_string_lossy().to_string(),
+            false,
+        );
+        server.test()
+    }
+
+    #[tokio::test]
+    async fn answers_single_ranges_of_static_files() {
+        let directory = upload_directory("range-single");
+        std::fs::write(directory.join("a.txt"), "0123456789").unwrap();
+        let client = static_mount(&directory);
+
+        let response = client
+            .get("/files/a.txt")
+            .header("Range", "bytes=2-5")
+            .send()
+            .await;
+        assert_eq!(response.status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.text(), "2345");
+        assert_eq!(response.header("content-range").unwrap(), "bytes 2-5/10");
+        assert_eq!(response.header("content-length").unwrap(), "4");
+
+        let response = client
+            .get("/files/a.txt")
+            .header("Range", "bytes=-3")
+            .send()
+            .await;
+        assert_eq!(response.text(), "789");
+
+        let response = client
+            .get("/files/a.txt")
+            .header("Range", "bytes=10-")
+            .send()
+            .await;
+        assert_eq!(response.status, StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.header("content-range").unwrap(), "bytes */10");
+
+        // ranges it can't parse get the whole file
+        let response = client
+            .get("/files/a.txt")
+            .header("Range", "bytes=5-2")
+            .send()
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.text(), "0123456789");
+        assert_eq!(response.header("accept-ranges").unwrap(), "bytes");
+    }
+
+    #[tokio::test]
+    async fn answers_several_ranges_with_multipart_byteranges() {
+        let directory = upload_directory("range-multi");
+        std::fs::write(directory.join("a.txt"), "0123456789").unwrap();
+        let client = static_mount(&directory);
+
+        let response = client
+            .get("/files/a.txt")
+            .header("Range", "bytes=7-8, 0-1, 1-2")
+            .send()
+            .await;
+        assert_eq!(response.status, StatusCode::PARTIAL_CONTENT);
+        let content_type = response.header("content-type").unwrap().clone();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap();
+        let expected = format!(
+            "--{b}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-2/10\r\n\r\n012\r\n\
+             --{b}\r\nContent-Type: text/plain\r\nContent-Range: bytes 7-8/10\r\n\r\n78\r\n\
+             --{b}--\r\n",
+            b = boundary
+        );
+        assert_eq!(response.text(), expected);
+        assert_eq!(
+            response.header("content-length").unwrap(),
+            &expected.len().to_string()
+        );
+        assert!(response.header("content-range").is_none());
+    }
+
+    #[tokio::test]
+    async fn connect_is_not_answered_like_an_open_tunnel() {
+        let mut server = Server::new(0);
+        server.get(String::from("/"), |_| async {
+            Response::new(StatusCode::OK)
+        });
+        let client = server.test();
+        for target in ["example.com:443", "127.0.0.1:22", "/"] {
+            let response = client.request(HttpVerb::CONNECT, target).send().await;
+            assert_eq!(
+                response.status,
+                StatusCode::NOT_IMPLEMENTED,
+                "CONNECT {}",
+                target
+            );
+        }
+        let response = client.request(HttpVerb::OPTIONS, "*").send().await;
+        assert_eq!(response.status, StatusCode::OK);
+        // other verbs can't use the CONNECT form at all
+        let response = client.get("example.com:443").send().await;
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
     }
 }
@@ -0,0 +1,174 @@
+use serde::Deserialize;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+use tokio_rustls::TlsAcceptor;
+
+use crate::tls::load_tls_acceptor;
+use crate::{ConnectionInfo, Scheme};
+
+/// A socket the server accepts connections on, see `Server::add_listener`.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Listener {
+    /// plain http on a tcp address
+    Tcp(SocketAddr),
+    /// https using the PEM encoded certificate chain and private key at the
+    /// given paths
+    Tls {
+        addr: SocketAddr,
+        cert_path: String,
+        key_path: String,
+    },
+    /// plain http on a unix domain socket
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+}
+impl Listener {
+    /// Binds the socket, loading the certificate first for tls.
+    pub(crate) async fn bind(&self, config: &ListenerConfig) -> io::Result<BoundListener> {
+        Ok(match self {
+            Listener::Tcp(addr) => BoundListener::Tcp(bind_tcp(*addr, config)?),
+            Listener::Tls {
+                addr,
+                cert_path,
+                key_path,
+            } => {
+                let acceptor = load_tls_acceptor(cert_path, key_path)?;
+                BoundListener::Tls(bind_tcp(*addr, config)?, acceptor)
+            }
+            #[cfg(unix)]
+            Listener::Unix(path) => BoundListener::Unix(bind_unix(path)?, path.clone()),
+        })
+    }
+}
+
+/// Socket options for tcp listeners and the connections they accept, see
+/// `Server::set_listener_config`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ListenerConfig {
+    /// sets `TCP_NODELAY` on accepted sockets so small responses aren't
+    /// held back waiting for more data
+    pub nodelay: bool,
+    /// sets `SO_REUSEADDR` so a restarted server can bind while old
+    /// connections are still in `TIME_WAIT`
+    pub reuse_address: bool,
+    /// sets `SO_REUSEPORT` so several processes can share the port and
+    /// have the os balance connections between them, unix only
+    pub reuse_port: bool,
+    /// most connections the os queues before they are accepted
+    pub backlog: u32,
+    /// idle time before tcp keepalive probes are sent on accepted
+    /// sockets, off when `None`
+    pub keepalive: Option<Duration>,
+}
+impl Default for ListenerConfig {
+    fn default() -> Self {
+        // the same as `TcpListener::bind`
+        ListenerConfig {
+            nodelay: false,
+            reuse_address: cfg!(unix),
+            reuse_port: false,
+            backlog: 1024,
+            keepalive: None,
+        }
+    }
+}
+impl ListenerConfig {
+    /// Applies the options that are set per connection.
+    pub(crate) fn configure_stream(&self, stream: &TcpStream) -> io::Result<()> {
+        if self.nodelay {
+            stream.set_nodelay(true)?;
+        }
+        if let Some(time) = self.keepalive {
+            let keepalive = socket2::TcpKeepalive::new().with_time(time);
+            socket2::SockRef::from(stream).set_tcp_keepalive(&keepalive)?;
+        }
+        Ok(())
+    }
+}
+
+fn bind_tcp(addr: SocketAddr, config: &ListenerConfig) -> io::Result<TcpListener> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    socket.set_reuseaddr(config.reuse_address)?;
+    #[cfg(unix)]
+    socket.set_reuseport(config.reuse_port)?;
+    socket.bind(addr)?;
+    socket.listen(config.backlog)
+}
+
+/// A `Listener` after binding, ready for the accept loop.
+pub(crate) enum BoundListener {
+    Tcp(TcpListener),
+    Tls(TcpListener, TlsAcceptor),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, std::path::PathBuf),
+}
+impl BoundListener {
+    /// The bound tcp address, `None` for unix sockets.
+    pub(crate) fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            BoundListener::Tcp(listener) | BoundListener::Tls(listener, _) => {
+                listener.local_addr().ok()
+            }
+            #[cfg(unix)]
+            BoundListener::Unix(..) => None,
+        }
+    }
+}
+
+/// A bound socket the accept loop takes connections from.
+pub(crate) trait Accept {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    /// Waits for the next connection and the addresses of both ends, if
+    /// the transport has them.
+    fn accept_connection(
+        &self,
+    ) -> impl Future<Output = io::Result<(Self::Stream, ConnectionInfo)>> + Send;
+}
+
+impl Accept for TcpListener {
+    type Stream = TcpStream;
+
+    async fn accept_connection(&self) -> io::Result<(Self::Stream, ConnectionInfo)> {
+        let (stream, remote_addr) = self.accept().await?;
+        let info = ConnectionInfo {
+            remote_addr: Some(remote_addr),
+            // the listener's address may be a wildcard like 0.0.0.0
+            local_addr: stream.local_addr().ok(),
+            scheme: Scheme::Http,
+        };
+        Ok((stream, info))
+    }
+}
+
+#[cfg(unix)]
+impl Accept for tokio::net::UnixListener {
+    type Stream = tokio::net::UnixStream;
+
+    async fn accept_connection(&self) -> io::Result<(Self::Stream, ConnectionInfo)> {
+        let (stream, _) = self.accept().await?;
+        Ok((stream, ConnectionInfo::default()))
+    }
+}
+
+/// Binds a unix socket at `path`, replacing a stale socket file left
+/// behind by a previous run.
+#[cfg(unix)]
+fn bind_unix(path: &std::path::Path) -> io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            std::fs::remove_file(path)?;
+        }
+    }
+    tokio::net::UnixListener::bind(path)
+}
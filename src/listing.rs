@@ -0,0 +1,115 @@
+use crate::{percent_decode, percent_encode, Request, Response, StatusCode};
+use serde::Serialize;
+use std::time::UNIX_EPOCH;
+
+/// One entry of a directory listing.
+#[derive(Debug, Serialize)]
+struct ListingEntry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    /// seconds since the unix epoch
+    modified: Option<u64>,
+}
+
+/// Builds an html listing of a directory, or json when the client asks for
+/// it with `?format=json` or an `Accept: application/json` header.
+///
+/// `url_path` is the requested path, still percent-encoded, and is used to
+/// build absolute links. Entries whose name `hidden` returns true for are
+/// left out.
+/// Returns `None` when `directory` isn't a readable directory.
+pub(crate) async fn directory_listing(
+    request: &Request,
+    directory: &str,
+    url_path: &str,
+    show_parent: bool,
+    hidden: impl Fn(&str) -> bool,
+) -> Option<Response> {
+    let mut read_dir = tokio::fs::read_dir(directory).await.ok()?;
+    let mut entries = Vec::new();
+    while let Ok(Some(dir_entry)) = read_dir.next_entry().await {
+        if hidden(&dir_entry.file_name().to_string_lossy()) {
+            continue;
+        }
+        let metadata = match dir_entry.metadata().await {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        entries.push(ListingEntry {
+            name: dir_entry.file_name().to_string_lossy().into_owned(),
+            is_dir: metadata.is_dir(),
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified: metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|modified| modified.as_secs()),
+        });
+    }
+    // directories first, then by name
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+
+    let wants_json = request.query.get("format").is_some_and(|f| f == "json")
+        || request
+            .headers
+            .get_joined("accept")
+            .is_some_and(|accept| accept.contains("application/json"));
+    if wants_json {
+        return Some(Response::json(&entries));
+    }
+
+    let base = format!("{}/", url_path.trim_end_matches('/'));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Index of {0}</title>\n</head>\n<body>\n<h1>Index of {0}</h1>\n<table>\n<tr><th>Name</th><th>Size</th><th>Modified</th></tr>\n",
+        html_escape(&percent_decode(&base))
+    );
+    if show_parent {
+        let parent = match base.trim_end_matches('/').rsplit_once('/') {
+            Some((parent, _)) => format!("{}/", parent),
+            None => String::from("/"),
+        };
+        html.push_str(&format!(
+            "<tr><td><a href=\"{}\">../</a></td><td></td><td></td></tr>\n",
+            html_escape(&parent)
+        ));
+    }
+    for entry in entries.iter() {
+        let suffix = if entry.is_dir { "/" } else { "" };
+        let size = if entry.is_dir {
+            String::from("-")
+        } else {
+            entry.size.to_string()
+        };
+        let modified = entry
+            .modified
+            .map(|secs| httpdate::fmt_http_date(UNIX_EPOCH + std::time::Duration::from_secs(secs)))
+            .unwrap_or_default();
+        html.push_str(&format!(
+            "<tr><td><a href=\"{}{}{}\">{}{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            html_escape(&base),
+            percent_encode(&entry.name),
+            suffix,
+            html_escape(&entry.name),
+            suffix,
+            size,
+            modified
+        ));
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+
+    Some(
+        Response::new(StatusCode::OK)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(html),
+    )
+}
+
+fn html_escape(input: &str) -> String {
+    input
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
@@ -1,37 +1,99 @@
-use std::env;
 use std::io::{self};
+use std::net::{IpAddr, SocketAddr};
 
+use clap::{ArgAction, Parser};
 use http_server_starter_rust::*;
+use tracing_subscriber::EnvFilter;
+
+/// Small http server with echo, user-agent and file endpoints.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// Port to listen on
+    #[arg(short, long, default_value_t = 4221)]
+    port: u16,
+
+    /// Address to listen on, use 0.0.0.0 to be reachable from other machines
+    #[arg(short, long, default_value = "127.0.0.1")]
+    address: IpAddr,
+
+    /// Directory served under /files
+    #[arg(short, long)]
+    directory: Option<String>,
+
+    /// Allow uploading files to the directory with POST
+    #[arg(long, default_value_t = true, action = ArgAction::Set)]
+    upload: bool,
+
+    /// Log filter like `info` or `debug`, defaults to RUST_LOG or `info`
+    #[arg(long)]
+    log_level: Option<String>,
+
+    /// PEM certificate chain, serves https instead of http
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<String>,
+
+    /// PEM private key for --tls-cert
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<String>,
+
+    /// TOML config file, replaces the address, port and tls flags
+    #[arg(short, long)]
+    config: Option<String>,
+}
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
-    // parse command line arguments
-    let args = env::args().collect::<Vec<String>>();
-    let mut directory = String::from("");
-    if args.len() > 2 && args[1] == "--directory" {
-        directory = args[2].clone();
-    }
+    let args = Args::parse();
 
-    let mut server = Server::new(4221);
+    let config = args.config.as_ref().map(ServerConfig::load).transpose()?;
+
+    let level = args
+        .log_level
+        .clone()
+        .or_else(|| config.as_ref()?.logging.level.clone());
+    let filter = match level {
+        Some(level) => EnvFilter::new(level),
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+    };
+    tracing_subscriber::fmt().with_env_filter(filter).init();
+
+    let mut server = match &config {
+        Some(config) => Server::from_server_config(config)?,
+        None => {
+            let addr = SocketAddr::new(args.address, args.port);
+            let mut server = Server::new_with_addr(addr);
+            if let (Some(cert_path), Some(key_path)) = (args.tls_cert, args.tls_key) {
+                server.add_listener(Listener::Tls {
+                    addr,
+                    cert_path,
+                    key_path,
+                });
+            }
+            // compress even tiny bodies when the client asks for it
+            server.set_compression_min_size(0);
+            server
+        }
+    };
 
-    server.get(String::from("echo/*"), |request| {
+    server.get(String::from("echo/*"), |request| async move {
         if !request.path.starts_with("/echo/") {
             return Server::respond(Some(400), Some(String::from("Bad Request")), None);
         }
         let echo_param = request.path[6..].to_string();
-        return Server::respond(Some(200), Some(String::from(echo_param)), None);
+        Server::respond(Some(200), Some(echo_param), None)
     });
 
-    server.get(String::from("user-agent"), |request| {
+    server.get(String::from("user-agent"), |request| async move {
         let unknown_agent = String::from("unknown");
         let user_agent = request.headers.get("user-agent").unwrap_or(&unknown_agent);
-        return Server::respond(Some(200), Some(user_agent.to_string()), None);
+        Server::respond(Some(200), Some(user_agent.to_string()), None)
     });
 
-    if !directory.is_empty() {
-        server.serve(String::from("files"), directory, true);
+    if let Some(directory) = args.directory {
+        server.serve(String::from("files"), directory, args.upload);
     }
 
-    // start server
-    server.listen().await
+    // start server, ctrl-c or SIGTERM lets open requests finish first
+    server.listen_all_with_shutdown(shutdown_signal()).await
 }
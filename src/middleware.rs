@@ -0,0 +1,55 @@
+use crate::{Handler, HandlerFuture, IntoResponse, Request};
+use std::future::Future;
+use std::sync::Arc;
+
+/// Type-erased middleware stored in the registry.
+pub type Middleware = Arc<dyn Fn(Request, Next) -> HandlerFuture + Send + Sync>;
+
+/// Wraps a middleware function into the type stored in the registry.
+pub(crate) fn into_middleware<F, Fut>(middleware: F) -> Middleware
+where
+    F: Fn(Request, Next) -> Fut + Send + Sync + 'static,
+    Fut: Future + Send + 'static,
+    Fut::Output: IntoResponse,
+{
+    Arc::new(move |request, next| {
+        let future = middleware(request, next);
+        Box::pin(async move { future.await.into_response() })
+    })
+}
+
+/// The rest of a middleware chain.
+///
+/// Middleware calls `next.run(request)` to pass the request on to the next
+/// middleware, or the endpoint once the chain is done. Not calling it
+/// short-circuits the chain with the middleware's own response.
+#[derive(Clone)]
+pub struct Next {
+    middleware: Arc<Vec<Middleware>>,
+    index: usize,
+    endpoint: Handler,
+}
+impl Next {
+    pub(crate) fn new(middleware: Arc<Vec<Middleware>>, endpoint: Handler) -> Next {
+        Next {
+            middleware,
+            index: 0,
+            endpoint,
+        }
+    }
+
+    /// Runs the rest of the chain and returns its response.
+    pub fn run(self, request: Request) -> HandlerFuture {
+        match self.middleware.get(self.index) {
+            Some(middleware) => {
+                let middleware = middleware.clone();
+                let next = Next {
+                    index: self.index + 1,
+                    ..self
+                };
+                middleware(request, next)
+            }
+            None => (self.endpoint)(request),
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+
+/// Content type used when the extension is unknown.
+const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+/// Built-in mapping of file extensions to content types.
+pub fn builtin_mime_type(extension: &str) -> Option<&'static str> {
+    let mime_type = match extension {
+        // text
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "js" | "mjs" => "text/javascript",
+        "txt" => "text/plain",
+        "csv" => "text/csv",
+        "md" => "text/markdown",
+        "xml" => "application/xml",
+        "json" | "map" => "application/json",
+        "webmanifest" => "application/manifest+json",
+        "yaml" | "yml" => "application/yaml",
+        // images
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "svg" => "image/svg+xml",
+        "ico" => "image/x-icon",
+        "bmp" => "image/bmp",
+        // fonts
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        // audio and video
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "ogg" => "audio/ogg",
+        "flac" => "audio/flac",
+        "m4a" => "audio/mp4",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        // everything else
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "tar" => "application/x-tar",
+        _ => return None,
+    };
+    Some(mime_type)
+}
+
+/// Maps file extensions to content types for static files.
+///
+/// Custom mappings take priority over the built-in table.
+#[derive(Debug, Default, Clone)]
+pub struct MimeTypes {
+    custom: HashMap<String, String>,
+}
+impl MimeTypes {
+    /// Maps an extension like `"wasm"` or `".wasm"` to a content type.
+    pub fn insert(&mut self, extension: &str, mime_type: impl Into<String>) {
+        let extension = extension.trim_start_matches('.').to_lowercase();
+        self.custom.insert(extension, mime_type.into());
+    }
+
+    /// Content type for a file path, based on its extension.
+    pub fn for_path(&self, path: &str) -> &str {
+        let file_name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+        let extension = match file_name.rsplit_once('.') {
+            Some((_, extension)) => extension.to_lowercase(),
+            None => return DEFAULT_MIME_TYPE,
+        };
+        match self.custom.get(&extension) {
+            Some(mime_type) => mime_type,
+            None => builtin_mime_type(&extension).unwrap_or(DEFAULT_MIME_TYPE),
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use crate::{Request, Response, StatusCode};
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+/// One part of a `multipart/form-data` body, either a form field or an
+/// uploaded file.
+#[derive(Debug, Default, Clone)]
+pub struct Part {
+    /// headers of the part, keys are lowercase
+    pub headers: HashMap<String, String>,
+    /// form field name from `Content-Disposition`
+    pub name: Option<String>,
+    /// original file name for file uploads
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub data: Bytes,
+}
+impl Part {
+    /// Whether this part is an uploaded file rather than a plain field.
+    pub fn is_file(&self) -> bool {
+        self.filename.is_some()
+    }
+
+    /// The part's data as text, replacing invalid utf-8.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.data).to_string()
+    }
+
+    /// Writes the part's data to a file without blocking the runtime.
+    pub async fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        tokio::fs::write(path, &self.data).await
+    }
+
+    /// The file name, safe to join onto an upload directory. Returns `None`
+    /// for names with path separators or `..`, which could point somewhere
+    /// else than the directory, and for empty ones.
+    pub fn safe_filename(&self) -> Option<String> {
+        let name = self.filename.as_ref()?.trim();
+        if name.is_empty() || name == "." || name.contains(['/', '\\', '\0']) || name.contains("..")
+        {
+            return None;
+        }
+        Some(name.to_string())
+    }
+}
+
+/// Reads the boundary parameter from a `multipart/form-data` content type.
+pub fn multipart_boundary(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    let media_type = params.next()?.trim();
+    if !media_type.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params.find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        if !key.trim().eq_ignore_ascii_case("boundary") {
+            return None;
+        }
+        let value = value.trim().trim_matches('"');
+        if value.is_empty() {
+            None
+        } else {
+            Some(value.to_string())
+        }
+    })
+}
+
+/// Splits a multipart body into its parts.
+/// Returns `None` when the body isn't valid multipart for `boundary`.
+pub fn parse_multipart(boundary: &str, body: &[u8]) -> Option<Vec<Part>> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let mut parts = Vec::new();
+
+    // anything before the first delimiter is a preamble and ignored
+    let mut position = find(body, &delimiter, 0)? + delimiter.len();
+    loop {
+        // the final delimiter is followed by "--"
+        if body[position..].starts_with(b"--") {
+            return Some(parts);
+        }
+        // skip transport padding up to the end of the delimiter line
+        position = find(body, b"\r\n", position)? + 2;
+
+        // a part without headers starts with the empty line right away
+        let (headers, content_start) = if body[position..].starts_with(b"\r\n") {
+            (HashMap::new(), position + 2)
+        } else {
+            let headers_end = find(body, b"\r\n\r\n", position)?;
+            (
+                parse_part_headers(&body[position..headers_end]),
+                headers_end + 4,
+            )
+        };
+
+        let mut next_delimiter = b"\r\n".to_vec();
+        next_delimiter.extend_from_slice(&delimiter);
+        let content_end = find(body, &next_delimiter, content_start)?;
+
+        let disposition = headers
+            .get("content-disposition")
+            .map(|value| parse_disposition(value))
+            .unwrap_or_default();
+        parts.push(Part {
+            name: disposition.get("name").cloned(),
+            filename: disposition.get("filename").cloned(),
+            content_type: headers.get("content-type").cloned(),
+            headers,
+            data: Bytes::copy_from_slice(&body[content_start..content_end]),
+        });
+
+        position = content_end + next_delimiter.len();
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    if from > haystack.len() {
+        return None;
+    }
+    haystack[from..]
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| position + from)
+}
+
+fn parse_part_headers(block: &[u8]) -> HashMap<String, String> {
+    String::from_utf8_lossy(block)
+        .split("\r\n")
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            Some((name.trim().to_lowercase(), value.trim().to_string()))
+        })
+        .collect()
+}
+
+/// Parses the parameters of a `Content-Disposition` value like
+/// `form-data; name="file"; filename="a.txt"`.
+fn parse_disposition(value: &str) -> HashMap<String, String> {
+    value
+        .split(';')
+        .skip(1)
+        .filter_map(|param| {
+            let (key, value) = param.split_once('=')?;
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+            Some((key.trim().to_lowercase(), value.to_string()))
+        })
+        .collect()
+}
+
+impl Request {
+    /// Parses a `multipart/form-data` body into its parts.
+    ///
+    /// Fails with a 415 response when the request isn't multipart and with
+    /// a 400 response when the body is malformed.
+    pub fn multipart(&self) -> Result<Vec<Part>, Response> {
+        let boundary = self
+            .headers
+            .get("content-type")
+            .and_then(|content_type| multipart_boundary(content_type))
+            .ok_or_else(|| {
+                Response::new(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+                    .with_body("expected a Content-Type of multipart/form-data")
+            })?;
+        parse_multipart(&boundary, &self.body_raw).ok_or_else(|| {
+            Response::new(StatusCode::BAD_REQUEST).with_body("invalid multipart body")
+        })
+    }
+}
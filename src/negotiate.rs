@@ -0,0 +1,192 @@
+use crate::{IntoResponse, Request, Response, StatusCode};
+
+/// One media range of an `Accept` header, like `text/*;q=0.8`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaRange {
+    /// lowercased type and subtype, either can be `*`
+    pub media_type: String,
+    /// parameters other than q, like `charset=utf-8`
+    pub params: Vec<(String, String)>,
+    pub q: f32,
+}
+impl MediaRange {
+    /// How specific the range is, the most specific range matching a
+    /// media type decides its q-value.
+    fn specificity(&self) -> u8 {
+        match self.media_type.as_str() {
+            "*/*" => 0,
+            media_type if media_type.ends_with("/*") => 1,
+            _ if self.params.is_empty() => 2,
+            _ => 3,
+        }
+    }
+
+    /// Whether `media_type`, without parameters, is in this range.
+    fn matches(&self, media_type: &str) -> bool {
+        let Some((kind, subtype)) = media_type.split_once('/') else {
+            return false;
+        };
+        match self.media_type.split_once('/') {
+            Some(("*", "*")) => true,
+            Some((range_kind, "*")) => range_kind.eq_ignore_ascii_case(kind),
+            Some((range_kind, range_subtype)) => {
+                range_kind.eq_ignore_ascii_case(kind) && range_subtype.eq_ignore_ascii_case(subtype)
+            }
+            None => false,
+        }
+    }
+}
+
+/// Parses an `Accept` header value into media ranges, highest q-value
+/// first and more specific ranges first among equal q-values.
+/// A missing q-value counts as 1.
+pub fn parse_accept(accept: &str) -> Vec<MediaRange> {
+    let mut ranges: Vec<MediaRange> = accept
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let media_type = parts.next()?.trim().to_lowercase();
+            if !media_type.contains('/') {
+                return None;
+            }
+            let mut q = 1.0;
+            let mut params = Vec::new();
+            for param in parts {
+                let Some((name, value)) = param.split_once('=') else {
+                    continue;
+                };
+                let (name, value) = (name.trim().to_lowercase(), value.trim());
+                if name == "q" {
+                    q = value.parse::<f32>().unwrap_or(1.0).clamp(0.0, 1.0);
+                } else {
+                    params.push((name, value.trim_matches('"').to_string()));
+                }
+            }
+            Some(MediaRange {
+                media_type,
+                params,
+                q,
+            })
+        })
+        .collect();
+    ranges.sort_by(|a, b| {
+        b.q.total_cmp(&a.q)
+            .then_with(|| b.specificity().cmp(&a.specificity()))
+    });
+    ranges
+}
+
+/// Picks the media type from `available` the client prefers, given its
+/// `Accept` header value.
+///
+/// Each type gets the q-value of the most specific range matching it. The
+/// highest q-value wins and ties go to the earlier type in `available`.
+/// Types with `q=0` or no matching range are never chosen.
+pub fn negotiate<'a>(accept: &str, available: &[&'a str]) -> Option<&'a str> {
+    let ranges = parse_accept(accept);
+    let mut best: Option<(&str, f32)> = None;
+    for media_type in available {
+        let essence = media_type.split(';').next().unwrap_or("").trim();
+        let q = ranges
+            .iter()
+            .filter(|range| range.matches(essence))
+            .max_by_key(|range| range.specificity())
+            .map(|range| range.q);
+        let q = match q {
+            Some(q) if q > 0.0 => q,
+            _ => continue,
+        };
+        if best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((media_type, q));
+        }
+    }
+    best.map(|(media_type, _)| media_type)
+}
+
+impl Request {
+    /// The media type from `available` the client prefers according to
+    /// its `Accept` headers, see `negotiate`. Clients that don't send
+    /// `Accept` take anything, so they get the first one.
+    pub fn prefers<'a>(&self, available: &[&'a str]) -> Option<&'a str> {
+        match self.headers.get_joined("accept") {
+            Some(accept) => negotiate(&accept, available),
+            None => available.first().copied(),
+        }
+    }
+}
+
+type Render = Box<dyn FnOnce() -> Response + Send>;
+
+/// A response with several representations, like JSON and HTML, where the
+/// one the client prefers is sent.
+///
+/// Only the chosen representation is rendered. Responses get a
+/// `Vary: Accept` header and a `406 Not Acceptable` is sent when the
+/// client accepts none of them.
+///
+/// ```ignore
+/// server.get(String::from("users"), |request| async move {
+///     let users = load_users();
+///     Negotiated::new(&request)
+///         .with("text/html", {
+///             let users = users.clone();
+///             move || Response::new(StatusCode::OK).with_body(render_users(&users))
+///         })
+///         .json(users)
+/// });
+/// ```
+pub struct Negotiated {
+    accept: Option<String>,
+    representations: Vec<(String, Render)>,
+}
+impl Negotiated {
+    pub fn new(request: &Request) -> Negotiated {
+        Negotiated {
+            accept: request.headers.get_joined("accept"),
+            representations: Vec::new(),
+        }
+    }
+
+    /// Adds a representation of `media_type`, earlier ones win when the
+    /// client likes several equally. The `Content-Type` is set to
+    /// `media_type` unless `render` sets one.
+    pub fn with(
+        mut self,
+        media_type: impl Into<String>,
+        render: impl FnOnce() -> Response + Send + 'static,
+    ) -> Negotiated {
+        self.representations
+            .push((media_type.into(), Box::new(render)));
+        self
+    }
+
+    /// Adds an `application/json` representation of `value`.
+    pub fn json<T: serde::Serialize + Send + 'static>(self, value: T) -> Negotiated {
+        self.with("application/json", move || Response::json(&value))
+    }
+}
+impl IntoResponse for Negotiated {
+    fn into_response(mut self) -> Response {
+        let available: Vec<&str> = self
+            .representations
+            .iter()
+            .map(|(media_type, _)| media_type.as_str())
+            .collect();
+        let chosen = match &self.accept {
+            Some(accept) => negotiate(accept, &available),
+            None => available.first().copied(),
+        };
+        let Some(index) = chosen.and_then(|chosen| available.iter().position(|t| *t == chosen))
+        else {
+            return Response::new(StatusCode::NOT_ACCEPTABLE).with_vary("Accept");
+        };
+
+        let (media_type, render) = self.representations.swap_remove(index);
+        let mut response = render();
+        if response.header("Content-Type").is_none() {
+            response.set_header("Content-Type", media_type);
+        }
+        response.add_vary("Accept");
+        response
+    }
+}
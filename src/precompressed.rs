@@ -0,0 +1,102 @@
+use crate::compression::choose_encoding_among;
+use crate::embed::EmbeddedFiles;
+use crate::{
+    file_etag, file_response, CachePolicy, ContentEncoding, FileSource, Request, Response,
+    StaticFile, StatusCode,
+};
+
+/// Extensions of the pre-compressed copies looked for next to a file, in
+/// the server's preference.
+const SIDECARS: [(ContentEncoding, &str); 2] = [
+    (ContentEncoding::Brotli, "br"),
+    (ContentEncoding::Gzip, "gz"),
+];
+
+/// Serves `file_path.br` or `file_path.gz` instead of `file_path` when the
+/// client accepts that encoding and the copy exists. Returns `None` to
+/// serve the file itself.
+pub(crate) async fn serve_precompressed(
+    request: &Request,
+    file_path: &str,
+    content_type: &str,
+    cache_policy: Option<&CachePolicy>,
+) -> Option<Response> {
+    let accept_encoding = request.headers.get_joined("accept-encoding")?;
+    // the copies only stand in for a file that exists
+    if !tokio::fs::metadata(file_path)
+        .await
+        .is_ok_and(|metadata| metadata.is_file())
+    {
+        return None;
+    }
+    let mut available = Vec::new();
+    for (encoding, extension) in SIDECARS {
+        let sidecar_path = format!("{}.{}", file_path, extension);
+        if let Ok(metadata) = tokio::fs::metadata(&sidecar_path).await {
+            if metadata.is_file() {
+                available.push((encoding, sidecar_path, metadata));
+            }
+        }
+    }
+    let encodings: Vec<ContentEncoding> =
+        available.iter().map(|(encoding, ..)| *encoding).collect();
+    let chosen = choose_encoding_among(&accept_encoding, &encodings)?;
+    let (encoding, sidecar_path, metadata) = available
+        .into_iter()
+        .find(|(encoding, ..)| *encoding == chosen)?;
+
+    let file = StaticFile {
+        source: FileSource::Disk(&sidecar_path),
+        length: metadata.len() as usize,
+        etag: encoded_etag(&file_etag(&metadata), encoding),
+        modified: metadata.modified().ok(),
+    };
+    let response = file_response(request, file, content_type, cache_policy).await?;
+    Some(with_encoding(response, encoding))
+}
+
+/// Like `serve_precompressed` for embedded files, using the `.br` or `.gz`
+/// copy embedded next to `path`.
+pub(crate) async fn serve_embedded_precompressed(
+    request: &Request,
+    embedded: &EmbeddedFiles,
+    path: &str,
+    content_type: &str,
+    cache_policy: Option<&CachePolicy>,
+) -> Option<Response> {
+    let accept_encoding = request.headers.get_joined("accept-encoding")?;
+    let available: Vec<ContentEncoding> = SIDECARS
+        .iter()
+        .filter(|(_, extension)| embedded.get(&format!("{}.{}", path, extension)).is_some())
+        .map(|(encoding, _)| *encoding)
+        .collect();
+    let encoding = choose_encoding_among(&accept_encoding, &available)?;
+    let (_, extension) = SIDECARS.iter().find(|(e, _)| *e == encoding)?;
+    let sidecar = embedded.get(&format!("{}.{}", path, extension))?;
+
+    let file = StaticFile {
+        source: FileSource::Embedded(sidecar.contents),
+        length: sidecar.contents.len(),
+        etag: encoded_etag(&sidecar.etag, encoding),
+        modified: None,
+    };
+    let response = file_response(request, file, content_type, cache_policy).await?;
+    Some(with_encoding(response, encoding))
+}
+
+/// Tells the encoding apart in the ETag, so caches never mix up the
+/// compressed and plain file.
+fn encoded_etag(etag: &str, encoding: ContentEncoding) -> String {
+    format!("{}-{}\"", etag.trim_end_matches('"'), encoding.as_str())
+}
+
+/// Marks a response with the body of a pre-compressed copy.
+fn with_encoding(mut response: Response, encoding: ContentEncoding) -> Response {
+    if matches!(
+        response.status,
+        StatusCode::OK | StatusCode::PARTIAL_CONTENT
+    ) {
+        response.set_header("Content-Encoding", encoding.as_str());
+    }
+    response
+}
@@ -0,0 +1,698 @@
+use crate::http_client::{Client, ClientError, Url};
+use crate::CircuitBreaker;
+use crate::{
+    normalize_path, percent_encode, HeaderMap, HttpVerb, Request, Response, Server, StatusCode,
+};
+use futures_util::StreamExt;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// How long connecting to the upstream and waiting for its response head
+/// may take before the client gets a 504.
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Failed requests in a row after which an upstream is left out.
+const MAX_FAILS: usize = 3;
+
+/// How long an upstream that kept failing is left out.
+const FAIL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Headers that only apply to a single connection and are never forwarded.
+const HOP_BY_HOP_HEADERS: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Where proxied requests are sent, parsed from a url like
+/// `http://127.0.0.1:9000/api`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Upstream {
+    url: Url,
+    /// path prefix put in front of every forwarded path, without a
+    /// trailing slash
+    base_path: String,
+}
+impl Upstream {
+    fn parse(url: &str) -> Option<Upstream> {
+        // https upstreams aren't supported
+        let url = Url::parse(url)?;
+        let base_path = url.target.trim_end_matches('/').to_string();
+        Some(Upstream { url, base_path })
+    }
+
+    /// `Host` header value for requests to the upstream.
+    fn authority(&self) -> String {
+        self.url.authority()
+    }
+}
+
+/// How a proxy with several upstreams picks the one for a request.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum LoadBalancing {
+    /// each upstream in turn
+    #[default]
+    RoundRobin,
+    /// the upstream with the fewest requests in progress, in turn when
+    /// several are tied
+    LeastConnections,
+}
+
+/// Settings of the requests a proxy sends its upstreams on its own to
+/// find out which are up, see `Server::proxy_health_check`.
+#[derive(Debug, Clone)]
+pub struct UpstreamHealthCheck {
+    /// path requested from every upstream, relative to its host and not
+    /// its url's path
+    pub path: String,
+    /// time between two checks
+    pub interval: Duration,
+    /// how long an upstream may take to answer a check
+    pub timeout: Duration,
+    /// checks in a row a down upstream has to pass to get traffic again
+    pub healthy_threshold: u32,
+    /// checks in a row an upstream has to fail to stop getting traffic
+    pub unhealthy_threshold: u32,
+}
+impl UpstreamHealthCheck {
+    /// Requests `path` every 10 seconds, with a 2 second timeout. Upstreams
+    /// are down after failing 3 checks and up again after passing 2.
+    pub fn new(path: impl Into<String>) -> UpstreamHealthCheck {
+        UpstreamHealthCheck {
+            path: normalize_path(path.into()),
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(2),
+            healthy_threshold: 2,
+            unhealthy_threshold: 3,
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> UpstreamHealthCheck {
+        self.interval = interval;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> UpstreamHealthCheck {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets how many checks in a row make a down upstream healthy and a
+    /// healthy one down.
+    pub fn with_thresholds(mut self, healthy: u32, unhealthy: u32) -> UpstreamHealthCheck {
+        self.healthy_threshold = healthy.max(1);
+        self.unhealthy_threshold = unhealthy.max(1);
+        self
+    }
+}
+
+/// An upstream and what the proxy knows about its health.
+#[derive(Debug)]
+struct Backend {
+    upstream: Upstream,
+    /// requests being forwarded or streamed back right now
+    active: AtomicUsize,
+    /// failed requests since the last one that succeeded
+    failures: AtomicUsize,
+    /// set while the upstream is left out after failing repeatedly
+    down_until: Mutex<Option<Instant>>,
+    /// whether the upstream passes its health checks, always without them
+    healthy: AtomicBool,
+}
+impl Backend {
+    fn is_available(&self) -> bool {
+        if !self.healthy.load(Ordering::Relaxed) {
+            return false;
+        }
+        let down_until = self.down_until.lock().unwrap();
+        down_until.is_none_or(|until| Instant::now() >= until)
+    }
+
+    fn succeeded(&self) {
+        self.failures.store(0, Ordering::Relaxed);
+        *self.down_until.lock().unwrap() = None;
+    }
+
+    fn failed(&self) {
+        let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= MAX_FAILS {
+            self.failures.store(0, Ordering::Relaxed);
+            *self.down_until.lock().unwrap() = Some(Instant::now() + FAIL_TIMEOUT);
+            warn!(
+                upstream = %self.upstream.authority(),
+                "upstream failed {} times in a row, leaving it out for {:?}",
+                failures,
+                FAIL_TIMEOUT
+            );
+        }
+    }
+}
+
+/// Counts a request as active on its backend until dropped, which is
+/// after the response body was streamed back.
+struct ActiveRequest(Arc<Backend>);
+impl ActiveRequest {
+    fn start(backend: Arc<Backend>) -> ActiveRequest {
+        backend.active.fetch_add(1, Ordering::Relaxed);
+        ActiveRequest(backend)
+    }
+}
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The upstreams of one proxied path.
+#[derive(Debug)]
+pub(crate) struct UpstreamGroup {
+    backends: Vec<Arc<Backend>>,
+    balancing: LoadBalancing,
+    /// where round robin continues
+    next: AtomicUsize,
+    /// health checks waiting for a tokio runtime to run on
+    health_check: Mutex<Option<UpstreamHealthCheck>>,
+    circuit_breaker: OnceLock<CircuitBreaker>,
+}
+impl UpstreamGroup {
+    /// Starts the pending health checks, once a runtime is available. They
+    /// stop when the group is dropped with its server.
+    fn start_health_checks(self: &Arc<Self>) {
+        if tokio::runtime::Handle::try_current().is_err() {
+            return;
+        }
+        let Some(check) = self.health_check.lock().unwrap().take() else {
+            return;
+        };
+        tokio::spawn(run_health_checks(Arc::downgrade(self), check));
+    }
+
+    /// Picks the backend for a request, skipping the ones already `tried`
+    /// for it. Upstreams that keep failing are left out for a while unless
+    /// all of them are.
+    fn pick(&self, tried: &[usize]) -> Option<usize> {
+        let count = self.backends.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let untried: Vec<usize> = (0..count)
+            .map(|offset| (start + offset) % count)
+            .filter(|index| !tried.contains(index))
+            .collect();
+        let available: Vec<usize> = untried
+            .iter()
+            .copied()
+            .filter(|&index| self.backends[index].is_available())
+            .collect();
+        let candidates = if available.is_empty() {
+            untried
+        } else {
+            available
+        };
+        match self.balancing {
+            LoadBalancing::RoundRobin => candidates.first().copied(),
+            LoadBalancing::LeastConnections => candidates
+                .into_iter()
+                .min_by_key(|&index| self.backends[index].active.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl Server {
+    /// Forwards every request under `path_prefix` to the http server at
+    /// `upstream_url`, with the prefix replaced by the url's path.
+    ///
+    /// `server.proxy("api", "http://127.0.0.1:9000/v1")` sends
+    /// `/api/users?page=2` to `http://127.0.0.1:9000/v1/users?page=2`.
+    /// `X-Forwarded-For` and `X-Forwarded-Proto` are added, the upstream
+    /// response is streamed back and unreachable upstreams get a 502.
+    ///
+    /// Fails when `upstream_url` isn't an `http://` url.
+    pub fn proxy(&mut self, path_prefix: String, upstream_url: &str) -> io::Result<()> {
+        self.proxy_balanced(path_prefix, &[upstream_url], LoadBalancing::RoundRobin)
+    }
+
+    /// Forwards every request under `path_prefix` to one of several
+    /// upstreams picked by `balancing`, otherwise like `proxy`.
+    ///
+    /// Connections to the upstreams are kept open and reused. An upstream
+    /// that fails 3 requests in a row is left out for 10 seconds, and
+    /// requests that couldn't connect to one are sent to the next.
+    ///
+    /// Fails when `upstream_urls` is empty or one isn't an `http://` url.
+    pub fn proxy_balanced(
+        &mut self,
+        path_prefix: String,
+        upstream_urls: &[&str],
+        balancing: LoadBalancing,
+    ) -> io::Result<()> {
+        if upstream_urls.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a proxy needs at least one upstream",
+            ));
+        }
+        let mut backends = Vec::new();
+        for upstream_url in upstream_urls {
+            let upstream = Upstream::parse(upstream_url).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid upstream url {upstream_url}"),
+                )
+            })?;
+            backends.push(Arc::new(Backend {
+                upstream,
+                active: AtomicUsize::new(0),
+                failures: AtomicUsize::new(0),
+                down_until: Mutex::new(None),
+                healthy: AtomicBool::new(true),
+            }));
+        }
+        let group = Arc::new(UpstreamGroup {
+            backends,
+            balancing,
+            next: AtomicUsize::new(0),
+            health_check: Mutex::new(None),
+            circuit_breaker: OnceLock::new(),
+        });
+        let client = Client::new().with_timeout(UPSTREAM_TIMEOUT);
+        let prefix = normalize_path(path_prefix);
+        let prefix = Arc::new(prefix.trim_end_matches('/').to_string());
+        self.registry
+            .proxies
+            .insert(prefix.to_string(), group.clone());
+
+        for verb in [
+            HttpVerb::GET,
+            HttpVerb::HEAD,
+            HttpVerb::POST,
+            HttpVerb::PUT,
+            HttpVerb::PATCH,
+            HttpVerb::DELETE,
+            HttpVerb::OPTIONS,
+        ] {
+            let client = client.clone();
+            let group = group.clone();
+            let prefix = prefix.clone();
+            self.register_endpoint(verb, format!("{}/*", prefix), move |request| {
+                let client = client.clone();
+                let group = group.clone();
+                let prefix = prefix.clone();
+                async move { forward(&client, &group, &prefix, request).await }
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks the upstreams of the proxy at `path_prefix` in the
+    /// background, so requests only go to the ones passing the checks.
+    /// A check passes when the upstream answers with a 2xx or 3xx status.
+    ///
+    /// ```ignore
+    /// server.proxy_balanced(String::from("api"), &[first, second], LoadBalancing::RoundRobin)?;
+    /// server.proxy_health_check(
+    ///     String::from("api"),
+    ///     UpstreamHealthCheck::new("/health").with_interval(Duration::from_secs(5)),
+    /// )?;
+    /// ```
+    ///
+    /// Fails when no proxy was added at `path_prefix`.
+    pub fn proxy_health_check(
+        &mut self,
+        path_prefix: String,
+        check: UpstreamHealthCheck,
+    ) -> io::Result<()> {
+        let prefix = normalize_path(path_prefix);
+        let group = self
+            .registry
+            .proxies
+            .get(prefix.trim_end_matches('/'))
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, format!("no proxy at {prefix}"))
+            })?;
+        *group.health_check.lock().unwrap() = Some(check);
+        // without a runtime yet they start with the first request
+        group.start_health_checks();
+        Ok(())
+    }
+
+    /// Sends the requests of the proxy at `path_prefix` through
+    /// `breaker`, so upstreams that keep failing get no requests for a
+    /// while. Requests for which every upstream's circuit is open get a 503
+    /// with `Retry-After` right away.
+    ///
+    /// ```ignore
+    /// let breaker = CircuitBreaker::new(5, Duration::from_secs(30));
+    /// server.proxy_circuit_breaker(String::from("api"), breaker.clone())?;
+    /// server.get(String::from("metrics/circuits"), move |_request| {
+    ///     let stats = breaker.stats();
+    ///     async move { Response::new(StatusCode::OK).with_body(format!("{:?}", stats)) }
+    /// });
+    /// ```
+    ///
+    /// Fails when no proxy was added at `path_prefix` or it already has a
+    /// circuit breaker.
+    pub fn proxy_circuit_breaker(
+        &mut self,
+        path_prefix: String,
+        breaker: CircuitBreaker,
+    ) -> io::Result<()> {
+        let prefix = normalize_path(path_prefix);
+        let group = self
+            .registry
+            .proxies
+            .get(prefix.trim_end_matches('/'))
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, format!("no proxy at {prefix}"))
+            })?;
+        group.circuit_breaker.set(breaker).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("the proxy at {prefix} already has a circuit breaker"),
+            )
+        })
+    }
+}
+
+/// Checks every upstream of the group each interval, marking them down
+/// and up again as they fail and pass enough checks in a row.
+async fn run_health_checks(group: Weak<UpstreamGroup>, check: UpstreamHealthCheck) {
+    // new connections, so checks also find upstreams refusing them
+    let client = Client::new()
+        .with_timeout(check.timeout)
+        .with_max_idle_connections(0);
+    // checks passed or failed in a row by every upstream
+    let mut streaks: Vec<(u32, u32)> = Vec::new();
+    let mut interval = tokio::time::interval(check.interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let Some(group) = group.upgrade() else {
+            return;
+        };
+        streaks.resize(group.backends.len(), (0, 0));
+        let probes = group.backends.iter().map(|backend| {
+            let url = format!("http://{}{}", backend.upstream.authority(), check.path);
+            let request = client.get(&url);
+            async move {
+                match request.send().await {
+                    Ok(response) => response.status.as_u16() < 400,
+                    Err(_) => false,
+                }
+            }
+        });
+        let results = futures_util::future::join_all(probes).await;
+
+        for ((backend, passed), streak) in group.backends.iter().zip(results).zip(&mut streaks) {
+            *streak = if passed {
+                (streak.0 + 1, 0)
+            } else {
+                (0, streak.1 + 1)
+            };
+            let healthy = backend.healthy.load(Ordering::Relaxed);
+            if healthy && streak.1 >= check.unhealthy_threshold {
+                backend.healthy.store(false, Ordering::Relaxed);
+                warn!(upstream = %backend.upstream.authority(), "upstream failed its health checks");
+            } else if !healthy && streak.0 >= check.healthy_threshold {
+                backend.healthy.store(true, Ordering::Relaxed);
+                // a recovered upstream starts with a clean slate
+                backend.succeeded();
+                info!(upstream = %backend.upstream.authority(), "upstream passes its health checks again");
+            }
+        }
+    }
+}
+
+/// Sends `request` to an upstream of the group and streams its answer
+/// back.
+async fn forward(
+    client: &Client,
+    group: &Arc<UpstreamGroup>,
+    prefix: &str,
+    request: Request,
+) -> Response {
+    group.start_health_checks();
+
+    // forward the path as the client encoded it
+    let rest = match request.raw_path.strip_prefix(prefix) {
+        Some(rest) => rest.to_string(),
+        None => request
+            .path
+            .strip_prefix(prefix)
+            .unwrap_or("")
+            .split('/')
+            .map(percent_encode)
+            .collect::<Vec<_>>()
+            .join("/"),
+    };
+    // and the query byte for byte, with repeated keys and their order
+    let query = if request.raw_query.is_empty() {
+        String::new()
+    } else {
+        format!("?{}", request.raw_query)
+    };
+
+    let client_options = connection_options(&request.headers);
+    let mut headers: HeaderMap = request
+        .headers
+        .iter()
+        .filter(|(name, _)| {
+            !is_hop_by_hop(name, &client_options)
+                && !name.eq_ignore_ascii_case("x-forwarded-for")
+                && !name.eq_ignore_ascii_case("x-forwarded-proto")
+        })
+        .cloned()
+        .collect();
+    // append the client to the addresses earlier proxies added
+    let forwarded_for = match (
+        request.headers.get_joined("x-forwarded-for"),
+        request.remote_addr,
+    ) {
+        (Some(previous), Some(addr)) => Some(format!("{}, {}", previous, addr.ip())),
+        (Some(previous), None) => Some(previous),
+        (None, Some(addr)) => Some(addr.ip().to_string()),
+        (None, None) => None,
+    };
+    if let Some(forwarded_for) = forwarded_for {
+        headers.append("x-forwarded-for", forwarded_for);
+    }
+    headers.append("x-forwarded-proto", request.scheme.to_string());
+
+    let breaker = group.circuit_breaker.get();
+    let mut tried = Vec::new();
+    let mut rejected = false;
+    let (upstream_response, active) = loop {
+        let Some(index) = group.pick(&tried) else {
+            if let (true, Some(breaker)) = (rejected, breaker) {
+                // every upstream left had its circuit open
+                let retry_after = breaker.open_duration().as_secs().max(1);
+                return Response::new(StatusCode::SERVICE_UNAVAILABLE)
+                    .with_header("Retry-After", retry_after.to_string());
+            }
+            return Response::new(StatusCode::BAD_GATEWAY);
+        };
+        tried.push(index);
+        let backend = &group.backends[index];
+        let upstream = &backend.upstream;
+        let permit = match breaker {
+            Some(breaker) => match breaker.try_acquire(&upstream.authority()) {
+                Some(permit) => Some(permit),
+                None => {
+                    rejected = true;
+                    continue;
+                }
+            },
+            None => None,
+        };
+        let active = ActiveRequest::start(backend.clone());
+        let target = normalize_path(format!("{}{}", upstream.base_path, rest));
+        let url = format!("http://{}{}{}", upstream.authority(), target, query);
+        let result = client
+            .request(request.verb.clone(), &url)
+            .headers(headers.clone())
+            .body(request.body_raw.clone())
+            .send()
+            .await;
+        if let Some(permit) = permit {
+            match &result {
+                Ok(response) if response.status.as_u16() < 500 => permit.succeeded(),
+                _ => permit.failed(),
+            }
+        }
+        match result {
+            Ok(response) => {
+                backend.succeeded();
+                break (response, active);
+            }
+            Err(ClientError::Connect(e)) => {
+                // nothing was sent, so the next upstream can take it
+                warn!(upstream = %upstream.authority(), error = %e, "connecting to upstream failed");
+                backend.failed();
+            }
+            Err(ClientError::Timeout) => {
+                warn!(upstream = %upstream.authority(), "upstream timed out");
+                backend.failed();
+                return Response::new(StatusCode::GATEWAY_TIMEOUT);
+            }
+            Err(e) => {
+                warn!(upstream = %upstream.authority(), error = %e, "upstream request failed");
+                backend.failed();
+                return Response::new(StatusCode::BAD_GATEWAY);
+            }
+        }
+    };
+
+    let status = upstream_response.status;
+    let has_body =
+        request.verb != HttpVerb::HEAD && !matches!(status.as_u16(), 100..=199 | 204 | 304);
+    let upstream_headers = upstream_response.headers.clone();
+    let mut response = if has_body {
+        // the request counts as active until its body was streamed back
+        let body = upstream_response.into_stream().scan(active, |_, chunk| {
+            futures_util::future::ready(match chunk {
+                Ok(chunk) => Some(chunk),
+                Err(e) => {
+                    // the client sees a truncated body
+                    warn!(error = %e, "reading upstream body failed");
+                    None
+                }
+            })
+        });
+        Response::stream(body).with_status(status)
+    } else {
+        Response::new(status)
+    };
+    // the upstream's headers replace the defaults of `Response::stream`
+    response.headers.clear();
+    let upstream_options = connection_options(&upstream_headers);
+    for (name, value) in upstream_headers {
+        // streamed bodies are re-framed, HEAD responses keep the length
+        let reframed = has_body && name.eq_ignore_ascii_case("content-length");
+        if !is_hop_by_hop(&name, &upstream_options) && !reframed {
+            response.headers.append(name, value);
+        }
+    }
+    response
+}
+
+/// The header names listed in `Connection`, lowercase. They only apply to
+/// the connection they came on, like the fixed hop-by-hop headers.
+fn connection_options(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all("connection")
+        .flat_map(|value| value.split(','))
+        .map(|option| option.trim().to_ascii_lowercase())
+        .filter(|option| !option.is_empty())
+        .collect()
+}
+
+/// Whether a header only applies to one connection and isn't forwarded,
+/// see `connection_options`.
+fn is_hop_by_hop(name: &str, connection_options: &[String]) -> bool {
+    let name = name.to_ascii_lowercase();
+    HOP_BY_HOP_HEADERS.contains(&name.as_str()) || connection_options.contains(&name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// An upstream answering every request with its request head as the
+    /// body, and extra `response_headers`.
+    async fn echo_upstream(response_headers: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut head = Vec::new();
+                    let mut buffer = [0; 1024];
+                    while !head.ends_with(b"\r\n\r\n") {
+                        match socket.read(&mut buffer).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(read) => head.extend_from_slice(&buffer[..read]),
+                        }
+                    }
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n{}\r\n",
+                        head.len(),
+                        response_headers
+                    );
+                    let _ = socket.write_all(response.as_bytes()).await;
+                    let _ = socket.write_all(&head).await;
+                });
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    /// What the upstream got for `target`, sent through a proxy mounted at
+    /// `/api` with `headers`.
+    async fn proxied(target: &str, headers: &[(&str, &str)]) -> String {
+        let upstream = echo_upstream("").await;
+        let mut server = Server::new(0);
+        server.proxy(String::from("/api"), &upstream).unwrap();
+        let mut request = server.test().get(target);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.send().await.text()
+    }
+
+    #[tokio::test]
+    async fn forwards_query_as_sent() {
+        let head = proxied("/api/search?a=1&a=2&b=x%2By+z&empty", &[]).await;
+        assert!(
+            head.starts_with("GET /search?a=1&a=2&b=x%2By+z&empty HTTP/1.1\r\n"),
+            "{}",
+            head
+        );
+    }
+
+    #[tokio::test]
+    async fn drops_headers_named_in_connection() {
+        let head = proxied(
+            "/api/",
+            &[
+                ("Connection", "X-Hop, keep-alive"),
+                ("X-Hop", "1"),
+                ("X-Kept", "1"),
+            ],
+        )
+        .await
+        .to_ascii_lowercase();
+        assert!(!head.contains("x-hop"), "{}", head);
+        assert!(head.contains("x-kept: 1"), "{}", head);
+    }
+
+    #[tokio::test]
+    async fn drops_response_headers_named_in_connection() {
+        let upstream =
+            echo_upstream("connection: x-upstream-hop\r\nx-upstream-hop: 1\r\nx-upstream: 1\r\n")
+                .await;
+        let mut server = Server::new(0);
+        server.proxy(String::from("/api"), &upstream).unwrap();
+        let response = server.test().get("/api/").send().await;
+        assert_eq!(response.header("x-upstream-hop"), None);
+        assert_eq!(response.header("x-upstream").map(String::as_str), Some("1"));
+    }
+
+    #[tokio::test]
+    async fn forwards_path_without_query() {
+        let head = proxied("/api/items/a%20b", &[]).await;
+        assert!(
+            head.starts_with("GET /items/a%20b HTTP/1.1\r\n"),
+            "{}",
+            head
+        );
+    }
+}
@@ -0,0 +1,216 @@
+/// A `Range` request header matched against the length of a file.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ByteRange {
+    /// bytes `start..=end` of the file
+    Satisfiable { start: usize, end: usize },
+    /// no part of the range is inside the file, answered with a 416
+    Unsatisfiable,
+}
+
+/// More ranges than this in one header are ignored and the whole file sent,
+/// since lots of tiny ranges cost more to answer than the file itself.
+const MAX_RANGES: usize = 64;
+
+/// Parses a single `bytes=` range like `0-99`, `100-` or the suffix range
+/// `-100` against a file that is `length` bytes long.
+///
+/// Returns `None` when the header should be ignored and the whole file
+/// sent, which includes unknown units, bad syntax and multiple ranges, see
+/// `parse_ranges` for those.
+pub fn parse_range(header: &str, length: usize) -> Option<ByteRange> {
+    let spec = header.trim().strip_prefix("bytes=")?.trim();
+    if spec.contains(',') {
+        return None;
+    }
+    parse_range_spec(spec, length)
+}
+
+/// Parses a `bytes=` range header with one or more comma separated ranges
+/// like `0-99, 200-299, -100` against a file that is `length` bytes long.
+///
+/// Ranges outside the file are left out, and overlapping or adjacent ones
+/// are merged, so the satisfiable ranges come back sorted. When none of
+/// them is satisfiable it's `[ByteRange::Unsatisfiable]`, answered with a
+/// 416. Returns `None` when the header should be ignored like for
+/// `parse_range`, or when it has too many ranges.
+pub fn parse_ranges(header: &str, length: usize) -> Option<Vec<ByteRange>> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+    // empty list elements like in `0-1,,5-6` are allowed
+    let specs: Vec<&str> = spec
+        .split(',')
+        .map(str::trim)
+        .filter(|spec| !spec.is_empty())
+        .collect();
+    if specs.is_empty() || specs.len() > MAX_RANGES {
+        return None;
+    }
+    let mut ranges = Vec::with_capacity(specs.len());
+    for spec in specs {
+        if let ByteRange::Satisfiable { start, end } = parse_range_spec(spec, length)? {
+            ranges.push((start, end));
+        }
+    }
+    if ranges.is_empty() {
+        return Some(vec![ByteRange::Unsatisfiable]);
+    }
+
+    ranges.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some((_, last_end)) if start <= *last_end + 1 => *last_end = end.max(*last_end),
+            _ => merged.push((start, end)),
+        }
+    }
+    Some(
+        merged
+            .into_iter()
+            .map(|(start, end)| ByteRange::Satisfiable { start, end })
+            .collect(),
+    )
+}
+
+/// Parses one range of a `bytes=` header, like `0-99`.
+fn parse_range_spec(spec: &str, length: usize) -> Option<ByteRange> {
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    if start.is_empty() {
+        // suffix range, the last `end` bytes
+        let suffix = parse_position(end)?;
+        if suffix == 0 || length == 0 {
+            return Some(ByteRange::Unsatisfiable);
+        }
+        return Some(ByteRange::Satisfiable {
+            start: length.saturating_sub(suffix),
+            end: length - 1,
+        });
+    }
+
+    let start = parse_position(start)?;
+    let end = match end {
+        "" => None,
+        end => Some(parse_position(end)?),
+    };
+    if end.is_some_and(|end| end < start) {
+        return None;
+    }
+    if start >= length {
+        return Some(ByteRange::Unsatisfiable);
+    }
+    Some(ByteRange::Satisfiable {
+        start,
+        end: end.map_or(length - 1, |end| end.min(length - 1)),
+    })
+}
+
+/// A byte position, only digits since `parse` would also take a sign.
+fn parse_position(position: &str) -> Option<usize> {
+    if position.is_empty() || !position.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    position.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: usize, end: usize) -> ByteRange {
+        ByteRange::Satisfiable { start, end }
+    }
+
+    #[test]
+    fn parses_single_ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Some(range(0, 99)));
+        assert_eq!(parse_range(" bytes= 10 - 19 ", 1000), Some(range(10, 19)));
+        assert_eq!(parse_range("bytes=900-", 1000), Some(range(900, 999)));
+        assert_eq!(parse_range("bytes=-100", 1000), Some(range(900, 999)));
+        // ends past the file are cut to it
+        assert_eq!(parse_range("bytes=990-2000", 1000), Some(range(990, 999)));
+        assert_eq!(parse_range("bytes=-5000", 1000), Some(range(0, 999)));
+    }
+
+    #[test]
+    fn unsatisfiable_ranges_get_a_416() {
+        assert_eq!(
+            parse_range("bytes=1000-", 1000),
+            Some(ByteRange::Unsatisfiable)
+        );
+        assert_eq!(
+            parse_range("bytes=-0", 1000),
+            Some(ByteRange::Unsatisfiable)
+        );
+        assert_eq!(parse_range("bytes=0-", 0), Some(ByteRange::Unsatisfiable));
+        assert_eq!(parse_range("bytes=-10", 0), Some(ByteRange::Unsatisfiable));
+    }
+
+    #[test]
+    fn ignores_ranges_it_does_not_understand() {
+        for header in [
+            "items=0-1",
+            "bytes 0-1",
+            "bytes=",
+            "bytes=5",
+            "bytes=5-1",
+            "bytes=a-b",
+            "bytes=-",
+            "bytes=+1-2",
+            "bytes=1-+2",
+            "bytes=-+5",
+            "bytes=0-1,2-3",
+            "bytes=99999999999999999999999-",
+        ] {
+            assert_eq!(parse_range(header, 1000), None, "{}", header);
+        }
+    }
+
+    #[test]
+    fn parses_several_ranges() {
+        assert_eq!(
+            parse_ranges("bytes=0-9, 20-29, -10", 100),
+            Some(vec![range(0, 9), range(20, 29), range(90, 99)])
+        );
+        assert_eq!(
+            parse_ranges("bytes=0-1,,5-6", 100),
+            Some(vec![range(0, 1), range(5, 6)])
+        );
+        assert_eq!(parse_ranges("bytes=0-9", 100), Some(vec![range(0, 9)]));
+    }
+
+    #[test]
+    fn sorts_and_merges_overlapping_ranges() {
+        assert_eq!(
+            parse_ranges("bytes=50-59, 0-9, 5-14, 15-19", 100),
+            Some(vec![range(0, 19), range(50, 59)])
+        );
+        assert_eq!(
+            parse_ranges("bytes=0-, 10-20", 100),
+            Some(vec![range(0, 99)])
+        );
+    }
+
+    #[test]
+    fn leaves_out_ranges_outside_the_file() {
+        assert_eq!(
+            parse_ranges("bytes=0-9, 500-600", 100),
+            Some(vec![range(0, 9)])
+        );
+        assert_eq!(
+            parse_ranges("bytes=500-600, 700-", 100),
+            Some(vec![ByteRange::Unsatisfiable])
+        );
+    }
+
+    #[test]
+    fn ignores_bad_or_too_many_ranges() {
+        assert_eq!(parse_ranges("bytes=0-9, x", 100), None);
+        assert_eq!(parse_ranges("bytes=,", 100), None);
+        assert_eq!(parse_ranges("lines=0-9", 100), None);
+        let many = (0..=MAX_RANGES)
+            .map(|n| format!("{}-{}", n * 2, n * 2))
+            .collect::<Vec<_>>()
+            .join(",");
+        assert_eq!(parse_ranges(&format!("bytes={}", many), 1000), None);
+    }
+}
@@ -0,0 +1,116 @@
+use crate::{HandlerFuture, Next, Request, Response, StatusCode};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Clients tracked before buckets that are full again get dropped.
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Token bucket settings for `rate_limit`.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    /// requests a client can make at once
+    pub burst: u32,
+    /// how long it takes to earn back one request
+    pub refill_interval: Duration,
+    /// keep a separate bucket for every path a client requests
+    pub per_route: bool,
+}
+impl RateLimit {
+    /// Allows `requests` per `period` for every client, all at once if
+    /// they want.
+    pub fn new(requests: u32, period: Duration) -> RateLimit {
+        let requests = requests.max(1);
+        RateLimit {
+            burst: requests,
+            refill_interval: period / requests,
+            per_route: false,
+        }
+    }
+
+    /// Sets how many requests can be made at once.
+    pub fn with_burst(mut self, burst: u32) -> RateLimit {
+        self.burst = burst.max(1);
+        self
+    }
+
+    /// Limits every path separately instead of all requests of a client.
+    pub fn per_route(mut self) -> RateLimit {
+        self.per_route = true;
+        self
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Middleware that limits how often each client ip can make requests.
+///
+/// Requests over the limit get a 429 with a `Retry-After` header saying
+/// how many seconds until the next request is allowed.
+pub fn rate_limit(
+    limit: RateLimit,
+) -> impl Fn(Request, Next) -> HandlerFuture + Send + Sync + Clone + 'static {
+    let buckets: Arc<Mutex<HashMap<String, Bucket>>> = Arc::new(Mutex::new(HashMap::new()));
+    move |request: Request, next: Next| {
+        let mut key = match request.client_ip() {
+            Some(ip) => ip.to_string(),
+            None => String::from("unknown"),
+        };
+        if limit.per_route {
+            key.push(' ');
+            key.push_str(&request.path);
+        }
+
+        let retry_after = take_token(&mut buckets.lock().unwrap(), key, &limit);
+        match retry_after {
+            None => next.run(request),
+            Some(retry_after) => {
+                // round up so clients don't retry too early
+                let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                let response = Response::new(StatusCode::TOO_MANY_REQUESTS)
+                    .with_header("Retry-After", seconds.max(1).to_string());
+                Box::pin(async move { response })
+            }
+        }
+    }
+}
+
+/// Takes a token from the client's bucket.
+/// Returns how long to wait for the next token when the bucket is empty.
+fn take_token(
+    buckets: &mut HashMap<String, Bucket>,
+    key: String,
+    limit: &RateLimit,
+) -> Option<Duration> {
+    let now = Instant::now();
+    let burst = f64::from(limit.burst);
+    let refill = limit.refill_interval.as_secs_f64();
+    let refilled = |bucket: &Bucket| {
+        if refill == 0.0 {
+            return burst;
+        }
+        let earned = now.duration_since(bucket.updated).as_secs_f64() / refill;
+        (bucket.tokens + earned).min(burst)
+    };
+
+    if buckets.len() >= MAX_TRACKED_CLIENTS && !buckets.contains_key(&key) {
+        // a full bucket is the same as no bucket
+        buckets.retain(|_, bucket| refilled(bucket) < burst);
+    }
+
+    let bucket = buckets.entry(key).or_insert(Bucket {
+        tokens: burst,
+        updated: now,
+    });
+    bucket.tokens = refilled(bucket);
+    bucket.updated = now;
+    if bucket.tokens >= 1.0 {
+        bucket.tokens -= 1.0;
+        None
+    } else {
+        Some(Duration::from_secs_f64((1.0 - bucket.tokens) * refill))
+    }
+}
@@ -0,0 +1,591 @@
+use crate::date::http_date_now;
+use crate::{is_token, percent_encode, Cookie, HeaderMap, MimeTypes};
+use bytes::Bytes;
+use futures_util::Stream;
+use std::collections::HashMap;
+use std::fmt;
+use std::pin::Pin;
+
+/// HTTP status code of a response.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct StatusCode(u16);
+impl StatusCode {
+    pub const CONTINUE: StatusCode = StatusCode(100);
+    pub const SWITCHING_PROTOCOLS: StatusCode = StatusCode(101);
+    pub const PROCESSING: StatusCode = StatusCode(102);
+    pub const EARLY_HINTS: StatusCode = StatusCode(103);
+    pub const OK: StatusCode = StatusCode(200);
+    pub const CREATED: StatusCode = StatusCode(201);
+    pub const ACCEPTED: StatusCode = StatusCode(202);
+    pub const NON_AUTHORITATIVE_INFORMATION: StatusCode = StatusCode(203);
+    pub const NO_CONTENT: StatusCode = StatusCode(204);
+    pub const RESET_CONTENT: StatusCode = StatusCode(205);
+    pub const PARTIAL_CONTENT: StatusCode = StatusCode(206);
+    pub const MULTI_STATUS: StatusCode = StatusCode(207);
+    pub const ALREADY_REPORTED: StatusCode = StatusCode(208);
+    pub const IM_USED: StatusCode = StatusCode(226);
+    pub const MULTIPLE_CHOICES: StatusCode = StatusCode(300);
+    pub const MOVED_PERMANENTLY: StatusCode = StatusCode(301);
+    pub const FOUND: StatusCode = StatusCode(302);
+    pub const SEE_OTHER: StatusCode = StatusCode(303);
+    pub const NOT_MODIFIED: StatusCode = StatusCode(304);
+    pub const USE_PROXY: StatusCode = StatusCode(305);
+    pub const TEMPORARY_REDIRECT: StatusCode = StatusCode(307);
+    pub const PERMANENT_REDIRECT: StatusCode = StatusCode(308);
+    pub const BAD_REQUEST: StatusCode = StatusCode(400);
+    pub const UNAUTHORIZED: StatusCode = StatusCode(401);
+    pub const PAYMENT_REQUIRED: StatusCode = StatusCode(402);
+    pub const FORBIDDEN: StatusCode = StatusCode(403);
+    pub const NOT_FOUND: StatusCode = StatusCode(404);
+    pub const METHOD_NOT_ALLOWED: StatusCode = StatusCode(405);
+    pub const NOT_ACCEPTABLE: StatusCode = StatusCode(406);
+    pub const PROXY_AUTHENTICATION_REQUIRED: StatusCode = StatusCode(407);
+    pub const REQUEST_TIMEOUT: StatusCode = StatusCode(408);
+    pub const CONFLICT: StatusCode = StatusCode(409);
+    pub const GONE: StatusCode = StatusCode(410);
+    pub const LENGTH_REQUIRED: StatusCode = StatusCode(411);
+    pub const PRECONDITION_FAILED: StatusCode = StatusCode(412);
+    pub const CONTENT_TOO_LARGE: StatusCode = StatusCode(413);
+    /// older name of `CONTENT_TOO_LARGE`
+    pub const PAYLOAD_TOO_LARGE: StatusCode = StatusCode(413);
+    pub const URI_TOO_LONG: StatusCode = StatusCode(414);
+    pub const UNSUPPORTED_MEDIA_TYPE: StatusCode = StatusCode(415);
+    pub const RANGE_NOT_SATISFIABLE: StatusCode = StatusCode(416);
+    pub const EXPECTATION_FAILED: StatusCode = StatusCode(417);
+    pub const MISDIRECTED_REQUEST: StatusCode = StatusCode(421);
+    pub const UNPROCESSABLE_CONTENT: StatusCode = StatusCode(422);
+    /// older name of `UNPROCESSABLE_CONTENT`
+    pub const UNPROCESSABLE_ENTITY: StatusCode = StatusCode(422);
+    pub const LOCKED: StatusCode = StatusCode(423);
+    pub const FAILED_DEPENDENCY: StatusCode = StatusCode(424);
+    pub const TOO_EARLY: StatusCode = StatusCode(425);
+    pub const UPGRADE_REQUIRED: StatusCode = StatusCode(426);
+    pub const PRECONDITION_REQUIRED: StatusCode = StatusCode(428);
+    pub const TOO_MANY_REQUESTS: StatusCode = StatusCode(429);
+    pub const REQUEST_HEADER_FIELDS_TOO_LARGE: StatusCode = StatusCode(431);
+    pub const UNAVAILABLE_FOR_LEGAL_REASONS: StatusCode = StatusCode(451);
+    pub const INTERNAL_SERVER_ERROR: StatusCode = StatusCode(500);
+    pub const NOT_IMPLEMENTED: StatusCode = StatusCode(501);
+    pub const BAD_GATEWAY: StatusCode = StatusCode(502);
+    pub const SERVICE_UNAVAILABLE: StatusCode = StatusCode(503);
+    pub const GATEWAY_TIMEOUT: StatusCode = StatusCode(504);
+    pub const HTTP_VERSION_NOT_SUPPORTED: StatusCode = StatusCode(505);
+    pub const VARIANT_ALSO_NEGOTIATES: StatusCode = StatusCode(506);
+    pub const INSUFFICIENT_STORAGE: StatusCode = StatusCode(507);
+    pub const LOOP_DETECTED: StatusCode = StatusCode(508);
+    pub const NOT_EXTENDED: StatusCode = StatusCode(510);
+    pub const NETWORK_AUTHENTICATION_REQUIRED: StatusCode = StatusCode(511);
+
+    pub const fn from_u16(code: u16) -> StatusCode {
+        StatusCode(code)
+    }
+
+    pub fn as_u16(&self) -> u16 {
+        self.0
+    }
+
+    /// Standard reason phrase sent on the status line, from the IANA
+    /// status code registry.
+    pub fn reason_phrase(&self) -> &'static str {
+        match self.0 {
+            100 => "Continue",
+            101 => "Switching Protocols",
+            102 => "Processing",
+            103 => "Early Hints",
+            200 => "OK",
+            201 => "Created",
+            202 => "Accepted",
+            203 => "Non-Authoritative Information",
+            204 => "No Content",
+            205 => "Reset Content",
+            206 => "Partial Content",
+            207 => "Multi-Status",
+            208 => "Already Reported",
+            226 => "IM Used",
+            300 => "Multiple Choices",
+            301 => "Moved Permanently",
+            302 => "Found",
+            303 => "See Other",
+            304 => "Not Modified",
+            305 => "Use Proxy",
+            307 => "Temporary Redirect",
+            308 => "Permanent Redirect",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            402 => "Payment Required",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            406 => "Not Acceptable",
+            407 => "Proxy Authentication Required",
+            408 => "Request Timeout",
+            409 => "Conflict",
+            410 => "Gone",
+            411 => "Length Required",
+            412 => "Precondition Failed",
+            413 => "Content Too Large",
+            414 => "URI Too Long",
+            415 => "Unsupported Media Type",
+            416 => "Range Not Satisfiable",
+            417 => "Expectation Failed",
+            421 => "Misdirected Request",
+            422 => "Unprocessable Content",
+            423 => "Locked",
+            424 => "Failed Dependency",
+            425 => "Too Early",
+            426 => "Upgrade Required",
+            428 => "Precondition Required",
+            429 => "Too Many Requests",
+            431 => "Request Header Fields Too Large",
+            451 => "Unavailable For Legal Reasons",
+            500 => "Internal Server Error",
+            501 => "Not Implemented",
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            504 => "Gateway Timeout",
+            505 => "HTTP Version Not Supported",
+            506 => "Variant Also Negotiates",
+            507 => "Insufficient Storage",
+            508 => "Loop Detected",
+            510 => "Not Extended",
+            511 => "Network Authentication Required",
+            _ => "Unknown",
+        }
+    }
+}
+impl Default for StatusCode {
+    fn default() -> Self {
+        StatusCode::OK
+    }
+}
+impl From<u16> for StatusCode {
+    fn from(code: u16) -> Self {
+        StatusCode(code)
+    }
+}
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.0, self.reason_phrase())
+    }
+}
+
+/// Stream of body chunks for responses whose length isn't known up front.
+pub type BodyStream = Pin<Box<dyn Stream<Item = Bytes> + Send>>;
+
+/// Body of a response.
+pub enum Body {
+    /// the whole body, sent with a `Content-Length`
+    Full(Bytes),
+    /// chunks sent with `Transfer-Encoding: chunked` as they are produced
+    Stream(BodyStream),
+    /// chunks of a body whose length is known up front, like a file, sent
+    /// with a `Content-Length` as they are produced
+    Sized(BodyStream, usize),
+}
+impl Body {
+    /// Length of the body, or `None` for streams of unknown length.
+    pub fn len(&self) -> Option<usize> {
+        match self {
+            Body::Full(bytes) => Some(bytes.len()),
+            Body::Stream(_) => None,
+            Body::Sized(_, length) => Some(*length),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// The chunks of the body, however it was made.
+    pub(crate) fn into_stream(self) -> BodyStream {
+        match self {
+            Body::Full(bytes) => Box::pin(futures_util::stream::once(async move { bytes })),
+            Body::Stream(stream) | Body::Sized(stream, _) => stream,
+        }
+    }
+}
+impl Default for Body {
+    fn default() -> Self {
+        Body::Full(Bytes::new())
+    }
+}
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Body::Full(bytes) => f.debug_tuple("Full").field(bytes).finish(),
+            Body::Stream(_) => f.write_str("Stream"),
+            Body::Sized(_, length) => f.debug_tuple("Sized").field(length).finish(),
+        }
+    }
+}
+impl From<Bytes> for Body {
+    fn from(bytes: Bytes) -> Self {
+        Body::Full(bytes)
+    }
+}
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Body::Full(bytes.into())
+    }
+}
+impl From<String> for Body {
+    fn from(text: String) -> Self {
+        Body::Full(text.into())
+    }
+}
+impl From<&'static str> for Body {
+    fn from(text: &'static str) -> Self {
+        Body::Full(text.into())
+    }
+}
+
+/// A response returned by a handler, serialized by the server when written
+/// to the socket.
+#[derive(Debug, Default)]
+pub struct Response {
+    pub status: StatusCode,
+    /// header names keep the case they were set with
+    pub headers: HeaderMap,
+    /// cookies sent as one `Set-Cookie` header each
+    pub cookies: Vec<Cookie>,
+    pub body: Body,
+}
+/// Every name listed in the `Vary` headers, in order.
+fn vary_names(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all("Vary")
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// `Content-Disposition` value for downloading a file named `filename`.
+fn content_disposition(filename: &str) -> String {
+    // quotes, backslashes and path separators can't be trusted in the
+    // plain parameter, and anything outside ASCII isn't allowed in it
+    let fallback: String = filename
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if !matches!(c, '"' | '\\' | '/') => c,
+            _ => '_',
+        })
+        .collect();
+    if fallback == filename {
+        format!("attachment; filename=\"{}\"", filename)
+    } else {
+        format!(
+            "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+            fallback,
+            percent_encode(filename)
+        )
+    }
+}
+
+impl Response {
+    pub fn new(status: StatusCode) -> Response {
+        Response {
+            status,
+            ..Default::default()
+        }
+    }
+
+    /// 200 response with a `text/plain` body.
+    pub fn text(body: impl Into<String>) -> Response {
+        Response::new(StatusCode::OK)
+            .with_header("Content-Type", "text/plain")
+            .with_body(body.into())
+    }
+
+    /// 200 response that streams its body with chunked transfer-encoding.
+    pub fn stream(stream: impl Stream<Item = Bytes> + Send + 'static) -> Response {
+        Response::new(StatusCode::OK)
+            .with_header("Content-Type", "application/octet-stream")
+            .with_body(Body::Stream(Box::pin(stream)))
+    }
+
+    /// 200 response that browsers save as a file named `filename` instead
+    /// of showing it, with the `Content-Type` guessed from its extension.
+    pub fn attachment(filename: &str, body: impl Into<Body>) -> Response {
+        Response::new(StatusCode::OK)
+            .with_header("Content-Type", MimeTypes::default().for_path(filename))
+            .with_body(body)
+            .with_attachment(filename)
+    }
+
+    /// Makes browsers download the body as a file named `filename`, like
+    /// for a `Response::file`. Names outside ASCII are sent encoded as RFC
+    /// 5987 describes, with an ASCII fallback for old clients.
+    pub fn with_attachment(self, filename: &str) -> Response {
+        self.with_header("Content-Disposition", content_disposition(filename))
+    }
+
+    /// 302 response sending the client to `location`.
+    pub fn redirect(location: impl Into<String>) -> Response {
+        Response::redirect_with(StatusCode::FOUND, location)
+    }
+
+    /// 301 response telling the client `location` replaces this url.
+    pub fn permanent_redirect(location: impl Into<String>) -> Response {
+        Response::redirect_with(StatusCode::MOVED_PERMANENTLY, location)
+    }
+
+    /// Redirect with any status, like `TEMPORARY_REDIRECT` or
+    /// `PERMANENT_REDIRECT` which make clients keep the request method.
+    pub fn redirect_with(status: StatusCode, location: impl Into<String>) -> Response {
+        Response::new(status).with_header("Location", location)
+    }
+
+    pub fn with_status(mut self, status: StatusCode) -> Response {
+        self.status = status;
+        self
+    }
+
+    /// Sets a header, replacing any existing header with the same name.
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Response {
+        self.set_header(name, value);
+        self
+    }
+
+    /// Adds a header, keeping any existing headers with the same name.
+    pub fn with_appended_header(
+        mut self,
+        name: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Response {
+        self.append_header(name, value);
+        self
+    }
+
+    /// Notes that the response depends on the request header `name`, see
+    /// `add_vary`.
+    pub fn with_vary(mut self, name: &str) -> Response {
+        self.add_vary(name);
+        self
+    }
+
+    /// Adds a cookie to send with the response.
+    pub fn with_cookie(mut self, cookie: Cookie) -> Response {
+        self.cookies.push(cookie);
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Body>) -> Response {
+        self.body = body.into();
+        self
+    }
+
+    /// Looks up a header by name, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&String> {
+        self.headers.get(name)
+    }
+
+    /// Sets a header, replacing any existing header with the same name.
+    pub fn set_header(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.headers.insert(name, value);
+    }
+
+    /// Adds a header, keeping any existing headers with the same name.
+    pub fn append_header(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.headers.append(name, value);
+    }
+
+    /// Adds `name` to the `Vary` header, telling caches the response
+    /// depends on that request header. Names already listed aren't added
+    /// again, and nothing is added to `Vary: *`.
+    pub fn add_vary(&mut self, name: &str) {
+        let mut names = vary_names(&self.headers);
+        if names
+            .iter()
+            .any(|listed| listed == "*" || listed.eq_ignore_ascii_case(name))
+        {
+            return;
+        }
+        names.push(name.to_string());
+        self.set_header("Vary", names.join(", "));
+    }
+
+    /// Drops the body for a HEAD response while keeping the headers that
+    /// describe it, like `Content-Type` and `Content-Length`.
+    pub(crate) fn strip_body(&mut self) {
+        self.fill_default_headers();
+        match self.body.len() {
+            Some(_) if self.status == StatusCode::NOT_MODIFIED => {}
+            Some(length) => {
+                if self.header("Content-Length").is_none() {
+                    self.set_header("Content-Length", length.to_string());
+                }
+            }
+            None => self.set_header("Transfer-Encoding", "chunked"),
+        }
+        self.body = Body::default();
+    }
+
+    /// Adds headers every response should have regardless of protocol.
+    pub(crate) fn fill_default_headers(&mut self) {
+        // handlers and middleware may each have appended their own
+        if self.headers.get_all("Vary").nth(1).is_some() {
+            let mut names: Vec<String> = Vec::new();
+            for name in vary_names(&self.headers) {
+                if !names
+                    .iter()
+                    .any(|listed| listed.eq_ignore_ascii_case(&name))
+                {
+                    names.push(name);
+                }
+            }
+            let vary = if names.iter().any(|name| name == "*") {
+                String::from("*")
+            } else {
+                names.join(", ")
+            };
+            self.set_header("Vary", vary);
+        }
+        if self.header("Date").is_none() {
+            self.set_header("Date", http_date_now());
+        }
+        if !self.body.is_empty() && self.header("Content-Type").is_none() {
+            self.set_header("Content-Type", "text/plain");
+        }
+    }
+
+    /// Fills in the framing headers and serializes the status line and
+    /// headers into raw HTTP/1.1 bytes. The body is written separately.
+    ///
+    /// Streams of unknown length are marked as chunked unless `chunked`
+    /// is false, then the connection closing ends the body.
+    pub(crate) fn head_bytes(&mut self, chunked: bool) -> Vec<u8> {
+        self.fill_default_headers();
+        match self.body.len() {
+            // a 304 never has a body, not even an empty one
+            Some(_) if self.status == StatusCode::NOT_MODIFIED => {}
+            Some(length) => {
+                let has_length = self.header("Content-Length").is_some()
+                    || self.header("Transfer-Encoding").is_some();
+                if !has_length {
+                    self.set_header("Content-Length", length.to_string());
+                }
+            }
+            None => {
+                self.headers.retain(|k, _| {
+                    !k.eq_ignore_ascii_case("Content-Length")
+                        && !k.eq_ignore_ascii_case("Transfer-Encoding")
+                });
+                if chunked {
+                    self.set_header("Transfer-Encoding", "chunked");
+                }
+            }
+        }
+
+        let mut head = format!("HTTP/1.1 {}\r\n", self.status);
+        for (name, value) in self.headers.iter() {
+            // a line break from a handler would start a header of its own
+            if !is_token(name) {
+                continue;
+            }
+            let value: String = value
+                .chars()
+                .filter(|c| *c == '\t' || !c.is_ascii_control())
+                .collect();
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        for cookie in self.cookies.iter() {
+            head.push_str(&format!("Set-Cookie: {}\r\n", cookie));
+        }
+        head.push_str("\r\n");
+        head.into_bytes()
+    }
+}
+
+/// Anything a handler can return.
+pub trait IntoResponse {
+    fn into_response(self) -> Response;
+}
+
+impl IntoResponse for Response {
+    fn into_response(self) -> Response {
+        self
+    }
+}
+
+impl IntoResponse for StatusCode {
+    fn into_response(self) -> Response {
+        Response::new(self)
+    }
+}
+
+impl IntoResponse for String {
+    fn into_response(self) -> Response {
+        Response::text(self)
+    }
+}
+
+impl IntoResponse for &'static str {
+    fn into_response(self) -> Response {
+        Response::text(self)
+    }
+}
+
+impl IntoResponse for Vec<u8> {
+    fn into_response(self) -> Response {
+        Response::new(StatusCode::OK)
+            .with_header("Content-Type", "application/octet-stream")
+            .with_body(self)
+    }
+}
+
+impl IntoResponse for () {
+    fn into_response(self) -> Response {
+        Response::new(StatusCode::OK)
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for (StatusCode, T) {
+    fn into_response(self) -> Response {
+        self.1.into_response().with_status(self.0)
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for (StatusCode, HashMap<String, String>, T) {
+    fn into_response(self) -> Response {
+        let mut response = self.2.into_response().with_status(self.0);
+        for (name, value) in self.1 {
+            response.set_header(name, value);
+        }
+        response
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for (StatusCode, HeaderMap, T) {
+    /// Replaces the body's headers with the same names, keeping every
+    /// value of repeated headers.
+    fn into_response(self) -> Response {
+        let mut response = self.2.into_response().with_status(self.0);
+        for (name, _) in self.1.iter() {
+            response.headers.remove(name);
+        }
+        response.headers.extend(self.1);
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_values_cannot_start_new_headers() {
+        let mut response = Response::text("ok")
+            .with_header("Location", "/next\r\nSet-Cookie: admin=1")
+            .with_header("Bad\r\nName", "x")
+            .with_cookie(Cookie::new("a", "1\r\n\r\n<script>"));
+        let head = String::from_utf8(response.head_bytes(false)).unwrap();
+        assert!(head.contains("Location: /nextSet-Cookie: admin=1\r\n"));
+        assert!(head.contains("Set-Cookie: a=1<script>\r\n"));
+        assert!(!head.contains("Bad"));
+        // only the blank line that ends the head
+        assert!(head.ends_with("\r\n\r\n"));
+        assert_eq!(head.matches("\r\n\r\n").count(), 1);
+    }
+}
@@ -0,0 +1,675 @@
+use crate::{
+    parse_bearer_token, parse_query, percent_encode, Body, HandlerFuture, HeaderMap, HttpVerb,
+    Next, Request, Response, StatusCode,
+};
+use bytes::Bytes;
+use futures_util::FutureExt;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// A stored response and the request headers it was stored for.
+#[derive(Debug)]
+struct CachedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    /// values of the headers named by the response's `Vary`
+    vary: Vec<(String, Option<String>)>,
+    stored_at: Instant,
+    expires_at: Instant,
+    /// how long after expiring it's sent while a new one is fetched
+    stale_while_revalidate: Duration,
+    /// how long after expiring it's sent when the handler fails
+    stale_if_error: Duration,
+    /// set while a new response is fetched in the background
+    revalidating: bool,
+    last_used: Instant,
+}
+impl CachedResponse {
+    /// When the response can't be sent anymore, not even stale.
+    fn usable_until(&self) -> Instant {
+        self.expires_at + self.stale_while_revalidate.max(self.stale_if_error)
+    }
+
+    fn matches(&self, headers: &HeaderMap) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| headers.get_joined(name) == *value)
+    }
+
+    fn to_response(&self) -> Response {
+        let mut response = Response::new(self.status);
+        response.headers = self.headers.clone();
+        response.set_header("Age", self.stored_at.elapsed().as_secs().to_string());
+        response.body = Body::Full(self.body.clone());
+        response
+    }
+}
+
+/// Hit and miss counters of a `ResponseCache`.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct ResponseCacheStats {
+    /// requests answered from the cache
+    pub hits: u64,
+    /// requests passed on to the handler
+    pub misses: u64,
+    /// requests answered with an expired response, while it's fetched
+    /// again or because the handler failed
+    pub stale: u64,
+    /// responses stored right now
+    pub entries: usize,
+}
+
+/// What the cache has for a request.
+enum Lookup {
+    Fresh(Response),
+    /// expired, but can be sent while a new one is fetched, which is up to
+    /// the caller when `revalidate` is set
+    Revalidate {
+        response: Response,
+        revalidate: bool,
+    },
+    /// expired, but can be sent if the handler fails
+    Stale(Response),
+    Miss,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    /// responses by method and url, one for every `Vary` variant
+    entries: Mutex<HashMap<String, Vec<CachedResponse>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    stale: AtomicU64,
+}
+
+/// Keeps the responses of GET routes in memory for a while, for
+/// expensive dynamic endpoints, see `middleware`.
+///
+/// Responses are stored by method, path and query, with a variant for
+/// every combination of the request headers their `Vary` header names.
+/// Only complete 200 responses without cookies are stored, and not when
+/// the request has an `Authorization` header or the response says
+/// `no-store` or `private`. A `max-age` or `s-maxage` in the response's
+/// `Cache-Control` replaces the default time to live.
+///
+/// Expired responses can still be sent for a while, as their
+/// `stale-while-revalidate` and `stale-if-error` directives or
+/// `with_stale_while_revalidate` and `with_stale_if_error` allow. Within
+/// the first the stale response is sent right away while a new one is
+/// fetched in the background, within the second it's sent instead of a
+/// 500, 502, 503 or 504 or a panic of the handler.
+///
+/// Clones share their entries, so a clone kept around can report the
+/// counters.
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    ttl: Duration,
+    stale_while_revalidate: Duration,
+    stale_if_error: Duration,
+    max_entries: usize,
+    shared: Arc<Shared>,
+}
+impl ResponseCache {
+    /// Keeps responses for `ttl` and at most 1000 of them.
+    pub fn new(ttl: Duration) -> ResponseCache {
+        ResponseCache {
+            ttl,
+            stale_while_revalidate: Duration::ZERO,
+            stale_if_error: Duration::ZERO,
+            max_entries: 1000,
+            shared: Arc::new(Shared::default()),
+        }
+    }
+
+    /// Sets how many responses are kept, the least recently used ones
+    /// make room for new ones.
+    pub fn with_max_entries(mut self, max_entries: usize) -> ResponseCache {
+        self.max_entries = max_entries.max(1);
+        self
+    }
+
+    /// How long expired responses are sent while a new one is fetched,
+    /// for responses without a `stale-while-revalidate` directive.
+    pub fn with_stale_while_revalidate(mut self, duration: Duration) -> ResponseCache {
+        self.stale_while_revalidate = duration;
+        self
+    }
+
+    /// How long expired responses are sent when the handler fails, for
+    /// responses without a `stale-if-error` directive.
+    pub fn with_stale_if_error(mut self, duration: Duration) -> ResponseCache {
+        self.stale_if_error = duration;
+        self
+    }
+
+    pub fn stats(&self) -> ResponseCacheStats {
+        let entries = self.shared.entries.lock().unwrap();
+        ResponseCacheStats {
+            hits: self.shared.hits.load(Ordering::Relaxed),
+            misses: self.shared.misses.load(Ordering::Relaxed),
+            stale: self.shared.stale.load(Ordering::Relaxed),
+            entries: entries.values().map(Vec::len).sum(),
+        }
+    }
+
+    /// Removes the responses stored for `target`, a path with its query
+    /// string like `/reports/7?page=2`, returning how many there were.
+    pub fn purge(&self, target: &str) -> usize {
+        let target = normalize_target(target);
+        self.purge_where(|stored| stored == target)
+    }
+
+    /// Removes the responses of every url starting with `prefix`, like
+    /// `/reports/`.
+    pub fn purge_prefix(&self, prefix: &str) -> usize {
+        self.purge_where(|stored| stored.starts_with(prefix))
+    }
+
+    /// Removes the responses of every url `pattern` matches, where `*`
+    /// matches any text including `/`, like `/reports/*/summary*`.
+    pub fn purge_matching(&self, pattern: &str) -> usize {
+        self.purge_where(|stored| wildcard_match(pattern.as_bytes(), stored.as_bytes()))
+    }
+
+    /// Removes every stored response.
+    pub fn purge_all(&self) -> usize {
+        self.purge_where(|_| true)
+    }
+
+    fn purge_where(&self, purge: impl Fn(&str) -> bool) -> usize {
+        let mut entries = self.shared.entries.lock().unwrap();
+        let mut purged = 0;
+        entries.retain(|key, variants| {
+            // keys are `GET /path?query`
+            let target = key
+                .split_once(' ')
+                .map_or(key.as_str(), |(_, target)| target);
+            if purge(target) {
+                purged += variants.len();
+                false
+            } else {
+                true
+            }
+        });
+        purged
+    }
+
+    /// Handler purging the cache over http, for deployments to drop stale
+    /// responses without a restart. Requests need an
+    /// `Authorization: Bearer <token>` header and one of the query
+    /// parameters `key`, `prefix` or `pattern`, used like `purge`,
+    /// `purge_prefix` and `purge_matching`. It answers with the number of
+    /// purged responses as `{"purged": 3}`.
+    ///
+    /// ```ignore
+    /// server.post(String::from("admin/cache/purge"), cache.purge_handler(token));
+    /// // curl -X POST -H "Authorization: Bearer $TOKEN" \
+    /// //     "http://localhost:4221/admin/cache/purge?prefix=/reports/"
+    /// ```
+    pub fn purge_handler(
+        &self,
+        token: impl Into<String>,
+    ) -> impl Fn(Request) -> HandlerFuture + Send + Sync + Clone + 'static {
+        let cache = self.clone();
+        let token: Arc<[u8]> = Sha256::digest(token.into().as_bytes()).to_vec().into();
+        move |request: Request| {
+            let authorized = request
+                .headers
+                .get("authorization")
+                .and_then(|header| parse_bearer_token(header))
+                // compare digests so the time taken doesn't leak the token
+                .is_some_and(|given| *Sha256::digest(given.as_bytes()) == *token);
+            let response = if !authorized {
+                Response::new(StatusCode::UNAUTHORIZED).with_header("WWW-Authenticate", "Bearer")
+            } else if let Some(key) = request.query.get("key") {
+                purged_response(cache.purge(key))
+            } else if let Some(prefix) = request.query.get("prefix") {
+                purged_response(cache.purge_prefix(prefix))
+            } else if let Some(pattern) = request.query.get("pattern") {
+                purged_response(cache.purge_matching(pattern))
+            } else {
+                Response::new(StatusCode::BAD_REQUEST)
+                    .with_body("expected a key, prefix or pattern query parameter")
+            };
+            Box::pin(async move { response })
+        }
+    }
+
+    /// Middleware answering GET requests from the cache, for every route
+    /// with `Server::use_middleware` or single routes with `Endpoint::with`.
+    ///
+    /// ```ignore
+    /// let cache = ResponseCache::new(Duration::from_secs(60));
+    /// server
+    ///     .get(String::from("reports/:id"), build_report)
+    ///     .with(cache.middleware());
+    /// ```
+    ///
+    /// Requests with `Cache-Control: no-cache` skip the stored response
+    /// and replace it, with `no-store` they bypass the cache.
+    pub fn middleware(
+        &self,
+    ) -> impl Fn(Request, Next) -> HandlerFuture + Send + Sync + Clone + 'static {
+        let cache = self.clone();
+        move |request: Request, next: Next| {
+            let cache = cache.clone();
+            Box::pin(async move {
+                if request.verb != HttpVerb::GET || request.headers.contains_key("authorization") {
+                    return next.run(request).await;
+                }
+                let directives = cache_control(&request.headers);
+                if has_directive(&directives, "no-store") {
+                    return next.run(request).await;
+                }
+                let key = cache_key(&request);
+                let lookup = if has_directive(&directives, "no-cache") {
+                    Lookup::Miss
+                } else {
+                    cache.lookup(&key, &request.headers)
+                };
+                let stale = match lookup {
+                    Lookup::Fresh(response) => {
+                        cache.shared.hits.fetch_add(1, Ordering::Relaxed);
+                        return response;
+                    }
+                    Lookup::Revalidate {
+                        response,
+                        revalidate,
+                    } => {
+                        cache.shared.stale.fetch_add(1, Ordering::Relaxed);
+                        if revalidate {
+                            cache.revalidate(key, &request, next);
+                        }
+                        return response;
+                    }
+                    Lookup::Stale(response) => Some(response),
+                    Lookup::Miss => None,
+                };
+                cache.shared.misses.fetch_add(1, Ordering::Relaxed);
+
+                let request_headers = request.headers.clone();
+                let result = AssertUnwindSafe(next.run(request)).catch_unwind().await;
+                match (result, stale) {
+                    (Ok(response), Some(stale)) if is_error(response.status) => {
+                        cache.shared.stale.fetch_add(1, Ordering::Relaxed);
+                        stale
+                    }
+                    (Ok(response), _) => {
+                        cache.store(key, &request_headers, &response);
+                        response
+                    }
+                    (Err(_), Some(stale)) => {
+                        warn!(key, "handler panicked, sending the stale response");
+                        cache.shared.stale.fetch_add(1, Ordering::Relaxed);
+                        stale
+                    }
+                    (Err(panic), None) => std::panic::resume_unwind(panic),
+                }
+            })
+        }
+    }
+
+    /// What is stored for the request.
+    fn lookup(&self, key: &str, headers: &HeaderMap) -> Lookup {
+        let mut entries = self.shared.entries.lock().unwrap();
+        let now = Instant::now();
+        let Some(variant) = entries
+            .get_mut(key)
+            .and_then(|variants| variants.iter_mut().find(|variant| variant.matches(headers)))
+        else {
+            return Lookup::Miss;
+        };
+        variant.last_used = now;
+        if now < variant.expires_at {
+            return Lookup::Fresh(variant.to_response());
+        }
+        let stale_for = now - variant.expires_at;
+        if stale_for < variant.stale_while_revalidate {
+            // only one request fetches it again
+            let revalidate = !variant.revalidating;
+            variant.revalidating = true;
+            return Lookup::Revalidate {
+                response: variant.to_response(),
+                revalidate,
+            };
+        }
+        if stale_for < variant.stale_if_error {
+            return Lookup::Stale(variant.to_response());
+        }
+        Lookup::Miss
+    }
+
+    /// Fetches a new response for the stale one at `key` in the
+    /// background, with a copy of the request. Extensions added by earlier
+    /// middleware aren't copied.
+    fn revalidate(&self, key: String, request: &Request, next: Next) {
+        let cache = self.clone();
+        let request_headers = request.headers.clone();
+        let request = copy_request(request);
+        tokio::spawn(async move {
+            let result = AssertUnwindSafe(next.run(request)).catch_unwind().await;
+            match result {
+                // failures keep the stale response until it can't be used
+                Ok(response) if !is_error(response.status) => {
+                    cache.store(key.clone(), &request_headers, &response);
+                }
+                Ok(response) => {
+                    warn!(
+                        key,
+                        status = response.status.as_u16(),
+                        "revalidating failed"
+                    );
+                }
+                Err(_) => warn!(key, "handler panicked while revalidating"),
+            }
+            // let a later request try again if nothing was stored
+            let mut entries = cache.shared.entries.lock().unwrap();
+            if let Some(variant) = entries.get_mut(&key).and_then(|variants| {
+                variants
+                    .iter_mut()
+                    .find(|variant| variant.matches(&request_headers))
+            }) {
+                variant.revalidating = false;
+            }
+        });
+    }
+
+    /// Stores the response when it's allowed to be.
+    fn store(&self, key: String, request_headers: &HeaderMap, response: &Response) {
+        let Some((ttl, stale_while_revalidate, stale_if_error)) = self.lifetimes(response) else {
+            return;
+        };
+        let Body::Full(body) = &response.body else {
+            return;
+        };
+        let vary: Vec<(String, Option<String>)> = response
+            .headers
+            .get_joined("vary")
+            .unwrap_or_default()
+            .split(',')
+            .map(|name| name.trim().to_ascii_lowercase())
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                let value = request_headers.get_joined(&name);
+                (name, value)
+            })
+            .collect();
+
+        let now = Instant::now();
+        let cached = CachedResponse {
+            status: response.status,
+            headers: response.headers.clone(),
+            body: body.clone(),
+            vary,
+            stored_at: now,
+            expires_at: now + ttl,
+            stale_while_revalidate,
+            stale_if_error,
+            revalidating: false,
+            last_used: now,
+        };
+
+        let mut entries = self.shared.entries.lock().unwrap();
+        let variants = entries.entry(key.clone()).or_default();
+        variants.retain(|variant| variant.vary != cached.vary && variant.usable_until() > now);
+        variants.push(cached);
+        evict(&mut entries, self.max_entries, &key);
+    }
+
+    /// How long the response is fresh, and then can be sent while
+    /// revalidating and on errors. `None` when it may not be stored.
+    fn lifetimes(&self, response: &Response) -> Option<(Duration, Duration, Duration)> {
+        if response.status != StatusCode::OK || !response.cookies.is_empty() {
+            return None;
+        }
+        let vary_all = response
+            .headers
+            .get_joined("vary")
+            .is_some_and(|vary| vary.split(',').any(|name| name.trim() == "*"));
+        let directives = cache_control(&response.headers);
+        if vary_all
+            || response.headers.contains_key("set-cookie")
+            || has_directive(&directives, "no-store")
+            || has_directive(&directives, "private")
+        {
+            return None;
+        }
+        let seconds = |name: &str| {
+            directives
+                .iter()
+                .find(|(directive, _)| directive == name)
+                .and_then(|(_, value)| value.as_deref()?.parse::<u64>().ok())
+                .map(Duration::from_secs)
+        };
+        let ttl = seconds("s-maxage")
+            .or_else(|| seconds("max-age"))
+            .unwrap_or(self.ttl);
+        let stale_while_revalidate =
+            seconds("stale-while-revalidate").unwrap_or(self.stale_while_revalidate);
+        let stale_if_error = seconds("stale-if-error").unwrap_or(self.stale_if_error);
+        if ttl.is_zero() && stale_while_revalidate.is_zero() && stale_if_error.is_zero() {
+            return None;
+        }
+        Some((ttl, stale_while_revalidate, stale_if_error))
+    }
+}
+
+/// Statuses after which a stale response is sent instead, as RFC 5861
+/// defines errors for `stale-if-error`.
+fn is_error(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 500 | 502 | 503 | 504)
+}
+
+/// A copy of `request` for running the handler again in the background,
+/// without its extensions.
+fn copy_request(request: &Request) -> Request {
+    Request {
+        verb: request.verb.clone(),
+        version: request.version,
+        path: request.path.clone(),
+        raw_path: request.raw_path.clone(),
+        query: request.query.clone(),
+        raw_query: request.raw_query.clone(),
+        params: request.params.clone(),
+        headers: request.headers.clone(),
+        cookies: request.cookies.clone(),
+        body: request.body.clone(),
+        body_raw: request.body_raw.clone(),
+        extensions: Default::default(),
+        remote_addr: request.remote_addr,
+        local_addr: request.local_addr,
+        scheme: request.scheme,
+        client_ip: request.client_ip,
+        state: request.state.clone(),
+    }
+}
+
+/// Drops responses that can't be sent anymore, then the least recently used ones until at
+/// most `max_entries` are left. The variants at `keep` were just stored.
+fn evict(entries: &mut HashMap<String, Vec<CachedResponse>>, max_entries: usize, keep: &str) {
+    let count: usize = entries.values().map(Vec::len).sum();
+    if count <= max_entries {
+        return;
+    }
+    let now = Instant::now();
+    for variants in entries.values_mut() {
+        variants.retain(|variant| variant.usable_until() > now);
+    }
+    entries.retain(|_, variants| !variants.is_empty());
+
+    let mut count: usize = entries.values().map(Vec::len).sum();
+    while count > max_entries {
+        let oldest = entries
+            .iter()
+            .filter(|(key, _)| key.as_str() != keep)
+            .flat_map(|(key, variants)| {
+                variants
+                    .iter()
+                    .enumerate()
+                    .map(move |(index, variant)| (variant.last_used, key.clone(), index))
+            })
+            .min();
+        let Some((_, key, index)) = oldest else {
+            break;
+        };
+        let variants = entries.get_mut(&key).unwrap();
+        variants.remove(index);
+        if variants.is_empty() {
+            entries.remove(&key);
+        }
+        count -= 1;
+    }
+}
+
+fn purged_response(purged: usize) -> Response {
+    Response::json(&serde_json::json!({ "purged": purged }))
+}
+
+/// `GET /path?query`, with the query parameters sorted since their
+/// original order isn't kept.
+fn cache_key(request: &Request) -> String {
+    format!(
+        "{} {}",
+        request.verb,
+        encode_target(&request.raw_path, &request.query)
+    )
+}
+
+fn encode_target(raw_path: &str, query: &HashMap<String, String>) -> String {
+    if query.is_empty() {
+        return raw_path.to_string();
+    }
+    let mut pairs: Vec<(&String, &String)> = query.iter().collect();
+    pairs.sort();
+    let query: Vec<String> = pairs
+        .iter()
+        .map(|(name, value)| format!("{}={}", percent_encode(name), percent_encode(value)))
+        .collect();
+    format!("{}?{}", raw_path, query.join("&"))
+}
+
+/// A target given to `purge` the way it's stored, with its query sorted.
+fn normalize_target(target: &str) -> String {
+    match target.split_once('?') {
+        Some((raw_path, query)) => encode_target(raw_path, &parse_query(query)),
+        None => target.to_string(),
+    }
+}
+
+/// Matches `text` against `pattern`, where `*` matches any text.
+///
+/// Only the last `*` is ever backtracked to, a later one can match
+/// everything an earlier one could, so this takes linear time per `*`
+/// instead of trying every split for every star.
+fn wildcard_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // the last `*` seen and where in `text` its match currently ends
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                // let the star take one more character
+                Some((star_p, star_t)) => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+/// The directives of the `Cache-Control` headers, lowercase, with their
+/// values.
+fn cache_control(headers: &HeaderMap) -> Vec<(String, Option<String>)> {
+    headers
+        .get_joined("cache-control")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|directive| {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name, Some(value.trim().trim_matches('"').to_string())),
+                None => (directive, None),
+            };
+            let name = name.trim().to_ascii_lowercase();
+            (!name.is_empty()).then_some((name, value))
+        })
+        .collect()
+}
+
+fn has_directive(directives: &[(String, Option<String>)], name: &str) -> bool {
+    directives.iter().any(|(directive, _)| directive == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn wildcard_matches() {
+        let matches =
+            |pattern: &str, text: &str| wildcard_match(pattern.as_bytes(), text.as_bytes());
+        assert!(matches(
+            "/reports/*/summary*",
+            "/reports/2024/q1/summary?page=2"
+        ));
+        assert!(matches("*", ""));
+        assert!(matches("/a*", "/a"));
+        assert!(matches("*b*", "abc"));
+        assert!(matches("/a**b", "/axxb"));
+        assert!(!matches("/a*b", "/axxbc"));
+        assert!(!matches("/a", "/ab"));
+        assert!(!matches("/ab", "/a"));
+        assert!(!matches("", "/a"));
+    }
+
+    #[test]
+    fn wildcard_match_is_not_exponential() {
+        let pattern = "*a*a*a*a*a*a*a*a*a*a*a*a*b";
+        let text = "a".repeat(10_000);
+        let started = Instant::now();
+        assert!(!wildcard_match(pattern.as_bytes(), text.as_bytes()));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn purges_matching_targets() {
+        let cache = ResponseCache::new(Duration::from_secs(60));
+        let mut server = crate::Server::new(0);
+        server
+            .get(String::from("reports/:id/:kind"), |_| async {
+                Response::new(StatusCode::OK).with_body("report")
+            })
+            .with(cache.middleware());
+        let client = server.test();
+        for target in [
+            "/reports/1/summary",
+            "/reports/2/detail",
+            "/reports/3/summary?page=2",
+        ] {
+            assert_eq!(client.get(target).send().await.status, StatusCode::OK);
+        }
+        assert_eq!(cache.stats().entries, 3);
+
+        assert_eq!(cache.purge_matching("*a*a*a*a*a*a*a*b"), 0);
+        assert_eq!(cache.purge_matching("/reports/*/summary*"), 2);
+        assert_eq!(cache.stats().entries, 1);
+    }
+}
@@ -0,0 +1,141 @@
+use crate::{decode_path, parse_query, percent_encode, Request, Response, Server, StatusCode};
+use regex::Regex;
+
+/// Which requests a `RewriteRule` applies to, matched against the path as
+/// the client sent it, still percent-encoded and without the query.
+#[derive(Debug, Clone)]
+pub enum RewritePattern {
+    /// paths starting with this, the rest of the path is appended to the
+    /// target
+    Prefix(String),
+    /// paths the regex matches, `$1` or `${name}` in the target are
+    /// replaced with its captures
+    Regex(Regex),
+}
+
+/// What a `RewriteRule` does with a matching request.
+#[derive(Debug, Clone)]
+pub enum RewriteAction {
+    /// routes the request as if it was for the target, like nginx's
+    /// `rewrite`
+    Rewrite(String),
+    /// sends the client to the target with a 3xx status, like nginx's
+    /// `return`
+    Redirect(String, StatusCode),
+}
+
+/// A rule checked before routing, see `Server::add_rewrite`.
+///
+/// ```ignore
+/// server.add_rewrite(RewriteRule::prefix("/blog/", RewriteAction::Redirect(
+///     String::from("https://blog.example.com/"),
+///     StatusCode::MOVED_PERMANENTLY,
+/// )));
+/// server.add_rewrite(RewriteRule::regex(
+///     r"^/u/(\d+)$",
+///     RewriteAction::Rewrite(String::from("/users/$1")),
+/// )?);
+/// ```
+#[derive(Debug, Clone)]
+pub struct RewriteRule {
+    pub pattern: RewritePattern,
+    pub action: RewriteAction,
+}
+impl RewriteRule {
+    pub fn prefix(prefix: impl Into<String>, action: RewriteAction) -> RewriteRule {
+        RewriteRule {
+            pattern: RewritePattern::Prefix(prefix.into()),
+            action,
+        }
+    }
+
+    pub fn regex(regex: &str, action: RewriteAction) -> Result<RewriteRule, regex::Error> {
+        Ok(RewriteRule {
+            pattern: RewritePattern::Regex(Regex::new(regex)?),
+            action,
+        })
+    }
+
+    /// The target with the matched parts of `path` filled in, `None` when
+    /// the rule doesn't match.
+    fn target(&self, path: &str) -> Option<String> {
+        let target = match &self.action {
+            RewriteAction::Rewrite(target) | RewriteAction::Redirect(target, _) => target,
+        };
+        match &self.pattern {
+            RewritePattern::Prefix(prefix) => {
+                let rest = path.strip_prefix(prefix.as_str())?;
+                Some(format!("{}{}", target, rest))
+            }
+            RewritePattern::Regex(regex) => {
+                let captures = regex.captures(path)?;
+                let mut expanded = String::new();
+                captures.expand(target, &mut expanded);
+                Some(expanded)
+            }
+        }
+    }
+}
+
+/// Runs the first rule matching `request`. Rewrites change the request's
+/// path and add the target's query parameters, redirects return the
+/// response to send instead of routing.
+pub(crate) fn apply_rewrites(rules: &[RewriteRule], request: &mut Request) -> Option<Response> {
+    let (rule, target) = rules
+        .iter()
+        .find_map(|rule| Some((rule, rule.target(&request.raw_path)?)))?;
+    match &rule.action {
+        RewriteAction::Redirect(_, status) => {
+            // the query carries over unless the target has its own
+            let location = if target.contains('?') || request.query.is_empty() {
+                target
+            } else {
+                format!("{}?{}", target, encode_query(request))
+            };
+            Some(Response::redirect_with(*status, location))
+        }
+        RewriteAction::Rewrite(_) => {
+            let (raw_path, query) = match target.split_once('?') {
+                Some((raw_path, query)) => (raw_path, Some(query)),
+                None => (target.as_str(), None),
+            };
+            let Some(path) = decode_path(raw_path) else {
+                return Some(Response::new(StatusCode::BAD_REQUEST));
+            };
+            request.path = path;
+            request.raw_path = raw_path.to_string();
+            if let Some(query) = query {
+                request.query.extend(parse_query(query));
+                // the target's parameters go last so they win over the
+                // client's like in `query`
+                request.raw_query = if request.raw_query.is_empty() {
+                    query.to_string()
+                } else {
+                    format!("{}&{}", request.raw_query, query)
+                };
+            }
+            None
+        }
+    }
+}
+
+/// The request's query parameters as a query string, sorted since the
+/// original order isn't kept.
+fn encode_query(request: &Request) -> String {
+    let mut pairs: Vec<(&String, &String)> = request.query.iter().collect();
+    pairs.sort();
+    pairs
+        .iter()
+        .map(|(key, value)| format!("{}={}", percent_encode(key), percent_encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+impl Server {
+    /// Adds a rule that rewrites or redirects requests before they are
+    /// routed, even before global middleware. Rules are checked in the
+    /// order they were added and only the first match applies.
+    pub fn add_rewrite(&mut self, rule: RewriteRule) {
+        self.registry.rewrites.push(rule);
+    }
+}
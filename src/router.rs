@@ -0,0 +1,812 @@
+use crate::middleware::into_middleware;
+use crate::{
+    into_handler, normalize_path, Endpoint, EndpointKey, HttpVerb, IntoResponse, Middleware, Next,
+    Request,
+};
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// A group of routes that can be built on its own and mounted on a
+/// `Server` (or another `Router`) under a path prefix.
+#[derive(Default, Clone)]
+pub struct Router {
+    endpoints: HashMap<EndpointKey, Endpoint>,
+    middleware: Vec<Middleware>,
+}
+impl Router {
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    /// Registers a route relative to where the router gets mounted.
+    pub fn register_endpoint<F, Fut>(
+        &mut self,
+        verb: HttpVerb,
+        path: String,
+        handler: F,
+    ) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        let endpoint_key = EndpointKey {
+            verb,
+            path: normalize_path(path),
+        };
+        self.endpoints
+            .entry(endpoint_key)
+            .insert_entry(Endpoint::new(into_handler(handler)))
+            .into_mut()
+    }
+
+    pub fn get<F, Fut>(&mut self, path: String, handler: F) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.register_endpoint(HttpVerb::GET, path, handler)
+    }
+
+    pub fn post<F, Fut>(&mut self, path: String, handler: F) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.register_endpoint(HttpVerb::POST, path, handler)
+    }
+
+    pub fn put<F, Fut>(&mut self, path: String, handler: F) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.register_endpoint(HttpVerb::PUT, path, handler)
+    }
+
+    pub fn patch<F, Fut>(&mut self, path: String, handler: F) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.register_endpoint(HttpVerb::PATCH, path, handler)
+    }
+
+    pub fn delete<F, Fut>(&mut self, path: String, handler: F) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.register_endpoint(HttpVerb::DELETE, path, handler)
+    }
+
+    /// Routes HEAD requests separately, without one they are answered
+    /// by the GET route with the body left out.
+    pub fn head<F, Fut>(&mut self, path: String, handler: F) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.register_endpoint(HttpVerb::HEAD, path, handler)
+    }
+
+    pub fn options<F, Fut>(&mut self, path: String, handler: F) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.register_endpoint(HttpVerb::OPTIONS, path, handler)
+    }
+
+    /// Adds a middleware that runs for every route in this router.
+    pub fn use_middleware<F, Fut>(&mut self, middleware: F)
+    where
+        F: Fn(Request, Next) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.middleware.push(into_middleware(middleware));
+    }
+
+    /// Nests another router under a prefix of this one.
+    pub fn mount(&mut self, prefix: String, router: Router) {
+        for (key, endpoint) in router.into_endpoints(&prefix) {
+            self.endpoints.insert(key, endpoint);
+        }
+    }
+
+    /// Consumes the router, returning its endpoints with the prefix added to
+    /// their paths and the router middleware in front of their own.
+    pub(crate) fn into_endpoints(self, prefix: &str) -> Vec<(EndpointKey, Endpoint)> {
+        let prefix = normalize_path(prefix.to_string());
+        let prefix = prefix.trim_end_matches('/');
+
+        self.endpoints
+            .into_iter()
+            .map(|(key, mut endpoint)| {
+                let path = if key.path == "/" {
+                    normalize_path(prefix.to_string())
+                } else {
+                    format!("{}{}", prefix, key.path)
+                };
+
+                let mut middleware = self.middleware.clone();
+                middleware.extend(endpoint.middleware.iter().cloned());
+                endpoint.middleware = Arc::new(middleware);
+
+                (
+                    EndpointKey {
+                        verb: key.verb,
+                        path,
+                    },
+                    endpoint,
+                )
+            })
+            .collect()
+    }
+}
+
+/// Several verbs of one path registered together, created with
+/// `Server::route`.
+///
+/// ```ignore
+/// server
+///     .route(String::from("items/:id"))
+///     .get(show_item)
+///     .put(update_item)
+///     .any(|_| async { StatusCode::METHOD_NOT_ALLOWED });
+/// ```
+pub struct Route<'a> {
+    endpoints: &'a mut RouteTree,
+    path: String,
+}
+impl<'a> Route<'a> {
+    pub(crate) fn new(endpoints: &'a mut RouteTree, path: String) -> Route<'a> {
+        Route {
+            endpoints,
+            path: normalize_path(path),
+        }
+    }
+
+    /// Routes `verb` requests for the path to `handler`.
+    pub fn on<F, Fut>(self, verb: HttpVerb, handler: F) -> Route<'a>
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        let key = EndpointKey {
+            verb,
+            path: self.path.clone(),
+        };
+        self.endpoints
+            .insert(key, Endpoint::new(into_handler(handler)));
+        self
+    }
+
+    pub fn get<F, Fut>(self, handler: F) -> Route<'a>
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.on(HttpVerb::GET, handler)
+    }
+
+    pub fn post<F, Fut>(self, handler: F) -> Route<'a>
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.on(HttpVerb::POST, handler)
+    }
+
+    pub fn put<F, Fut>(self, handler: F) -> Route<'a>
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.on(HttpVerb::PUT, handler)
+    }
+
+    pub fn patch<F, Fut>(self, handler: F) -> Route<'a>
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.on(HttpVerb::PATCH, handler)
+    }
+
+    pub fn delete<F, Fut>(self, handler: F) -> Route<'a>
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.on(HttpVerb::DELETE, handler)
+    }
+
+    pub fn head<F, Fut>(self, handler: F) -> Route<'a>
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.on(HttpVerb::HEAD, handler)
+    }
+
+    pub fn options<F, Fut>(self, handler: F) -> Route<'a>
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.on(HttpVerb::OPTIONS, handler)
+    }
+
+    /// Routes every verb without a handler of its own to `handler`.
+    pub fn any<F, Fut>(self, handler: F) -> Route<'a>
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.endpoints
+            .insert_any(self.path.clone(), Endpoint::new(into_handler(handler)));
+        self
+    }
+}
+
+/// One `/` separated part of a route pattern.
+enum Segment<'a> {
+    Static(&'a str),
+    /// `:name` or `{name}`
+    Param(&'a str),
+    /// `{name:regex}`, only matches segments the regex matches in full
+    Constrained(&'a str, &'a str),
+    /// `prefix*`, only special as the last segment
+    Wildcard(&'a str),
+}
+
+fn parse_segment(segment: &str, is_last: bool) -> Segment<'_> {
+    if let Some(name) = segment.strip_prefix(':') {
+        return Segment::Param(name);
+    }
+    if let Some(inner) = segment
+        .strip_prefix('{')
+        .and_then(|segment| segment.strip_suffix('}'))
+    {
+        return match inner.split_once(':') {
+            Some((name, regex)) => Segment::Constrained(name, regex),
+            None => Segment::Param(inner),
+        };
+    }
+    match segment.strip_suffix('*') {
+        Some(prefix) if is_last => Segment::Wildcard(prefix),
+        _ => Segment::Static(segment),
+    }
+}
+
+/// Compiles the regex of a `{name:regex}` segment so it has to match the
+/// whole segment. Compiled regexes are cached since patterns are matched
+/// on every request.
+pub(crate) fn constraint_regex(source: &str) -> Result<Regex, regex::Error> {
+    static CACHE: OnceLock<Mutex<HashMap<String, Regex>>> = OnceLock::new();
+    let mut cache = CACHE.get_or_init(Default::default).lock().unwrap();
+    if let Some(regex) = cache.get(source) {
+        return Ok(regex.clone());
+    }
+    let regex = Regex::new(&format!("^(?:{})$", source))?;
+    cache.insert(source.to_string(), regex.clone());
+    Ok(regex)
+}
+
+/// Matches a requested path against a route pattern.
+///
+/// Patterns are split into `/` separated segments where
+/// - `:name` or `{name}` matches any single segment and captures it as
+///   `name`
+/// - `{name:regex}` does the same but only for segments matching the
+///   regex, like `{id:[0-9]+}`
+/// - a trailing `*` (or `prefix*`) matches the rest of the path, which is
+///   captured as `*`
+/// - anything else must match the segment exactly
+///
+/// Empty segments are ignored on both sides, so `//` is treated as `/`.
+/// Returns the captured parameters when the path matches.
+pub(crate) fn match_route(pattern: &str, path: &str) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    let pattern_segments: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+    let path_segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+    for (i, segment) in pattern_segments.iter().enumerate() {
+        let is_last = i == pattern_segments.len() - 1;
+
+        let (name, requested) = match parse_segment(segment, is_last) {
+            Segment::Wildcard(prefix) => {
+                let rest = path_segments[i.min(path_segments.len())..].join("/");
+                if !rest.starts_with(prefix) {
+                    return None;
+                }
+                params.insert(String::from("*"), rest[prefix.len()..].to_string());
+                return Some(params);
+            }
+            Segment::Static(segment) => {
+                if path_segments.get(i) != Some(&segment) {
+                    return None;
+                }
+                continue;
+            }
+            Segment::Param(name) => (name, path_segments.get(i)?),
+            Segment::Constrained(name, regex) => {
+                let requested = path_segments.get(i)?;
+                if !constraint_regex(regex).is_ok_and(|regex| regex.is_match(requested)) {
+                    return None;
+                }
+                (name, requested)
+            }
+        };
+        params.insert(name.to_string(), requested.to_string());
+    }
+
+    if pattern_segments.len() != path_segments.len() {
+        return None;
+    }
+    Some(params)
+}
+
+/// The registered routes as a tree of path segments, so finding the route
+/// for a request only walks the segments of its path.
+///
+/// When several routes match, static segments win over `{name:regex}`
+/// segments, which win over `:param` segments, which win over wildcards,
+/// so `/users/me` beats `/users/{id:[0-9]+}` which beats `/users/:name`
+/// which beats `/users/*`.
+#[derive(Default, Clone)]
+pub struct RouteTree {
+    root: RouteNode,
+}
+
+#[derive(Default, Clone)]
+struct RouteNode {
+    /// children for static segments
+    statics: HashMap<String, RouteNode>,
+    /// children for `{name:regex}` segments, tried in the order they were
+    /// added
+    constrained: Vec<(String, Regex, RouteNode)>,
+    /// child for a `:param` segment, whatever the param is named
+    param: Option<Box<RouteNode>>,
+    /// routes ending in a `prefix*` wildcard, longest prefix first
+    wildcards: Vec<(String, RouteLeaf)>,
+    /// routes ending at this node
+    leaf: RouteLeaf,
+}
+
+/// Routes for the same path pattern, by verb.
+#[derive(Default, Clone)]
+struct RouteLeaf {
+    routes: BTreeMap<HttpVerb, (EndpointKey, Endpoint)>,
+    /// pattern and route for verbs without their own route
+    any: Option<(String, Endpoint)>,
+}
+
+impl RouteTree {
+    pub fn new() -> RouteTree {
+        RouteTree::default()
+    }
+
+    /// Adds a route, replacing any route with the same verb and pattern.
+    pub fn insert(&mut self, key: EndpointKey, endpoint: Endpoint) -> &mut Endpoint {
+        let leaf = self.leaf_mut(&key.path);
+        let verb = key.verb.clone();
+        leaf.routes.insert(verb.clone(), (key, endpoint));
+        &mut leaf.routes.get_mut(&verb).unwrap().1
+    }
+
+    /// Adds a route for every verb of `path` that has no route of its
+    /// own, whether that route was added before or after this one.
+    pub fn insert_any(&mut self, path: String, endpoint: Endpoint) -> &mut Endpoint {
+        let leaf = self.leaf_mut(&path);
+        &mut leaf.any.insert((path, endpoint)).1
+    }
+
+    /// The leaf for a route pattern, adding the nodes leading to it.
+    fn leaf_mut(&mut self, path: &str) -> &mut RouteLeaf {
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let mut node = &mut self.root;
+        let mut leaf = None;
+        for (i, segment) in segments.iter().enumerate() {
+            node = match parse_segment(segment, i == segments.len() - 1) {
+                Segment::Wildcard(prefix) => {
+                    let index = match node.wildcards.iter().position(|(p, _)| p == prefix) {
+                        Some(index) => index,
+                        None => {
+                            node.wildcards
+                                .push((prefix.to_string(), RouteLeaf::default()));
+                            node.wildcards
+                                .sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+                            node.wildcards
+                                .iter()
+                                .position(|(p, _)| p == prefix)
+                                .unwrap()
+                        }
+                    };
+                    leaf = Some(&mut node.wildcards[index].1);
+                    break;
+                }
+                Segment::Static(segment) => node.statics.entry(segment.to_string()).or_default(),
+                Segment::Param(_) => node.param.get_or_insert_with(Default::default),
+                Segment::Constrained(_, source) => {
+                    let index = match node.constrained.iter().position(|(s, _, _)| s == source) {
+                        Some(index) => index,
+                        None => {
+                            let regex = constraint_regex(source).unwrap_or_else(|e| {
+                                panic!("invalid constraint in route {}: {}", path, e)
+                            });
+                            node.constrained.push((
+                                source.to_string(),
+                                regex,
+                                RouteNode::default(),
+                            ));
+                            node.constrained.len() - 1
+                        }
+                    };
+                    &mut node.constrained[index].2
+                }
+            };
+        }
+        leaf.unwrap_or(&mut node.leaf)
+    }
+
+    /// The verb and pattern of every registered route, for all verbs.
+    pub fn keys(&self) -> Vec<&EndpointKey> {
+        let mut keys = Vec::new();
+        self.root.collect_keys(&mut keys);
+        keys
+    }
+
+    /// Finds the route for `verb` and `path` along with its captured
+    /// params. HEAD requests fall back to the GET route, and verbs
+    /// without a route to the route added with `insert_any`.
+    ///
+    /// Fails with the verbs of the routes that do match the path, which
+    /// is empty when no route matches at all.
+    pub(crate) fn find(
+        &self,
+        verb: &HttpVerb,
+        path: &str,
+    ) -> Result<(&Endpoint, HashMap<String, String>), BTreeSet<HttpVerb>> {
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let mut allowed = BTreeSet::new();
+
+        let mut leaf = self.root.find(&segments, &mut |leaf: &RouteLeaf| {
+            allowed.extend(leaf.routes.keys().cloned());
+            leaf.routes.contains_key(verb) || leaf.any.is_some()
+        });
+        let is_head = *verb == HttpVerb::HEAD;
+        if leaf.is_none() && is_head {
+            leaf = self.root.find(&segments, &mut |leaf: &RouteLeaf| {
+                leaf.routes.contains_key(&HttpVerb::GET)
+            });
+        }
+
+        let route = leaf.and_then(|leaf| {
+            leaf.routes
+                .get(verb)
+                .or_else(|| is_head.then(|| leaf.routes.get(&HttpVerb::GET)).flatten())
+                .map(|(key, endpoint)| (&key.path, endpoint))
+                .or_else(|| leaf.any.as_ref().map(|(path, endpoint)| (path, endpoint)))
+        });
+        match route {
+            Some((pattern, endpoint)) => {
+                let params = match_route(pattern, path).unwrap_or_default();
+                Ok((endpoint, params))
+            }
+            None => Err(allowed),
+        }
+    }
+}
+impl fmt::Debug for RouteTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.keys()).finish()
+    }
+}
+
+impl RouteNode {
+    /// Depth first search in precedence order for the first route leaf
+    /// matching `segments` that `accept` agrees to.
+    fn find<'a>(
+        &'a self,
+        segments: &[&str],
+        accept: &mut dyn FnMut(&'a RouteLeaf) -> bool,
+    ) -> Option<&'a RouteLeaf> {
+        if let Some((segment, rest)) = segments.split_first() {
+            if let Some(leaf) = self
+                .statics
+                .get(*segment)
+                .and_then(|child| child.find(rest, accept))
+            {
+                return Some(leaf);
+            }
+            if let Some(leaf) = self
+                .constrained
+                .iter()
+                .filter(|(_, regex, _)| regex.is_match(segment))
+                .find_map(|(_, _, child)| child.find(rest, accept))
+            {
+                return Some(leaf);
+            }
+            if let Some(leaf) = self
+                .param
+                .as_ref()
+                .and_then(|child| child.find(rest, accept))
+            {
+                return Some(leaf);
+            }
+        } else if accept(&self.leaf) {
+            return Some(&self.leaf);
+        }
+
+        if self.wildcards.is_empty() {
+            return None;
+        }
+        let rest = segments.join("/");
+        self.wildcards
+            .iter()
+            .filter(|(prefix, _)| rest.starts_with(prefix.as_str()))
+            .map(|(_, leaf)| leaf)
+            .find(|leaf| accept(leaf))
+    }
+
+    fn collect_keys<'a>(&'a self, keys: &mut Vec<&'a EndpointKey>) {
+        keys.extend(self.leaf.routes.values().map(|(key, _)| key));
+        for (_, leaf) in self.wildcards.iter() {
+            keys.extend(leaf.routes.values().map(|(key, _)| key));
+        }
+        for (_, _, child) in self.constrained.iter() {
+            child.collect_keys(keys);
+        }
+        if let Some(param) = &self.param {
+            param.collect_keys(keys);
+        }
+        for child in self.statics.values() {
+            child.collect_keys(keys);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Response, Server, StatusCode, TestClient};
+
+    /// A server answering every route in `patterns` with the pattern and
+    /// its sorted params, like `users/:name name=ada`.
+    fn server_with(patterns: &[&'static str]) -> TestClient {
+        let mut server = Server::new(0);
+        for &pattern in patterns {
+            server.get(
+                String::from(pattern),
+                move |request: crate::Request| async move {
+                    let mut params: Vec<String> = request
+                        .params
+                        .iter()
+                        .map(|(name, value)| format!("{}={}", name, value))
+                        .collect();
+                    params.sort();
+                    let body = format!("{} {}", pattern, params.join(" "));
+                    Response::new(StatusCode::OK).with_body(body.trim_end().to_string())
+                },
+            );
+        }
+        server.test()
+    }
+
+    async fn route_of(client: &TestClient, path: &str) -> String {
+        let response = client.get(path).send().await;
+        if response.status == StatusCode::OK {
+            response.text()
+        } else {
+            response.status.as_u16().to_string()
+        }
+    }
+
+    #[tokio::test]
+    async fn routes_only_match_whole_segments() {
+        let client = server_with(&["user", "user-agent"]);
+        assert_eq!(route_of(&client, "/user").await, "user");
+        assert_eq!(route_of(&client, "/user-agent").await, "user-agent");
+        assert_eq!(route_of(&client, "/user-").await, "404");
+        assert_eq!(route_of(&client, "/user/agent").await, "404");
+
+        let client = server_with(&["user"]);
+        assert_eq!(route_of(&client, "/user-agent").await, "404");
+    }
+
+    #[tokio::test]
+    async fn static_beats_constrained_beats_param_beats_wildcard() {
+        let client = server_with(&["users/*", "users/:name", "users/{id:[0-9]+}", "users/me"]);
+        assert_eq!(route_of(&client, "/users/me").await, "users/me");
+        assert_eq!(
+            route_of(&client, "/users/42").await,
+            "users/{id:[0-9]+} id=42"
+        );
+        assert_eq!(
+            route_of(&client, "/users/ada").await,
+            "users/:name name=ada"
+        );
+        assert_eq!(
+            route_of(&client, "/users/ada/posts").await,
+            "users/* *=ada/posts"
+        );
+    }
+
+    #[tokio::test]
+    async fn later_segments_can_send_a_match_back_to_a_param() {
+        // the static `me` branch has no `settings` below it
+        let client = server_with(&["users/me/profile", "users/:name/settings"]);
+        assert_eq!(
+            route_of(&client, "/users/me/profile").await,
+            "users/me/profile"
+        );
+        assert_eq!(
+            route_of(&client, "/users/me/settings").await,
+            "users/:name/settings name=me"
+        );
+    }
+
+    #[tokio::test]
+    async fn longer_wildcard_prefixes_win() {
+        let client = server_with(&["files/*", "files/img*"]);
+        assert_eq!(
+            route_of(&client, "/files/img/a.png").await,
+            "files/img* *=/a.png"
+        );
+        assert_eq!(
+            route_of(&client, "/files/doc.txt").await,
+            "files/* *=doc.txt"
+        );
+    }
+
+    #[tokio::test]
+    async fn trailing_and_repeated_slashes_are_ignored() {
+        let client = server_with(&["users/:name", "about"]);
+        assert_eq!(route_of(&client, "/about/").await, "about");
+        assert_eq!(route_of(&client, "//about").await, "about");
+        assert_eq!(
+            route_of(&client, "/users/ada/").await,
+            "users/:name name=ada"
+        );
+        assert_eq!(route_of(&client, "/users/").await, "404");
+    }
+
+    #[tokio::test]
+    async fn other_verbs_get_a_405_listing_the_allowed_ones() {
+        let mut server = Server::new(0);
+        server
+            .route(String::from("items/:id"))
+            .get(|_| async { "item" })
+            .delete(|_| async { StatusCode::NO_CONTENT });
+        let client = server.test();
+
+        let response = client.post("/items/1").send().await;
+        assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.header("allow").unwrap(), "GET, DELETE, HEAD");
+        assert_eq!(
+            client.post("/items").send().await.status,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn allowed_verbs_come_from_every_matching_pattern() {
+        let mut server = Server::new(0);
+        server.get(String::from("items/:id"), |_| async { "item" });
+        server.post(String::from("items/new"), |_| async { "created" });
+        let client = server.test();
+
+        // the static route has no GET, so the param route answers it
+        assert_eq!(client.get("/items/new").send().await.text(), "item");
+        assert_eq!(client.post("/items/new").send().await.text(), "created");
+        let response = client.put("/items/new").send().await;
+        assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.header("allow").unwrap(), "GET, POST, HEAD");
+    }
+
+    #[tokio::test]
+    async fn head_falls_back_to_get_routes() {
+        let mut server = Server::new(0);
+        server.get(String::from("page"), |_| async { "page" });
+        server
+            .route(String::from("custom"))
+            .get(|_| async { "get" })
+            .head(|_| async { Response::new(StatusCode::OK).with_header("X-Route", "head") });
+        let client = server.test();
+
+        let response = client.request(HttpVerb::HEAD, "/page").send().await;
+        assert_eq!(response.status, StatusCode::OK);
+        assert!(response.body.is_empty());
+        let response = client.request(HttpVerb::HEAD, "/custom").send().await;
+        assert_eq!(response.header("x-route").unwrap(), "head");
+    }
+
+    #[tokio::test]
+    async fn any_answers_verbs_without_their_own_route() {
+        let mut server = Server::new(0);
+        server
+            .route(String::from("echo"))
+            .any(|request: crate::Request| async move { format!("any {}", request.verb) })
+            .post(|_| async { "post" });
+        let client = server.test();
+
+        assert_eq!(client.post("/echo").send().await.text(), "post");
+        assert_eq!(client.put("/echo").send().await.text(), "any PUT");
+        assert_eq!(client.get("/echo").send().await.text(), "any GET");
+        assert_eq!(
+            client.get("/echo/more").send().await.status,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn mounted_routers_are_found_under_their_prefix() {
+        let mut api = Router::new();
+        api.get(
+            String::from("users/:id"),
+            |request: crate::Request| async move { format!("user {}", request.params["id"]) },
+        );
+        let mut server = Server::new(0);
+        server.mount(String::from("api"), api);
+        let client = server.test();
+
+        assert_eq!(client.get("/api/users/7").send().await.text(), "user 7");
+        assert_eq!(
+            client.get("/users/7").send().await.status,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn keys_list_every_route() {
+        let mut server = Server::new(0);
+        server.get(String::from("a"), |_| async { "" });
+        server.post(String::from("a"), |_| async { "" });
+        server.get(String::from("b/:id"), |_| async { "" });
+        server.get(String::from("c/*"), |_| async { "" });
+        let mut keys: Vec<String> = server
+            .registry
+            .endpoints
+            .keys()
+            .iter()
+            .map(|key| format!("{} {}", key.verb, key.path))
+            .collect();
+        keys.sort();
+        assert_eq!(keys, ["GET /a", "GET /b/:id", "GET /c/*", "POST /a"]);
+    }
+
+    #[test]
+    fn match_route_captures_params() {
+        let params = match_route("/users/:id/posts/{post:[a-z]+}", "/users/7/posts/intro").unwrap();
+        assert_eq!(params["id"], "7");
+        assert_eq!(params["post"], "intro");
+        assert!(match_route("/users/:id/posts/{post:[a-z]+}", "/users/7/posts/42").is_none());
+        assert!(match_route("/user", "/user-agent").is_none());
+        assert_eq!(
+            match_route("/static/*", "/static/css/a.css").unwrap()["*"],
+            "css/a.css"
+        );
+    }
+}
@@ -0,0 +1,135 @@
+use crate::{HandlerFuture, HttpVerb, Next, Request, Response, Server, ServerRegistry, StatusCode};
+use std::convert::Infallible;
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower_layer::Layer;
+use tower_service::Service;
+use tracing::error;
+
+/// Error type of tower services that can be mounted on a `Server`.
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// The server's routes, middleware and static files as a tower `Service`,
+/// created with `Server::into_service`.
+#[derive(Debug, Clone)]
+pub struct ServerService {
+    registry: Arc<ServerRegistry>,
+}
+impl Service<Request> for ServerService {
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        let registry = self.registry.clone();
+        Box::pin(async move { Ok(registry.serve_request(request).await) })
+    }
+}
+
+/// The rest of the middleware chain as a tower `Service`, which is what
+/// layers added with `Server::layer` wrap.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NextService;
+impl Service<Request> for NextService {
+    type Response = Response;
+    type Error = Infallible;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, mut request: Request) -> Self::Future {
+        // `Server::layer` passes the chain along in the request
+        let response: HandlerFuture = match request.extensions.remove::<Next>() {
+            Some(next) => next.run(request),
+            None => Box::pin(async { Response::new(StatusCode::INTERNAL_SERVER_ERROR) }),
+        };
+        Box::pin(async move { Ok(response.await) })
+    }
+}
+
+impl Server {
+    /// Turns the server into a tower `Service` that runs requests through
+    /// everything a connection would, without any listeners.
+    pub fn into_service(self) -> ServerService {
+        ServerService {
+            registry: Arc::new(self.registry),
+        }
+    }
+
+    /// Wraps every request in a tower `Layer`, like the timeout or
+    /// concurrency limit layers from the `tower` crate.
+    ///
+    /// Layers run as global middleware in the order they were added. The
+    /// layered service is built once and cloned per request, so limits
+    /// kept by the layer are shared. Errors from the layer, like a
+    /// timeout, are answered with a 500.
+    pub fn layer<L>(&mut self, layer: L)
+    where
+        L: Layer<NextService>,
+        L::Service: Service<Request, Response = Response> + Clone + Send + Sync + 'static,
+        <L::Service as Service<Request>>::Error: Into<BoxError>,
+        <L::Service as Service<Request>>::Future: Send,
+    {
+        let service = layer.layer(NextService);
+        self.use_middleware(move |mut request: Request, next: Next| {
+            let mut service = service.clone();
+            async move {
+                request.extensions.insert(next);
+                call_service(&mut service, request).await
+            }
+        });
+    }
+
+    /// Routes every method of `path` to a tower `Service`.
+    /// Errors from the service are answered with a 500.
+    pub fn service<S>(&mut self, path: String, service: S)
+    where
+        S: Service<Request, Response = Response> + Clone + Send + Sync + 'static,
+        S::Error: Into<BoxError>,
+        S::Future: Send,
+    {
+        for verb in [
+            HttpVerb::GET,
+            HttpVerb::HEAD,
+            HttpVerb::POST,
+            HttpVerb::PUT,
+            HttpVerb::PATCH,
+            HttpVerb::DELETE,
+            HttpVerb::OPTIONS,
+        ] {
+            let service = service.clone();
+            self.register_endpoint(verb, path.clone(), move |request| {
+                let mut service = service.clone();
+                async move { call_service(&mut service, request).await }
+            });
+        }
+    }
+}
+
+/// Waits for `service` to be ready and calls it.
+async fn call_service<S>(service: &mut S, request: Request) -> Response
+where
+    S: Service<Request, Response = Response>,
+    S::Error: Into<BoxError>,
+{
+    if let Err(e) = poll_fn(|cx| service.poll_ready(cx)).await {
+        return service_error(e.into());
+    }
+    match service.call(request).await {
+        Ok(response) => response,
+        Err(e) => service_error(e.into()),
+    }
+}
+
+fn service_error(e: BoxError) -> Response {
+    error!(error = %e, "service failed");
+    Response::new(StatusCode::INTERNAL_SERVER_ERROR)
+}
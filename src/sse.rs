@@ -0,0 +1,107 @@
+use crate::{IntoResponse, Response};
+use bytes::Bytes;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+const DEFAULT_SSE_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// A single server-sent event.
+#[derive(Debug, Default, Clone)]
+pub struct Event {
+    pub event: Option<String>,
+    pub id: Option<String>,
+    pub retry: Option<Duration>,
+    pub data: String,
+}
+impl Event {
+    /// Event with only a `data` field.
+    pub fn data(data: impl Into<String>) -> Event {
+        Event {
+            data: data.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Sets the event name clients listen for with `addEventListener`.
+    pub fn with_event(mut self, event: impl Into<String>) -> Event {
+        self.event = Some(event.into());
+        self
+    }
+
+    pub fn with_id(mut self, id: impl Into<String>) -> Event {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Sets how long the client waits before reconnecting.
+    pub fn with_retry(mut self, retry: Duration) -> Event {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Serializes the event in the `text/event-stream` format.
+    pub fn to_bytes(&self) -> Bytes {
+        let mut out = String::new();
+        if let Some(event) = &self.event {
+            out.push_str(&format!("event: {}\n", event));
+        }
+        if let Some(id) = &self.id {
+            out.push_str(&format!("id: {}\n", id));
+        }
+        if let Some(retry) = self.retry {
+            out.push_str(&format!("retry: {}\n", retry.as_millis()));
+        }
+        // multi-line data is sent as one data field per line
+        for line in self.data.split('\n') {
+            out.push_str(&format!("data: {}\n", line));
+        }
+        out.push('\n');
+        Bytes::from(out)
+    }
+}
+
+/// Server-sent events response.
+///
+/// The connection stays open and every `Event` sent on the channel is
+/// written to the client until all senders are dropped. A comment is sent
+/// when the channel is idle so proxies don't close the connection.
+pub struct Sse {
+    receiver: mpsc::Receiver<Event>,
+    keep_alive: Duration,
+}
+impl Sse {
+    pub fn new(receiver: mpsc::Receiver<Event>) -> Sse {
+        Sse {
+            receiver,
+            keep_alive: DEFAULT_SSE_KEEP_ALIVE,
+        }
+    }
+
+    /// Creates a channel and the response that streams it.
+    pub fn channel(buffer: usize) -> (mpsc::Sender<Event>, Sse) {
+        let (sender, receiver) = mpsc::channel(buffer);
+        (sender, Sse::new(receiver))
+    }
+
+    /// Sets how often a keep-alive comment is sent while idle.
+    pub fn keep_alive(mut self, interval: Duration) -> Sse {
+        self.keep_alive = interval;
+        self
+    }
+}
+impl IntoResponse for Sse {
+    fn into_response(self) -> Response {
+        let keep_alive = self.keep_alive;
+        let stream = futures_util::stream::unfold(self.receiver, move |mut receiver| async move {
+            let chunk = tokio::select! {
+                event = receiver.recv() => event?.to_bytes(),
+                _ = tokio::time::sleep(keep_alive) => Bytes::from_static(b": keep-alive\n\n"),
+            };
+            Some((chunk, receiver))
+        });
+
+        Response::stream(stream)
+            .with_header("Content-Type", "text/event-stream")
+            .with_header("Cache-Control", "no-cache")
+    }
+}
@@ -0,0 +1,117 @@
+use crate::{
+    insert_static_directory, Request, Response, Server, StaticDirectoryEntry, VirtualHost,
+};
+use std::fmt;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::pin::Pin;
+use std::sync::Arc;
+
+type AuthorizeFn = dyn Fn(&Request, &str) -> Pin<Box<dyn Future<Output = Result<(), Response>> + Send>>
+    + Send
+    + Sync;
+
+/// Decides who may access the files of a static directory, see
+/// `StaticDirectoryEntry::authorize`.
+///
+/// Two authorizers are only equal when they are the same callback.
+#[derive(Clone)]
+pub struct StaticAuthorizer(Arc<AuthorizeFn>);
+impl StaticAuthorizer {
+    /// Runs the callback for a request of the file at `file_path`.
+    pub(crate) async fn check(&self, request: &Request, file_path: &str) -> Result<(), Response> {
+        (self.0)(request, file_path).await
+    }
+}
+impl fmt::Debug for StaticAuthorizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StaticAuthorizer")
+    }
+}
+impl PartialEq for StaticAuthorizer {
+    fn eq(&self, other: &StaticAuthorizer) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+impl Eq for StaticAuthorizer {}
+impl Hash for StaticAuthorizer {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Arc::as_ptr(&self.0).cast::<()>().hash(state);
+    }
+}
+
+impl StaticDirectoryEntry {
+    /// Checks every request for the directory with `authorize` before it is
+    /// served, uploaded to or deleted. It gets the request and the path of
+    /// the file on disk, or the embedded path, and returns the response to
+    /// deny with, like a 401 or 403.
+    ///
+    /// The returned future can't borrow the request, so copy what it
+    /// needs out of it first.
+    ///
+    /// ```ignore
+    /// server
+    ///     .serve(String::from("downloads"), String::from("/srv/downloads"), false)
+    ///     .unwrap()
+    ///     .authorize(|request, _file_path| {
+    ///         let token = request.headers.get("authorization").cloned();
+    ///         async move {
+    ///             match token {
+    ///                 Some(token) if is_valid(&token).await => Ok(()),
+    ///                 _ => Err(Response::new(StatusCode::UNAUTHORIZED)),
+    ///             }
+    ///         }
+    ///     });
+    /// ```
+    pub fn authorize<F, Fut>(&mut self, authorize: F) -> &mut Self
+    where
+        F: Fn(&Request, &str) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), Response>> + Send + 'static,
+    {
+        self.authorizer = Some(StaticAuthorizer(Arc::new(move |request, file_path| {
+            Box::pin(authorize(request, file_path))
+        })));
+        self
+    }
+}
+
+impl Server {
+    /// Serves a directory of static files like `serve`, only to requests
+    /// `authorize` lets through, see `StaticDirectoryEntry::authorize`.
+    pub fn serve_protected<F, Fut>(
+        &mut self,
+        path: String,
+        directory: String,
+        authorize: F,
+    ) -> Option<&mut StaticDirectoryEntry>
+    where
+        F: Fn(&Request, &str) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), Response>> + Send + 'static,
+    {
+        let entry = insert_static_directory(
+            &mut self.registry.static_directories,
+            path,
+            directory,
+            false,
+        )?;
+        Some(entry.authorize(authorize))
+    }
+}
+
+impl VirtualHost {
+    /// Serves a protected directory for this host, see
+    /// `Server::serve_protected`.
+    pub fn serve_protected<F, Fut>(
+        &mut self,
+        path: String,
+        directory: String,
+        authorize: F,
+    ) -> Option<&mut StaticDirectoryEntry>
+    where
+        F: Fn(&Request, &str) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), Response>> + Send + 'static,
+    {
+        let entry = insert_static_directory(&mut self.static_directories, path, directory, false)?;
+        Some(entry.authorize(authorize))
+    }
+}
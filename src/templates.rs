@@ -0,0 +1,71 @@
+use crate::{Response, Server, StatusCode};
+use serde::Serialize;
+use std::sync::{Arc, RwLock};
+use tracing::warn;
+
+/// Templates `Response::render` renders with, shared by every server in
+/// the process since rendering has no server at hand.
+static TEMPLATES: RwLock<Option<Arc<tera::Tera>>> = RwLock::new(None);
+
+impl Server {
+    /// Compiles every template in `directory` and its subdirectories for
+    /// `Response::render`, named by their path relative to it like
+    /// `pages/about.html`. Templates use the Tera syntax, which is close to
+    /// Jinja2, and `.html` ones escape the values they insert.
+    ///
+    /// Templates are compiled once here, not on every render. They are
+    /// shared by every server in the process, so loading them again
+    /// replaces them for all of them.
+    pub fn templates(&mut self, directory: &str) -> Result<(), tera::Error> {
+        let pattern = format!("{}/**/*", directory.trim_end_matches('/'));
+        self.set_templates(tera::Tera::new(&pattern)?);
+        Ok(())
+    }
+
+    /// Renders with an already set up `Tera`, like one with custom filters
+    /// registered, see `templates`.
+    pub fn set_templates(&mut self, templates: tera::Tera) {
+        *TEMPLATES.write().unwrap() = Some(Arc::new(templates));
+    }
+}
+
+impl Response {
+    /// 200 html response with the template `name` rendered using the
+    /// fields of `context`, see `Server::templates`.
+    /// Responds with a 500 when the template is missing or fails to render.
+    ///
+    /// ```ignore
+    /// server.get(String::from("users/:id"), |request| async move {
+    ///     let user = load_user(&request.params["id"]).await;
+    ///     Response::render("user.html", &serde_json::json!({ "user": user }))
+    /// });
+    /// ```
+    pub fn render<T: Serialize + ?Sized>(name: &str, context: &T) -> Response {
+        let Some(templates) = TEMPLATES.read().unwrap().clone() else {
+            warn!(template = name, "no templates loaded");
+            return Response::new(StatusCode::INTERNAL_SERVER_ERROR);
+        };
+        let rendered = tera::Context::from_serialize(context)
+            .and_then(|context| templates.render(name, &context));
+        match rendered {
+            Ok(html) => Response::new(StatusCode::OK)
+                .with_header("Content-Type", "text/html; charset=utf-8")
+                .with_body(html),
+            Err(e) => {
+                // the error chain says which template line went wrong
+                let mut message = e.to_string();
+                let mut source = std::error::Error::source(&e);
+                while let Some(cause) = source {
+                    message = format!("{}: {}", message, cause);
+                    source = cause.source();
+                }
+                warn!(
+                    template = name,
+                    error = message,
+                    "failed to render template"
+                );
+                Response::new(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use crate::{HeaderMap, HttpVerb, Server, ServerRegistry, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// size of the in-memory pipe between a `TestClient` and the server
+const PIPE_SIZE: usize = 64 * 1024;
+
+impl ServerRegistry {
+    /// Serves raw HTTP/1.1 request bytes over an in-memory connection and
+    /// returns everything the server wrote back.
+    ///
+    /// The connection is closed once `request` is sent, so several
+    /// pipelined requests get their responses one after another.
+    pub async fn handle_raw(self: &Arc<Self>, request: &[u8]) -> Vec<u8> {
+        let (mut client, server) = tokio::io::duplex(PIPE_SIZE);
+        let mut response = Vec::new();
+        let client = async {
+            // the server may stop reading early, like after a 400
+            if client.write_all(request).await.is_ok() {
+                let _ = client.shutdown().await;
+            }
+            let _ = client.read_to_end(&mut response).await;
+        };
+        tokio::join!(self.clone().handle_socket(server), client);
+        response
+    }
+}
+
+impl Server {
+    /// Turns the server into a client that sends requests straight to its
+    /// routes and middleware, without binding any ports.
+    pub fn test(self) -> TestClient {
+        TestClient {
+            registry: Arc::new(self.registry),
+        }
+    }
+}
+
+/// Sends requests to a server in the same process, created with
+/// `Server::test`.
+///
+/// Requests go through the same parsing and response writing as a real
+/// connection.
+#[derive(Debug, Clone)]
+pub struct TestClient {
+    registry: Arc<ServerRegistry>,
+}
+impl TestClient {
+    pub fn request(&self, verb: HttpVerb, path: &str) -> TestRequest {
+        TestRequest {
+            registry: self.registry.clone(),
+            verb,
+            path: path.to_string(),
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, path: &str) -> TestRequest {
+        self.request(HttpVerb::GET, path)
+    }
+
+    pub fn head(&self, path: &str) -> TestRequest {
+        self.request(HttpVerb::HEAD, path)
+    }
+
+    pub fn post(&self, path: &str) -> TestRequest {
+        self.request(HttpVerb::POST, path)
+    }
+
+    pub fn put(&self, path: &str) -> TestRequest {
+        self.request(HttpVerb::PUT, path)
+    }
+
+    pub fn patch(&self, path: &str) -> TestRequest {
+        self.request(HttpVerb::PATCH, path)
+    }
+
+    pub fn delete(&self, path: &str) -> TestRequest {
+        self.request(HttpVerb::DELETE, path)
+    }
+}
+
+/// A request being built by a `TestClient`, sent with `send`.
+pub struct TestRequest {
+    registry: Arc<ServerRegistry>,
+    verb: HttpVerb,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+impl TestRequest {
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> TestRequest {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> TestRequest {
+        self.body = body.into();
+        self
+    }
+
+    /// Sends `value` serialized as a JSON body.
+    /// Panics if it can't be serialized.
+    pub fn json<T: Serialize + ?Sized>(self, value: &T) -> TestRequest {
+        let body = serde_json::to_vec(value).expect("failed to serialize json body");
+        self.header("Content-Type", "application/json").body(body)
+    }
+
+    /// Sends the request and reads back the response.
+    ///
+    /// Panics if the server's response can't be parsed, since that is a
+    /// bug in the server rather than in the test.
+    pub async fn send(self) -> TestResponse {
+        let mut request = format!("{} {} HTTP/1.1\r\n", self.verb, self.path);
+        let has_header = |name: &str| {
+            self.headers
+                .iter()
+                .any(|(header, _)| header.eq_ignore_ascii_case(name))
+        };
+        if !has_header("host") {
+            request.push_str("Host: localhost\r\n");
+        }
+        if !self.body.is_empty() && !has_header("content-length") {
+            request.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        for (name, value) in self.headers.iter() {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str("Connection: close\r\n\r\n");
+        let mut request = request.into_bytes();
+        request.extend_from_slice(&self.body);
+
+        let response = self.registry.handle_raw(&request).await;
+        parse_response(&response, self.verb == HttpVerb::HEAD)
+            .expect("server sent an invalid response")
+    }
+}
+
+/// A response read back by a `TestClient`.
+#[derive(Debug, Clone)]
+pub struct TestResponse {
+    pub status: StatusCode,
+    /// names are lowercase, repeated headers like `Set-Cookie` keep every
+    /// value
+    pub headers: HeaderMap,
+    /// body with any chunked framing removed
+    pub body: Vec<u8>,
+}
+impl TestResponse {
+    pub fn header(&self, name: &str) -> Option<&String> {
+        self.headers.get(name)
+    }
+
+    /// The body as text, replacing invalid utf-8.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).to_string()
+    }
+
+    /// Deserializes the body as JSON.
+    pub fn json<T: DeserializeOwned>(&self) -> serde_json::Result<T> {
+        serde_json::from_slice(&self.body)
+    }
+}
+
+/// Parses a raw HTTP/1.1 response as written by `Connection`.
+fn parse_response(raw: &[u8], is_head: bool) -> Option<TestResponse> {
+    let head_length = raw.windows(4).position(|window| window == b"\r\n\r\n")? + 4;
+    let head = std::str::from_utf8(&raw[..head_length]).ok()?;
+    let mut lines = head.split("\r\n");
+    let status = lines.next()?.split(' ').nth(1)?.parse().ok()?;
+    let headers: HeaderMap = lines
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            Some((name.trim().to_lowercase(), value.trim().to_string()))
+        })
+        .collect();
+
+    let rest = &raw[head_length..];
+    let body = if is_head {
+        Vec::new()
+    } else if headers.contains_key("transfer-encoding") {
+        decode_chunked(rest)?
+    } else {
+        match headers.get("content-length") {
+            Some(length) => rest.get(..length.parse().ok()?)?.to_vec(),
+            None => rest.to_vec(),
+        }
+    };
+    Some(TestResponse {
+        status: StatusCode::from_u16(status),
+        headers,
+        body,
+    })
+}
+
+fn decode_chunked(mut raw: &[u8]) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = raw.windows(2).position(|window| window == b"\r\n")?;
+        let size = std::str::from_utf8(&raw[..line_end]).ok()?;
+        let size = usize::from_str_radix(size.split(';').next()?.trim(), 16).ok()?;
+        if size == 0 {
+            return Some(body);
+        }
+        let chunk_start = line_end + 2;
+        body.extend_from_slice(raw.get(chunk_start..chunk_start + size)?);
+        raw = raw.get(chunk_start + size + 2..)?;
+    }
+}
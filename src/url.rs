@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+
+/// Decodes `%XX` escapes in a string.
+/// Invalid escapes are kept as-is and invalid utf-8 is replaced.
+pub fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && bytes[i + 1].is_ascii_hexdigit()
+            && bytes[i + 2].is_ascii_hexdigit()
+        {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap();
+            decoded.push(u8::from_str_radix(hex, 16).unwrap());
+            i += 3;
+            continue;
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Decodes a request path, failing when it contains an encoded NUL or line
+/// break that could cut off file names or forge log lines.
+pub fn decode_path(raw: &str) -> Option<String> {
+    let decoded = percent_decode(raw);
+    if decoded.contains(['\0', '\r', '\n']) {
+        return None;
+    }
+    Some(decoded)
+}
+
+/// Parses a query string like `a=1&b=hello+world` into a map.
+/// Keys without a value map to an empty string and later keys win.
+pub fn parse_query(query: &str) -> HashMap<String, String> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (
+                percent_decode(&key.replace('+', " ")),
+                percent_decode(&value.replace('+', " ")),
+            )
+        })
+        .collect()
+}
+
+/// Encodes everything but unreserved characters as `%XX` escapes, for
+/// putting arbitrary text like file names into a path segment.
+pub fn percent_encode(input: &str) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
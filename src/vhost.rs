@@ -0,0 +1,223 @@
+use crate::{
+    insert_static_directory, into_handler, normalize_path, Endpoint, EndpointKey, Handler,
+    HttpVerb, IntoResponse, Request, Response, Route, RouteTree, Router, Server, ServerRegistry,
+    StaticDirectoryEntry,
+};
+use std::collections::HashMap;
+use std::future::Future;
+
+/// Routes and static directories served for one hostname, created with
+/// `Server::vhost`.
+///
+/// Requests whose `Host` matches no virtual host are served by the routes
+/// registered on the `Server` itself. Global middleware runs for every
+/// host.
+#[derive(Default, Clone)]
+pub struct VirtualHost {
+    pub endpoints: RouteTree,
+    pub static_directories: HashMap<String, StaticDirectoryEntry>,
+    /// handles requests for this host no route or static directory matched
+    pub fallback: Option<Handler>,
+}
+impl std::fmt::Debug for VirtualHost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VirtualHost")
+            .field("endpoints", &self.endpoints)
+            .field("static_directories", &self.static_directories)
+            .field("fallback", &self.fallback.is_some())
+            .finish()
+    }
+}
+impl VirtualHost {
+    /// Registers a route for this host, see `Server::register_endpoint`.
+    pub fn register_endpoint<F, Fut>(
+        &mut self,
+        verb: HttpVerb,
+        path: String,
+        handler: F,
+    ) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        let endpoint_key = EndpointKey {
+            verb,
+            path: normalize_path(path),
+        };
+        self.endpoints
+            .insert(endpoint_key, Endpoint::new(into_handler(handler)))
+    }
+
+    pub fn get<F, Fut>(&mut self, path: String, handler: F) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.register_endpoint(HttpVerb::GET, path, handler)
+    }
+
+    pub fn post<F, Fut>(&mut self, path: String, handler: F) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.register_endpoint(HttpVerb::POST, path, handler)
+    }
+
+    pub fn put<F, Fut>(&mut self, path: String, handler: F) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.register_endpoint(HttpVerb::PUT, path, handler)
+    }
+
+    pub fn patch<F, Fut>(&mut self, path: String, handler: F) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.register_endpoint(HttpVerb::PATCH, path, handler)
+    }
+
+    pub fn delete<F, Fut>(&mut self, path: String, handler: F) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.register_endpoint(HttpVerb::DELETE, path, handler)
+    }
+
+    /// Routes HEAD requests separately, without one they are answered
+    /// by the GET route with the body left out.
+    pub fn head<F, Fut>(&mut self, path: String, handler: F) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.register_endpoint(HttpVerb::HEAD, path, handler)
+    }
+
+    pub fn options<F, Fut>(&mut self, path: String, handler: F) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.register_endpoint(HttpVerb::OPTIONS, path, handler)
+    }
+
+    /// Routes every verb of `path` that has no route of its own to
+    /// `handler`, no matter if the other routes are added before or after.
+    pub fn any<F, Fut>(&mut self, path: String, handler: F) -> &mut Endpoint
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.endpoints
+            .insert_any(normalize_path(path), Endpoint::new(into_handler(handler)))
+    }
+
+    /// Registers handlers for several verbs of the same path, see `Route`.
+    pub fn route(&mut self, path: String) -> Route<'_> {
+        Route::new(&mut self.endpoints, path)
+    }
+
+    /// Registers every route of a `Router` under the given path prefix.
+    pub fn mount(&mut self, prefix: String, router: Router) {
+        for (key, endpoint) in router.into_endpoints(&prefix) {
+            self.endpoints.insert(key, endpoint);
+        }
+    }
+
+    /// Serves a single file for this host, see `Server::serve_file`.
+    pub fn serve_file(&mut self, path: String, file_path: String) -> &mut Endpoint {
+        self.get(path, move |request| {
+            let file_path = file_path.clone();
+            async move { Response::file(&request, &file_path).await }
+        })
+    }
+
+    /// Serves a directory of static files for this host, see
+    /// `Server::serve`.
+    pub fn serve(
+        &mut self,
+        path: String,
+        directory: String,
+        allow_upload: bool,
+    ) -> Option<&mut StaticDirectoryEntry> {
+        insert_static_directory(&mut self.static_directories, path, directory, allow_upload)
+    }
+
+    /// Handles requests for this host that no route or static directory
+    /// matched. Without a fallback they get an empty 404.
+    pub fn fallback<F, Fut>(&mut self, handler: F)
+    where
+        F: Fn(Request) -> Fut + Send + Sync + 'static,
+        Fut: Future + Send + 'static,
+        Fut::Output: IntoResponse,
+    {
+        self.fallback = Some(into_handler(handler));
+    }
+}
+
+impl Server {
+    /// The virtual host for `host`, created on first use, to register
+    /// routes and static directories only served for that `Host` header.
+    ///
+    /// `host` is matched ignoring case and the port. A leading `*.`
+    /// matches any subdomain, like `*.example.com` for `a.example.com`,
+    /// when no exact host matches.
+    ///
+    /// ```ignore
+    /// server.vhost("api.example.com").get(String::from("users"), list_users);
+    /// server.vhost("www.example.com").serve(String::new(), String::from("site"), false);
+    /// ```
+    pub fn vhost(&mut self, host: &str) -> &mut VirtualHost {
+        self.registry
+            .vhosts
+            .entry(host.trim_end_matches('.').to_lowercase())
+            .or_default()
+    }
+}
+
+impl ServerRegistry {
+    /// The virtual host serving `request`, `None` for the default host.
+    pub(crate) fn vhost_for(&self, request: &Request) -> Option<&VirtualHost> {
+        if self.vhosts.is_empty() {
+            return None;
+        }
+        let host = request_host(request)?;
+        if let Some(vhost) = self.vhosts.get(&host) {
+            return Some(vhost);
+        }
+        // the closest wildcard wins, *.a.example.com before *.example.com
+        let mut parent = host.as_str();
+        while let Some((_, rest)) = parent.split_once('.') {
+            if let Some(vhost) = self.vhosts.get(&format!("*.{}", rest)) {
+                return Some(vhost);
+            }
+            parent = rest;
+        }
+        None
+    }
+}
+
+/// The lowercased hostname of the `Host` header without the port.
+fn request_host(request: &Request) -> Option<String> {
+    let host = request.headers.get("host")?.trim();
+    let name = match host.strip_prefix('[') {
+        // an IPv6 address like [::1]:8080
+        Some(rest) => &host[..rest.find(']')? + 2],
+        None => host.split(':').next()?,
+    };
+    Some(name.trim_end_matches('.').to_lowercase())
+}